@@ -8,8 +8,15 @@ use syn::{
     Meta, Path, Token, Type, Visibility,
 };
 
+#[derive(Default)]
 pub struct ViewModelAttr {
     default: bool,
+    persist: bool,
+    lifecycle: bool,
+    retain: bool,
+    debug: bool,
+    record: bool,
+    max_fps: Option<Expr>,
 }
 
 pub fn is_viewmodel_attr(attr: &Attribute) -> Option<ViewModelAttr> {
@@ -20,18 +27,41 @@ pub fn is_viewmodel_meta(meta: &Meta) -> Option<ViewModelAttr> {
     let is_viewmodel_path = |path: &Path| path.get_ident().is_some_and(|i| i == "viewmodel");
 
     match meta {
-        Meta::Path(p) if is_viewmodel_path(p) => Some(ViewModelAttr { default: false }),
+        Meta::Path(p) if is_viewmodel_path(p) => Some(ViewModelAttr::default()),
         Meta::List(l) if is_viewmodel_path(&l.path) => {
-            if l.parse_args::<syn::Ident>().unwrap() == "default" {
-                Some(ViewModelAttr { default: true })
-            } else {
-                panic!("unexpected values for #[viewmodel], only support #[viewmodel(default)] for now");
+            let mut attr = ViewModelAttr::default();
+            let args = l
+                .parse_args_with(Punctuated::<Meta, Comma>::parse_terminated)
+                .unwrap();
+
+            for arg in args {
+                match &arg {
+                    Meta::NameValue(nv) if nv.path.is_ident("max_fps") => {
+                        attr.max_fps = Some(nv.value.clone());
+                    }
+                    Meta::Path(p) if p.is_ident("default") => attr.default = true,
+                    Meta::Path(p) if p.is_ident("persist") => attr.persist = true,
+                    Meta::Path(p) if p.is_ident("lifecycle") => attr.lifecycle = true,
+                    Meta::Path(p) if p.is_ident("retain") => attr.retain = true,
+                    Meta::Path(p) if p.is_ident("debug") => attr.debug = true,
+                    Meta::Path(p) if p.is_ident("record") => attr.record = true,
+                    _ => panic!(
+                        "unexpected value `{}` for #[viewmodel], expected `default`, `persist`, `lifecycle`, `retain`, `debug`, `record` or `max_fps = <fps>`",
+                        arg.to_token_stream()
+                    ),
+                }
             }
+
+            Some(attr)
         }
         _ => None,
     }
 }
 
+fn is_persist_field_attr(attr: &Attribute) -> bool {
+    attr.path().get_ident().is_some_and(|i| i == "persist")
+}
+
 #[derive(Clone)]
 pub struct ViewModelMacroInput {
     attrs: Vec<Attribute>,
@@ -139,10 +169,18 @@ impl ToTokens for ViewModelMacroInput {
         let change = format_ident!("{}ChangeDetector", self.ident);
         let model = format_ident!("{}Model", self.ident);
         let vis = &self.vis;
-        let default = self
+        let ViewModelAttr {
+            default,
+            persist,
+            lifecycle,
+            retain,
+            debug,
+            record,
+            max_fps,
+        } = self
             .attrs
             .iter()
-            .find_map(|attr| Some(is_viewmodel_attr(attr)?.default))
+            .find_map(is_viewmodel_attr)
             .unwrap_or_default();
 
         match &mut item.fields {
@@ -178,7 +216,7 @@ impl ToTokens for ViewModelMacroInput {
             let mut fields = vec![];
             for field in self.fields.named.iter() {
                 let ident = &field.ident;
-                fields.push(quote! { #ident: self.#ident.change_detector() })
+                fields.push(quote! { #ident: egui_mvvm::Stateful::change_detector(&self.#ident) })
             }
 
             quote! { #change { #(#fields),* } }
@@ -188,26 +226,21 @@ impl ToTokens for ViewModelMacroInput {
             let mut fields = vec![];
             for field in self.fields.named.iter() {
                 let ident = &field.ident;
-                fields.push(quote! { #ident: self.#ident.handle() })
+                fields.push(quote! { #ident: egui_mvvm::Stateful::handle(&self.#ident) })
             }
 
             quote! { #model { #(#fields),* } }
         };
 
         let change_detector_impl = {
-            let mut select_arms = vec![];
+            let mut changes = vec![];
             for field in self.fields.named.iter() {
                 let ident = &field.ident;
-                select_arms.push(quote! { res = this.#ident.wait_for_change() => res })
+                changes.push(quote! { egui_mvvm::ChangeDetector::wait_for_change(&self.#ident) })
             }
 
             quote! {
-                let this = self.clone();
-                Box::pin(async move {
-                    tokio::select! {
-                        #(#select_arms),*
-                    }
-                })
+                egui_mvvm::wait_for_any(vec![#(#changes),*])
             }
         };
 
@@ -215,7 +248,7 @@ impl ToTokens for ViewModelMacroInput {
             let mut fields = vec![];
             for field in self.fields.named.iter() {
                 let ident = &field.ident;
-                fields.push(quote! { self.#ident.latch_value(); })
+                fields.push(quote! { egui_mvvm::Stateful::latch(&mut self.#ident); })
             }
 
             quote! {
@@ -223,6 +256,197 @@ impl ToTokens for ViewModelMacroInput {
             }
         };
 
+        let persist_impl = {
+            let persisted = self
+                .fields
+                .named
+                .iter()
+                .filter(|field| persist || field.attrs.iter().any(is_persist_field_attr))
+                .map(|field| &field.ident)
+                .collect::<Vec<_>>();
+
+            if persisted.is_empty() {
+                quote! {}
+            } else {
+                let names = persisted.iter().map(|ident| ident.to_string());
+                let names_2 = names.clone();
+                quote! {
+                    fn persist(&self) -> Option<String> {
+                        let mut state = egui_mvvm::persistence::PersistedState::default();
+                        #(state.save(#names, &self.#persisted);)*
+                        Some(state.to_string())
+                    }
+
+                    fn restore(&mut self, state: &str) {
+                        if let Some(state) = egui_mvvm::persistence::PersistedState::parse(state) {
+                            #(state.restore(#names_2, &mut self.#persisted);)*
+                        }
+                    }
+                }
+            }
+        };
+
+        let lifecycle_impl = if lifecycle {
+            quote! {
+                fn lifecycle(&mut self) -> Option<&mut dyn egui_mvvm::view_model::ViewModelLifecycle> {
+                    Some(self)
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        let retain_impl = if retain {
+            quote! {
+                fn retain(&self) -> bool {
+                    true
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        let max_fps_impl = max_fps.map(|max_fps| {
+            quote! {
+                fn max_fps(&self) -> Option<f32> {
+                    Some((#max_fps) as f32)
+                }
+            }
+        });
+
+        let inspect_impl = if debug {
+            let names = self
+                .fields
+                .named
+                .iter()
+                .map(|field| field.ident.to_string());
+            let idents = self.fields.named.iter().map(|field| &field.ident);
+            quote! {
+                fn inspect(&self) -> Option<Vec<egui_mvvm::devtools::FieldInspection>> {
+                    Some(vec![
+                        #(egui_mvvm::devtools::FieldInspection::new(#names, &self.#idents)),*
+                    ])
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        let record_impl = if record {
+            let names = self
+                .fields
+                .named
+                .iter()
+                .map(|field| field.ident.to_string())
+                .collect::<Vec<_>>();
+            let idents = self
+                .fields
+                .named
+                .iter()
+                .map(|field| &field.ident)
+                .collect::<Vec<_>>();
+            quote! {
+                fn record(&self) -> Option<Vec<(&'static str, egui_mvvm::recorder::Value)>> {
+                    let mut fields = Vec::new();
+                    #(
+                        if let Some(value) = egui_mvvm::persistence::Persistable::save(&self.#idents) {
+                            fields.push((#names, value));
+                        }
+                    )*
+                    Some(fields)
+                }
+
+                fn replay(
+                    &mut self,
+                    values: &std::collections::HashMap<&str, &egui_mvvm::recorder::Value>,
+                ) {
+                    #(
+                        egui_mvvm::persistence::Persistable::replay(
+                            &mut self.#idents,
+                            values.get(#names).map(|value| (*value).clone()),
+                        );
+                    )*
+                }
+
+                fn connect_recorder(&self, recorder: &egui_mvvm::recorder::ViewModelRecorder) {
+                    #(
+                        egui_mvvm::persistence::Persistable::connect_recorder(
+                            &self.#idents,
+                            recorder.field(#names),
+                        );
+                    )*
+                }
+            }
+        } else {
+            quote! {}
+        };
+
+        let set_names_impl = {
+            let type_name = ident.to_string();
+            let names = self
+                .fields
+                .named
+                .iter()
+                .map(|field| field.ident.to_string());
+            let idents = self.fields.named.iter().map(|field| &field.ident);
+            quote! {
+                fn set_names(&self) {
+                    self.task_pool.set_name(#type_name);
+                    egui_mvvm::view_model::ViewModelLike::set_field_names(self, #type_name);
+                }
+
+                fn set_field_names(&self, prefix: &'static str) {
+                    #(
+                        egui_mvvm::Stateful::set_name(
+                            &self.#idents,
+                            egui_mvvm::view_model::field_name(prefix, #names),
+                        );
+                    )*
+                }
+            }
+        };
+
+        let changed_fields_impl = {
+            let names = self
+                .fields
+                .named
+                .iter()
+                .map(|field| field.ident.to_string());
+            let idents = self.fields.named.iter().map(|field| &field.ident);
+            quote! {
+                fn changed_fields(&self) -> Vec<&'static str> {
+                    let mut fields = Vec::new();
+                    #(
+                        if egui_mvvm::Stateful::changed_this_frame(&self.#idents) {
+                            fields.push(#names);
+                        }
+                    )*
+                    fields
+                }
+            }
+        };
+
+        let run_lifecycle_impl = {
+            let idents = self.fields.named.iter().map(|field| &field.ident);
+            quote! {
+                fn run_lifecycle(&mut self, event: egui_mvvm::view_model::LifecycleEvent) {
+                    if let Some(lifecycle) = egui_mvvm::view_model::ViewModelLike::lifecycle(self) {
+                        event.run(lifecycle);
+                    }
+                    #(egui_mvvm::Stateful::run_lifecycle(&mut self.#idents, event);)*
+                }
+            }
+        };
+
+        let connect_impl = {
+            let idents = self.fields.named.iter().map(|field| &field.ident);
+            quote! {
+                fn connect(&self, notifier: &egui_mvvm::notify::ChangeNotifier) {
+                    #(egui_mvvm::Stateful::connect(&self.#idents, notifier);)*
+                }
+            }
+        };
+
         let default_impl = {
             if !default {
                 quote! {}
@@ -231,9 +455,7 @@ impl ToTokens for ViewModelMacroInput {
                 quote! {
                     impl Default for #ident {
                         fn default() -> #ident {
-                            #ident {
-                                #defaults
-                            }
+                            #defaults
                         }
                     }
                 }
@@ -258,15 +480,44 @@ impl ToTokens for ViewModelMacroInput {
                }
 
                fn change_detector_boxed(&self) -> Box<dyn egui_mvvm::ChangeDetector> {
-                   Box::new(self.change_detector())
+                   Box::new(egui_mvvm::view_model::ViewModel::change_detector(self))
+               }
+
+               #persist_impl
+
+               #lifecycle_impl
+
+               #run_lifecycle_impl
+
+               #retain_impl
+
+               #max_fps_impl
+
+               #inspect_impl
+
+               #record_impl
+
+               fn running_tasks(&self) -> usize {
+                   self.task_pool.running_tasks()
                }
+
+               #set_names_impl
+
+               #connect_impl
+
+               #changed_fields_impl
            }
 
 
+
            impl egui_mvvm::view_model::ViewModelTaskPool for #ident {
                 fn task_pool(&self) -> egui_mvvm::task_pool::TaskPool {
                     self.task_pool.clone()
                 }
+
+                fn set_task_pool(&mut self, task_pool: egui_mvvm::task_pool::TaskPool) {
+                    self.task_pool = task_pool;
+                }
             }
 
            #[derive(Clone)]
@@ -288,7 +539,35 @@ impl ToTokens for ViewModelMacroInput {
 
            impl egui_mvvm::Stateful for #ident {
                type ChangeDetector = #change;
-               type Handle = egui_mvvm::view_model::ViewModelHandle<#ident>;
+               type Handle = #model;
+
+               fn latch(&mut self) {
+                   egui_mvvm::view_model::ViewModelLike::latch_state(self);
+               }
+
+               fn handle(&self) -> #model {
+                   egui_mvvm::view_model::ViewModel::make_model(self)
+               }
+
+               fn change_detector(&self) -> #change {
+                   egui_mvvm::view_model::ViewModel::change_detector(self)
+               }
+
+               fn set_name(&self, name: &'static str) {
+                   egui_mvvm::view_model::ViewModelLike::set_field_names(self, name);
+               }
+
+               fn connect(&self, notifier: &egui_mvvm::notify::ChangeNotifier) {
+                   egui_mvvm::view_model::ViewModelLike::connect(self, notifier);
+               }
+
+               fn changed_this_frame(&self) -> bool {
+                   !egui_mvvm::view_model::ViewModelLike::changed_fields(self).is_empty()
+               }
+
+               fn run_lifecycle(&mut self, event: egui_mvvm::view_model::LifecycleEvent) {
+                   egui_mvvm::view_model::ViewModelLike::run_lifecycle(self, event);
+               }
            }
 
            impl egui_mvvm::view_model::ViewModel for #ident {
@@ -322,20 +601,26 @@ impl ViewModelFields {
         })
     }
 
+    /// Binds every default in declaration order, so a default can refer to the fields above it, and to
+    /// the `task_pool` of the ViewModel to share it with a nested one.
     pub fn as_default_fields(&self) -> TokenStream {
-        let fields = self
-            .named
-            .iter()
-            .map(|f| {
-                let ident = &f.ident;
-                let expr = &f.default_value.as_ref().unwrap().expr;
-                quote! { #ident: #expr.into() }
-            })
-            .chain(vec![
-                quote! { task_pool: egui_mvvm::task_pool::TaskPool::new() },
-            ]);
-
-        quote! { #(#fields),* }
+        let bindings = self.named.iter().map(|f| {
+            let ident = &f.ident;
+            let ty = &f.ty;
+            let expr = &f.default_value.as_ref().unwrap().expr;
+            quote! { let #ident: #ty = (#expr).into(); }
+        });
+        let fields = self.named.iter().map(|f| &f.ident);
+
+        quote! {
+            let task_pool = egui_mvvm::task_pool::TaskPool::new();
+            #(#bindings)*
+
+            Self {
+                #(#fields,)*
+                task_pool,
+            }
+        }
     }
 }
 
@@ -351,7 +636,10 @@ impl ViewModelField {
             default_value: _,
         } = self;
         Field {
-            attrs,
+            attrs: attrs
+                .into_iter()
+                .filter(|attr| !is_persist_field_attr(attr))
+                .collect(),
             vis,
             mutability,
             ident: Some(ident),
@@ -0,0 +1,82 @@
+//! Compares waiting for a change of one of `n` ViewModels through the `ViewModels` notification hub
+//! against `select_all` over the change detector of every ViewModel.
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use egui_mvvm::val_state::{ValState, ValStateHandle};
+use egui_mvvm::view_model;
+use egui_mvvm::view_model::{
+    EguiViewModelExt, EguiViewModelsExt, ViewModelHandle, ViewModelLike, ViewModels,
+};
+use egui_mvvm::{ChangeDetector, wait_for_any};
+use futures::executor::block_on;
+
+view_model! {
+    #[viewmodel(default)]
+    pub struct ItemViewModel {
+        pub title: ValState<String> = String::new(),
+        pub count: ValState<u64> = 0,
+        pub selected: ValState<bool> = false,
+    }
+}
+
+/// Registers `n` ViewModels, as a list of items would.
+fn fetch_items(
+    n: usize,
+) -> (
+    egui::Context,
+    ViewModels,
+    Vec<ViewModelHandle<ItemViewModel>>,
+) {
+    let ctx = egui::Context::default();
+    let mut items = Vec::new();
+    let _ = ctx.run(Default::default(), |ctx| {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            for i in 0..n {
+                ui.push_id(i, |ui| items.push(ui.fetch_model::<ItemViewModel>()));
+            }
+        });
+    });
+    let view_models = ctx.memory_mut(|mem| mem.view_models());
+
+    (ctx, view_models, items)
+}
+
+fn change_detection(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wait_for_change");
+
+    for n in [10, 100, 1000] {
+        let (_ctx, view_models, items) = fetch_items(n);
+        let count: ValStateHandle<u64> = items[n - 1].get().count.handle();
+
+        group.bench_with_input(BenchmarkId::new("select_all", n), &n, |b, _| {
+            let mut value = 0;
+            b.iter(|| {
+                let change = wait_for_any(
+                    items
+                        .iter()
+                        .map(|item| item.get().change_detector_boxed().wait_for_change())
+                        .collect(),
+                );
+                value += 1;
+                count.send_value(value);
+                block_on(change)
+            })
+        });
+
+        let detector = view_models.change_detector();
+        group.bench_with_input(BenchmarkId::new("hub", n), &n, |b, _| {
+            let mut value = 0;
+            b.iter(|| {
+                let change = detector.wait_for_change();
+                value += 1;
+                count.send_value(value);
+                block_on(change)
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, change_detection);
+criterion_main!(benches);
@@ -1,11 +1,11 @@
 use eframe::{CreationContext, Frame, NativeOptions};
-use egui::{Context, Response, Slider};
+use egui::{Button, Context, Response, Slider};
+use egui_mvvm::derived_state::DerivedState;
 use egui_mvvm::ref_state::RefState;
+use egui_mvvm::task_pool::SpawnPolicy;
 use egui_mvvm::val_state::ValState;
 use egui_mvvm::view_model;
-use egui_mvvm::view_model::{
-    request_repaint_on_change, EguiViewModelExt, EguiViewModelsExt, ViewModel,
-};
+use egui_mvvm::view_model::{EguiViewModelExt, ViewModel};
 use std::time::{Duration, Instant};
 
 #[tokio::main]
@@ -22,7 +22,7 @@ struct EguiApp {}
 
 impl EguiApp {
     pub fn new(ctx: &Context) -> Box<Self> {
-        tokio::spawn(request_repaint_on_change(ctx.clone()));
+        egui_mvvm::app::install(ctx);
 
         Box::new(Self {})
     }
@@ -30,8 +30,6 @@ impl EguiApp {
 
 impl eframe::App for EguiApp {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
-        ctx.memory_mut(|mem| mem.view_models().latch_values());
-
         egui::CentralPanel::default().show(ctx, |ui| {
             //ctx.memory_ui(ui);
 
@@ -56,6 +54,9 @@ view_model! {
     #[viewmodel(default)]
     pub struct DownloadViewModel {
         pub status: ValState<Option<Status>> = None,
+        pub is_simulating: DerivedState<bool> = DerivedState::new(&status, |status| {
+            matches!(status, Some(Status::Preparing | Status::Uploading(..)))
+        }),
         pub error: ValState<Option<Error>> = None,
         pub text: RefState<String> = "".to_string(),
         pub jitter: ValState<f32> = 0.0,
@@ -85,14 +86,14 @@ impl DownloadView<'_> {
             }
 
             ui.horizontal(|ui| {
-                if ui
-                    .text_edit_singleline(&mut *self.view_model.text.value_mut_untracked())
-                    .changed()
-                {
-                    self.view_model.text.mark_changed();
-                };
+                ui.text_edit_singleline(&mut self.view_model.text.value_mut());
 
-                if ui.button("Submit").clicked()
+                if ui
+                    .add_enabled(
+                        !*self.view_model.is_simulating.value(),
+                        Button::new("Submit"),
+                    )
+                    .clicked()
                     || ui.input(|input| input.key_pressed(egui::Key::Enter))
                 {
                     self.view_model.simulate_upload();
@@ -123,7 +124,7 @@ impl DownloadView<'_> {
                     ))
                     .changed()
                 {
-                    self.view_model.jitter.mark_changed()
+                    self.view_model.duration.mark_changed()
                 }
             });
 
@@ -149,28 +150,18 @@ impl DownloadView<'_> {
 }
 
 impl DownloadViewModel {
-    pub fn is_simulating(&self) -> bool {
-        matches!(
-            self.status.value(),
-            Some(Status::Preparing | Status::Uploading(..))
-        )
-    }
-
     pub fn simulate_upload(&self) {
         dbg!(self.duration.value(), self.jitter.value());
 
-        if self.is_simulating() {
-            return;
-        }
-
         if *self.duration.value() == 0.0 {
             self.error.send_value(Some(Error::MissingDuration));
             return;
         }
 
-        self.status.send_value(Some(Status::Preparing));
+        // A submit while uploading is dropped instead of starting a second upload.
+        self.spawn_keyed("upload", SpawnPolicy::IgnoreIfRunning, |this| async move {
+            this.status.send_value(Some(Status::Preparing));
 
-        self.spawn(|this| async move {
             let duration = *this.duration.value();
             let timestep = 1.0 / 90.0;
             let mut progress = 0.0;
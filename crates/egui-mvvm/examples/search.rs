@@ -4,9 +4,7 @@ use egui_mvvm::hooks::debounce::use_debounce;
 use egui_mvvm::ref_state::RefState;
 use egui_mvvm::val_state::ValState;
 use egui_mvvm::view_model;
-use egui_mvvm::view_model::{
-    EguiViewModelExt, EguiViewModelsExt, ViewModel, request_repaint_on_change,
-};
+use egui_mvvm::view_model::EguiViewModelExt;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -24,7 +22,7 @@ struct EguiApp {}
 
 impl EguiApp {
     pub fn new(ctx: &Context) -> Box<Self> {
-        tokio::spawn(request_repaint_on_change(ctx.clone()));
+        egui_mvvm::app::install(ctx);
 
         Box::new(Self {})
     }
@@ -32,8 +30,6 @@ impl EguiApp {
 
 impl eframe::App for EguiApp {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
-        ctx.memory_mut(|mem| mem.view_models().latch_values());
-
         egui::CentralPanel::default().show(ctx, |ui| {
             //ctx.memory_ui(ui);
 
@@ -66,12 +62,7 @@ impl SearchView<'_> {
     pub fn show(&mut self, ui: &mut egui::Ui) -> Response {
         ui.vertical(|ui| {
             ui.horizontal(|ui| {
-                if ui
-                    .text_edit_singleline(&mut *self.view_model.search.value_mut_untracked())
-                    .changed()
-                {
-                    self.view_model.search.mark_changed();
-                }
+                ui.text_edit_singleline(&mut self.view_model.search.value_mut());
 
                 ui.vertical(|ui| {
                     ui.label("Debounce in ms");
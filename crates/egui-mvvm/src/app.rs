@@ -0,0 +1,189 @@
+use crate::view_model::{EguiViewModelsExt, install_repaint_on_change};
+use std::sync::Arc;
+
+/// Latches the ViewModels at the start of every frame and repaints when they change.
+///
+/// Replaces spawning [`request_repaint_on_change`](crate::view_model::request_repaint_on_change) and
+/// calling [`latch_values`](crate::view_model::ViewModels::latch_values) in `update`. Installing twice on
+/// the same context does nothing.
+///
+/// ```ignore
+/// // In the app creator given to `eframe::run_native`.
+/// egui_mvvm::app::install(&creation.egui_ctx);
+/// ```
+pub fn install(ctx: &egui::Context) {
+    let view_models = ctx.memory_mut(|mem| mem.view_models());
+    if std::mem::replace(&mut view_models.0.lock().unwrap().latch_installed, true) {
+        return;
+    }
+
+    ctx.on_begin_pass(
+        "egui_mvvm",
+        Arc::new(|ctx| {
+            ctx.memory_mut(|mem| mem.view_models()).latch_values();
+        }),
+    );
+    install_repaint_on_change(ctx);
+}
+
+/// Wraps an [`eframe::App`], installing egui-mvvm on its context and shutting it down on exit.
+///
+/// ```ignore
+/// eframe::run_native(
+///     "app",
+///     NativeOptions::default(),
+///     Box::new(|creation| Ok(Box::new(MvvmApp::new(&creation.egui_ctx, MyApp::default())))),
+/// )
+/// ```
+#[cfg(feature = "eframe")]
+pub struct MvvmApp<A> {
+    ctx: egui::Context,
+    app: A,
+}
+
+#[cfg(feature = "eframe")]
+impl<A: eframe::App> MvvmApp<A> {
+    pub fn new(ctx: &egui::Context, app: A) -> Self {
+        install(ctx);
+
+        Self {
+            ctx: ctx.clone(),
+            app,
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.app
+    }
+
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.app
+    }
+}
+
+#[cfg(feature = "eframe")]
+impl<A: eframe::App> eframe::App for MvvmApp<A> {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.app.update(ctx, frame);
+    }
+
+    /// Saves the wrapped app, then the persisted ViewModels. A failed save is logged with the `tracing`
+    /// feature, call [`ViewModels::save`](crate::view_model::ViewModels::save) to handle it instead.
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.app.save(storage);
+
+        #[cfg(feature = "persistence")]
+        if let Err(error) = self.ctx.memory_mut(|mem| mem.view_models()).save() {
+            #[cfg(feature = "tracing")]
+            tracing::error!(target: "egui_mvvm::persistence", %error, "saving ViewModels failed");
+            #[cfg(not(feature = "tracing"))]
+            let _ = error;
+        }
+    }
+
+    /// Drops every ViewModel and the repaint task after the wrapped app exits.
+    fn on_exit(&mut self, gl: Option<&eframe::glow::Context>) {
+        self.app.on_exit(gl);
+        self.ctx.memory_mut(|mem| mem.view_models()).shutdown();
+    }
+
+    fn auto_save_interval(&self) -> std::time::Duration {
+        self.app.auto_save_interval()
+    }
+
+    fn clear_color(&self, visuals: &egui::Visuals) -> [f32; 4] {
+        self.app.clear_color(visuals)
+    }
+
+    fn persist_egui_memory(&self) -> bool {
+        self.app.persist_egui_memory()
+    }
+
+    fn raw_input_hook(&mut self, ctx: &egui::Context, raw_input: &mut egui::RawInput) {
+        self.app.raw_input_hook(ctx, raw_input);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate as egui_mvvm;
+    use crate::testing::TestHarness;
+    use crate::val_state::ValState;
+    use crate::view_model;
+    use crate::view_model::{EguiViewModelExt, ViewModelHandle};
+    use egui::{CentralPanel, RawInput};
+
+    view_model! {
+        #[viewmodel(default)]
+        struct CounterViewModel {
+            count: ValState<u32> = 0,
+        }
+    }
+
+    /// Runs a pass of `ctx` without latching it first, returning the ViewModel and the count it showed.
+    fn pass(ctx: &egui::Context) -> (ViewModelHandle<CounterViewModel>, u32) {
+        let mut shown = None;
+        let _ = ctx.run(RawInput::default(), |ctx| {
+            CentralPanel::default().show(ctx, |ui| {
+                let vm = ui.fetch_model::<CounterViewModel>();
+                let count = *vm.get().count.value();
+                shown = Some((vm, count));
+            });
+        });
+        shown.unwrap()
+    }
+
+    #[test]
+    fn install_latches_before_each_pass() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        install(harness.ctx());
+        install(harness.ctx());
+
+        let (vm, count) = pass(harness.ctx());
+        assert_eq!(count, 0);
+        vm.get().count.send_value(1);
+        assert_eq!(*vm.get().count.value(), 0);
+
+        let (_, count) = pass(harness.ctx());
+        assert_eq!(count, 1);
+    }
+
+    #[cfg(feature = "eframe")]
+    #[test]
+    fn mvvm_app_repaints_on_change() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct App;
+
+        impl eframe::App for App {
+            fn update(&mut self, _ctx: &egui::Context, _frame: &mut eframe::Frame) {}
+        }
+
+        let harness = TestHarness::new();
+        let repaints = Arc::new(AtomicUsize::new(0));
+        harness.ctx().set_request_repaint_callback({
+            let repaints = repaints.clone();
+            move |_| {
+                repaints.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let vm = {
+            let _runtime = harness.enter();
+            let _app = MvvmApp::new(harness.ctx(), App);
+            // egui repaints the first frames on its own.
+            pass(harness.ctx());
+            pass(harness.ctx()).0
+        };
+        harness.yield_to_tasks();
+        repaints.store(0, Ordering::Relaxed);
+
+        harness.yield_to_tasks();
+        assert_eq!(repaints.load(Ordering::Relaxed), 0);
+
+        vm.get().count.send_value(1);
+        harness.yield_to_tasks();
+        assert!(repaints.load(Ordering::Relaxed) > 0);
+    }
+}
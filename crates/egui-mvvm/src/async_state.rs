@@ -0,0 +1,611 @@
+use crate::notify::ChangeNotifier;
+use crate::query::QuerySubscription;
+#[cfg(feature = "recorder")]
+use crate::recorder::{FieldRecorder, Value};
+use crate::task_pool::{SpawnPolicy, TaskHandle};
+use crate::val_state::{ValState, ValStateChangeDetector, ValStateHandle};
+use crate::view_model::{ViewModel, ViewModelLike, ViewModelTaskPool};
+use crate::{ChangeDetector, Stateful};
+use futures::future::BoxFuture;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The state of an [`AsyncState`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsyncValue<T, E> {
+    /// Nothing was loaded yet.
+    Idle,
+    /// A load is in flight, with its progress between 0 and 1 if it reports one.
+    Loading {
+        progress: Option<f32>,
+    },
+    Ready(T),
+    Failed(E),
+}
+
+#[derive(Clone)]
+pub(crate) struct Resource<T, E> {
+    pub(crate) value: AsyncValue<T, E>,
+    /// The last loaded value, kept while reloading or after a failed reload.
+    pub(crate) stale: Option<T>,
+}
+
+impl<T, E> Resource<T, E> {
+    pub(crate) fn start(&mut self) {
+        let previous = std::mem::replace(&mut self.value, AsyncValue::Loading { progress: None });
+        if let AsyncValue::Ready(value) = previous {
+            self.stale = Some(value);
+        }
+    }
+
+    pub(crate) fn finish(&mut self, result: Result<T, E>) {
+        match result {
+            Ok(value) => {
+                self.value = AsyncValue::Ready(value);
+                self.stale = None;
+            }
+            Err(error) => self.value = AsyncValue::Failed(error),
+        }
+    }
+
+    fn cancel(&mut self) -> bool {
+        if !matches!(self.value, AsyncValue::Loading { .. }) {
+            return false;
+        }
+
+        self.value = match self.stale.take() {
+            Some(value) => AsyncValue::Ready(value),
+            None => AsyncValue::Idle,
+        };
+        true
+    }
+
+    pub(crate) fn data(&self) -> Option<&T> {
+        match &self.value {
+            AsyncValue::Ready(value) => Some(value),
+            _ => self.stale.as_ref(),
+        }
+    }
+}
+
+type Loader<T, E> =
+    Arc<dyn Fn(LoadProgress<T, E>) -> BoxFuture<'static, Result<T, E>> + Send + Sync>;
+
+static NEXT_KEY: AtomicU64 = AtomicU64::new(0);
+
+struct AsyncShared<T, E> {
+    /// The slot of the loads in the ViewModel's pool.
+    key: u64,
+    /// Incremented by every load and cancel, so an outdated load never publishes its result.
+    generation: AtomicU64,
+    loader: Mutex<Option<Loader<T, E>>>,
+    task: Mutex<Option<TaskHandle>>,
+}
+
+impl<T, E> AsyncShared<T, E> {
+    fn abort(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+/// A value loaded by a future, with its loading and error states.
+///
+/// Loads run on the pool of the ViewModel passed to [`AsyncState::load`], so they are aborted with it.
+/// Starting a load aborts the one in flight. While reloading, or after a failed reload, the previous
+/// value stays available from [`AsyncState::data`].
+///
+/// ```ignore
+/// pub fn open(&self, id: UserId) {
+///     let api = self.api.clone();
+///     self.user.load(self, move |_| api.clone().fetch_user(id));
+/// }
+/// ```
+pub struct AsyncState<T, E> {
+    state: ValState<Resource<T, E>>,
+    shared: Arc<AsyncShared<T, E>>,
+}
+
+impl<T: Clone, E: Clone> Clone for AsyncState<T, E> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T, E> Default for AsyncState<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, E> AsyncState<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self::with_value(AsyncValue::Idle)
+    }
+
+    /// Creates a state that starts with `value` loaded.
+    pub fn ready(value: T) -> Self {
+        Self::with_value(AsyncValue::Ready(value))
+    }
+
+    fn with_value(value: AsyncValue<T, E>) -> Self {
+        Self {
+            state: ValState::new(Resource { value, stale: None }),
+            shared: Arc::new(AsyncShared {
+                key: NEXT_KEY.fetch_add(1, Ordering::Relaxed),
+                generation: AtomicU64::new(0),
+                loader: Mutex::new(None),
+                task: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub fn latch_value(&mut self) {
+        self.state.latch_value()
+    }
+
+    /// Latches `data` as ready without publishing it, or the latest published value if `None`, for time travel.
+    #[cfg(feature = "recorder")]
+    pub(crate) fn latch_replayed(&mut self, data: Option<T>) {
+        self.state.latch_replayed(data.map(|data| Resource {
+            value: AsyncValue::Ready(data),
+            stale: None,
+        }));
+    }
+
+    /// Returns true if the latest latch picked up a new value.
+    pub fn changed_this_frame(&self) -> bool {
+        self.state.changed_this_frame()
+    }
+
+    pub fn value(&self) -> &AsyncValue<T, E> {
+        &self.state.value().value
+    }
+
+    /// The loaded value, or the previous one while reloading or after a failed reload.
+    pub fn data(&self) -> Option<&T> {
+        self.state.value().data()
+    }
+
+    pub fn error(&self) -> Option<&E> {
+        match self.value() {
+            AsyncValue::Failed(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        matches!(self.value(), AsyncValue::Loading { .. })
+    }
+
+    pub fn progress(&self) -> Option<f32> {
+        match self.value() {
+            AsyncValue::Loading { progress } => *progress,
+            _ => None,
+        }
+    }
+
+    pub fn latest_value(&self) -> AsyncValue<T, E> {
+        self.state.latest_value().value
+    }
+
+    /// Loads the output of the future returned by `f` on the pool of `vm`, aborting the load in flight.
+    ///
+    /// `f` is kept to be called again by [`AsyncState::reload`], and is given a [`LoadProgress`] to report
+    /// the progress of the load.
+    #[track_caller]
+    pub fn load<F>(
+        &self,
+        vm: &impl ViewModelTaskPool,
+        f: impl Fn(LoadProgress<T, E>) -> F + Send + Sync + 'static,
+    ) where
+        F: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        let loader: Loader<T, E> = Arc::new(move |progress| Box::pin(f(progress)));
+        *self.shared.loader.lock().unwrap() = Some(loader.clone());
+        self.spawn(vm, loader);
+    }
+
+    /// Runs the last load again, keeping its value available from [`AsyncState::data`] meanwhile.
+    ///
+    /// Returns false if nothing was loaded yet.
+    #[track_caller]
+    pub fn reload(&self, vm: &impl ViewModelTaskPool) -> bool {
+        let Some(loader) = self.shared.loader.lock().unwrap().clone() else {
+            return false;
+        };
+
+        self.spawn(vm, loader);
+        true
+    }
+
+    /// Aborts the load in flight, going back to the previous value.
+    pub fn cancel(&self) {
+        cancel(&self.state.handle(), &self.shared);
+    }
+
+    /// Shows the value of `query` until another load or subscription replaces it.
+    ///
+    /// The subscription is kept by a task on the pool of `vm`, so the query stays cached while the
+    /// ViewModel lives. [`AsyncState::reload`] does nothing meanwhile, queries are fetched again with
+    /// [`QueryClient::invalidate`](crate::query::QueryClient::invalidate).
+    #[track_caller]
+    pub fn subscribe(&self, vm: &impl ViewModelTaskPool, query: QuerySubscription<T, E>) {
+        self.shared.loader.lock().unwrap().take();
+        self.shared.generation.fetch_add(1, Ordering::SeqCst);
+
+        let state = self.state.handle();
+        let key = ("egui_mvvm::AsyncState", self.shared.key);
+        let task =
+            vm.task_pool()
+                .spawn_keyed(key, SpawnPolicy::CancelPrevious, move || async move {
+                    let mut rx = query.receiver();
+                    loop {
+                        let resource = rx.borrow_and_update().clone();
+                        state.send_value(resource);
+
+                        if rx.changed().await.is_err() {
+                            break;
+                        }
+                    }
+                });
+        *self.shared.task.lock().unwrap() = Some(task);
+    }
+
+    /// Publishes `value` as loaded, aborting the load in flight.
+    pub fn send_ready(&self, value: T) {
+        self.shared.abort();
+        self.state
+            .send_modify(|resource| resource.finish(Ok(value)));
+    }
+
+    /// Publishes `error` as the result of the load, aborting the load in flight.
+    pub fn send_failed(&self, error: E) {
+        self.shared.abort();
+        self.state
+            .send_modify(|resource| resource.finish(Err(error)));
+    }
+
+    #[track_caller]
+    fn spawn(&self, vm: &impl ViewModelTaskPool, loader: Loader<T, E>) {
+        let generation = self.shared.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.state.send_modify(Resource::start);
+
+        let state = self.state.handle();
+        let shared = self.shared.clone();
+        let progress = LoadProgress {
+            state: state.clone(),
+            shared: shared.clone(),
+            generation,
+        };
+
+        let key = ("egui_mvvm::AsyncState", self.shared.key);
+        let task =
+            vm.task_pool()
+                .spawn_keyed(key, SpawnPolicy::CancelPrevious, move || async move {
+                    let result = loader(progress).await;
+                    state.maybe_send_update(|resource| {
+                        let current = shared.generation.load(Ordering::SeqCst) == generation;
+                        if current {
+                            resource.finish(result);
+                        }
+                        current
+                    });
+                });
+        *self.shared.task.lock().unwrap() = Some(task);
+    }
+
+    /// Records every loaded value published to the state, see
+    /// [`Persistable::connect_recorder`](crate::persistence::Persistable::connect_recorder).
+    #[cfg(feature = "recorder")]
+    pub(crate) fn record_with(
+        &self,
+        recorder: FieldRecorder,
+        serialize: impl Fn(&T) -> Option<Value> + Send + Sync + 'static,
+    ) {
+        self.state
+            .record_with(recorder, move |resource| serialize(resource.data()?));
+    }
+
+    pub fn change_detector(&self) -> AsyncStateChangeDetector<T, E> {
+        AsyncStateChangeDetector(self.state.change_detector())
+    }
+
+    pub fn handle(&self) -> AsyncStateHandle<T, E> {
+        AsyncStateHandle {
+            state: self.state.handle(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+fn cancel<T: Clone + Send + Sync + 'static, E: Clone + Send + Sync + 'static>(
+    state: &ValStateHandle<Resource<T, E>>,
+    shared: &AsyncShared<T, E>,
+) {
+    shared.abort();
+    state.maybe_send_update(Resource::cancel);
+}
+
+/// Reports the progress of a load started by [`AsyncState::load`].
+pub struct LoadProgress<T, E> {
+    state: ValStateHandle<Resource<T, E>>,
+    shared: Arc<AsyncShared<T, E>>,
+    generation: u64,
+}
+
+impl<T: Clone + Send + Sync + 'static, E: Clone + Send + Sync + 'static> LoadProgress<T, E> {
+    /// Sets the progress of the load, between 0 and 1.
+    pub fn set(&self, progress: f32) {
+        let generation = self.shared.generation.load(Ordering::SeqCst);
+        self.state
+            .maybe_send_update(|resource| match &mut resource.value {
+                AsyncValue::Loading { progress: current } if generation == self.generation => {
+                    *current = Some(progress.clamp(0.0, 1.0));
+                    true
+                }
+                _ => false,
+            });
+    }
+}
+
+pub struct AsyncStateChangeDetector<T, E>(ValStateChangeDetector<Resource<T, E>>);
+
+impl<T, E> Clone for AsyncStateChangeDetector<T, E> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: Send + Sync + 'static, E: Send + Sync + 'static> ChangeDetector
+    for AsyncStateChangeDetector<T, E>
+{
+    fn wait_for_change(&self) -> Pin<Box<dyn Future<Output = Option<()>> + Send + 'static>> {
+        self.0.wait_for_change()
+    }
+}
+
+/// Publishes the state of an [`AsyncState`] from tasks.
+pub struct AsyncStateHandle<T, E> {
+    state: ValStateHandle<Resource<T, E>>,
+    shared: Arc<AsyncShared<T, E>>,
+}
+
+impl<T: Clone, E: Clone> Clone for AsyncStateHandle<T, E> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static, E: Clone + Send + Sync + 'static> AsyncStateHandle<T, E> {
+    pub fn value(&self) -> &AsyncValue<T, E> {
+        &self.state.value().value
+    }
+
+    pub fn data(&self) -> Option<&T> {
+        self.state.value().data()
+    }
+
+    pub fn latest_value(&self) -> AsyncValue<T, E> {
+        self.state.latest_value().value
+    }
+
+    /// Publishes `value` as loaded, aborting the load in flight.
+    pub fn send_ready(&self, value: T) {
+        self.shared.abort();
+        self.state
+            .send_update(|resource| resource.finish(Ok(value)));
+    }
+
+    /// Publishes `error` as the result of the load, aborting the load in flight.
+    pub fn send_failed(&self, error: E) {
+        self.shared.abort();
+        self.state
+            .send_update(|resource| resource.finish(Err(error)));
+    }
+
+    pub fn cancel(&self) {
+        cancel(&self.state, &self.shared);
+    }
+}
+
+impl<T, E> Stateful for AsyncState<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    type ChangeDetector = AsyncStateChangeDetector<T, E>;
+    type Handle = AsyncStateHandle<T, E>;
+
+    fn latch(&mut self) {
+        self.latch_value()
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+
+    fn set_name(&self, name: &'static str) {
+        self.state.set_name(name);
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(&self.state, notifier);
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        self.state.changed_this_frame()
+    }
+}
+
+impl<T, E> ViewModelLike for AsyncState<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    fn latch_state(&mut self) {
+        self.latch_value()
+    }
+
+    fn change_detector_boxed(&self) -> Box<dyn ChangeDetector> {
+        Box::new(self.change_detector())
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(self, notifier);
+    }
+}
+
+impl<T, E> ViewModel for AsyncState<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    type Model = AsyncStateHandle<T, E>;
+    type ChangeDetector = AsyncStateChangeDetector<T, E>;
+
+    fn make_model(&self) -> Self::Model {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate as egui_mvvm;
+    use crate::testing::TestHarness;
+    use crate::view_model;
+    use std::time::Duration;
+
+    view_model! {
+        #[viewmodel(default)]
+        struct UserViewModel {
+            user: AsyncState<String, String> = AsyncState::new(),
+        }
+    }
+
+    /// Loads `name` after `delay`.
+    async fn fetch(name: &str, delay: u64) -> Result<String, String> {
+        tokio::time::sleep(Duration::from_secs(delay)).await;
+        Ok(name.to_string())
+    }
+
+    #[test]
+    fn cancel_goes_back_to_the_previous_value() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        let vm = UserViewModel::default();
+
+        vm.user.load(&vm, |_| fetch("ada", 1));
+        harness.advance(Duration::from_secs(1));
+        assert_eq!(vm.user.latest_value(), AsyncValue::Ready("ada".to_string()));
+
+        vm.user.load(&vm, |progress| async move {
+            progress.set(0.5);
+            fetch("grace", 1).await
+        });
+        harness.yield_to_tasks();
+        assert_eq!(
+            vm.user.latest_value(),
+            AsyncValue::Loading {
+                progress: Some(0.5)
+            }
+        );
+
+        vm.user.cancel();
+        assert_eq!(vm.user.latest_value(), AsyncValue::Ready("ada".to_string()));
+        harness.advance(Duration::from_secs(1));
+        assert_eq!(vm.user.latest_value(), AsyncValue::Ready("ada".to_string()));
+        assert_eq!(vm.running_tasks(), 0);
+    }
+
+    #[test]
+    fn cancel_before_anything_loaded_goes_back_to_idle() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        let vm = UserViewModel::default();
+
+        vm.user.load(&vm, |_| fetch("ada", 1));
+        vm.user.cancel();
+        harness.advance(Duration::from_secs(1));
+
+        assert_eq!(vm.user.latest_value(), AsyncValue::Idle);
+    }
+
+    #[test]
+    fn new_load_aborts_the_one_in_flight() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        let vm = UserViewModel::default();
+
+        vm.user.load(&vm, |_| fetch("slow", 2));
+        harness.yield_to_tasks();
+        vm.user.load(&vm, |_| fetch("fast", 1));
+        harness.advance(Duration::from_secs(2));
+
+        assert_eq!(
+            vm.user.latest_value(),
+            AsyncValue::Ready("fast".to_string())
+        );
+    }
+
+    #[test]
+    fn reload_keeps_the_previous_value_available() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        let mut vm = UserViewModel::default();
+        assert!(!vm.user.reload(&vm));
+
+        let attempts = Arc::new(AtomicU64::new(0));
+        vm.user.load(&vm, {
+            let attempts = attempts.clone();
+            move |_| {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    match attempt {
+                        0 => Ok("ada".to_string()),
+                        _ => Err("offline".to_string()),
+                    }
+                }
+            }
+        });
+        harness.advance(Duration::from_secs(1));
+
+        assert!(vm.user.reload(&vm));
+        vm.user.latch_value();
+        assert!(vm.user.is_loading());
+        assert_eq!(vm.user.data().map(String::as_str), Some("ada"));
+
+        harness.advance(Duration::from_secs(1));
+        vm.user.latch_value();
+        assert_eq!(vm.user.error().map(String::as_str), Some("offline"));
+        assert_eq!(vm.user.data().map(String::as_str), Some("ada"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,627 @@
+use crate::notify::ChangeNotifier;
+use crate::view_model::{ViewModel, ViewModelLike};
+use crate::{ChangeDetector, Stateful};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// Tracks whether a source has published a value since it was last seen.
+pub trait ChangeTracker: Send + Sync + 'static {
+    fn has_changed(&self) -> bool;
+    fn mark_seen(&mut self);
+    fn change_detectors(&self, detectors: &mut Vec<Box<dyn ChangeDetector>>);
+    fn boxed_clone(&self) -> Box<dyn ChangeTracker>;
+
+    /// Connects the source to `notifier`, unless it already belongs to a ViewModel.
+    fn connect(&self, _notifier: &ChangeNotifier) {}
+}
+
+/// A [`ChangeTracker`] that can also read the latest and the latched value of its source.
+pub trait SourceTracker: ChangeTracker + Clone {
+    type Value;
+
+    /// Reads the latest value and marks it as seen.
+    fn read(&mut self) -> Self::Value;
+
+    /// Returns true if the source latched a new value since [`read_latched`](Self::read_latched).
+    fn latched_changed(&self) -> bool;
+
+    /// Reads the value the source latched last and marks it as seen, or the latest value if the source
+    /// was never latched.
+    fn read_latched(&mut self) -> Self::Value;
+}
+
+/// The value a state latched last, shared with the trackers of the [`DerivedState`]s computed from it.
+pub(crate) struct LatchedValue<T> {
+    tx: watch::Sender<Option<T>>,
+}
+
+impl<T> Clone for LatchedValue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<T> Default for LatchedValue<T> {
+    fn default() -> Self {
+        Self {
+            tx: watch::Sender::new(None),
+        }
+    }
+}
+
+impl<T: Clone> LatchedValue<T> {
+    /// Records `value` if the latch changed it, or if nothing was recorded yet.
+    pub(crate) fn update(&self, changed: bool, value: &T) {
+        if changed || self.tx.borrow().is_none() {
+            self.tx.send_replace(Some(value.clone()));
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> LatchedReceiver<T> {
+        LatchedReceiver {
+            rx: self.tx.subscribe(),
+        }
+    }
+}
+
+pub(crate) struct LatchedReceiver<T> {
+    rx: watch::Receiver<Option<T>>,
+}
+
+impl<T> Clone for LatchedReceiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            rx: self.rx.clone(),
+        }
+    }
+}
+
+impl<T: Clone> LatchedReceiver<T> {
+    /// Returns `None` if the state was never latched.
+    pub(crate) fn has_changed(&self) -> Option<bool> {
+        self.rx
+            .borrow()
+            .is_some()
+            .then(|| self.rx.has_changed().unwrap_or(false))
+    }
+
+    /// Returns `None` if the state was never latched.
+    pub(crate) fn read(&mut self) -> Option<T> {
+        self.rx.borrow_and_update().clone()
+    }
+}
+
+/// A state a [`DerivedState`] can be computed from.
+///
+/// Implemented for [`ValState`](crate::val_state::ValState), [`RefState`](crate::ref_state::RefState),
+/// [`DerivedState`] and tuples of them.
+pub trait DerivedSource {
+    type Tracker: SourceTracker;
+
+    fn tracker(&self) -> Self::Tracker;
+}
+
+impl<T: DerivedSource + ?Sized> DerivedSource for &T {
+    type Tracker = T::Tracker;
+
+    fn tracker(&self) -> Self::Tracker {
+        (**self).tracker()
+    }
+}
+
+macro_rules! impl_derived_source_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: DerivedSource),+> DerivedSource for ($($name,)+) {
+            type Tracker = ($($name::Tracker,)+);
+
+            fn tracker(&self) -> Self::Tracker {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                ($($name.tracker(),)+)
+            }
+        }
+
+        impl<$($name: SourceTracker),+> ChangeTracker for ($($name,)+) {
+            fn has_changed(&self) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                false $(|| $name.has_changed())+
+            }
+
+            fn mark_seen(&mut self) {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                $($name.mark_seen();)+
+            }
+
+            fn change_detectors(&self, detectors: &mut Vec<Box<dyn ChangeDetector>>) {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                $($name.change_detectors(detectors);)+
+            }
+
+            fn boxed_clone(&self) -> Box<dyn ChangeTracker> {
+                Box::new(self.clone())
+            }
+
+            fn connect(&self, notifier: &ChangeNotifier) {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                $($name.connect(notifier);)+
+            }
+        }
+
+        impl<$($name: SourceTracker),+> SourceTracker for ($($name,)+) {
+            type Value = ($($name::Value,)+);
+
+            fn read(&mut self) -> Self::Value {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                ($($name.read(),)+)
+            }
+
+            fn latched_changed(&self) -> bool {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                false $(|| $name.latched_changed())+
+            }
+
+            fn read_latched(&mut self) -> Self::Value {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                ($($name.read_latched(),)+)
+            }
+        }
+    };
+}
+
+impl_derived_source_tuple!(A);
+impl_derived_source_tuple!(A, B);
+impl_derived_source_tuple!(A, B, C);
+impl_derived_source_tuple!(A, B, C, D);
+impl_derived_source_tuple!(A, B, C, D, E);
+impl_derived_source_tuple!(A, B, C, D, E, F);
+
+trait Derive<T>: Send {
+    /// Computes the value from the latest values of the sources.
+    fn get(&mut self) -> T;
+    /// Computes the value from the latched values of the sources.
+    fn get_latched(&mut self) -> T;
+    /// Recomputes the value from the latched values of the sources, if one of them latched a change.
+    fn latch(&mut self) -> Option<T>;
+    fn tracker(&self) -> Box<dyn ChangeTracker>;
+    /// A derivation with the same compute function, which tracks the sources on its own from now on.
+    fn fork(&self) -> Arc<Mutex<dyn Derive<T>>>;
+}
+
+struct Derivation<Tr: SourceTracker, F, T> {
+    sources: Tr,
+    /// Tracks the latched values of the sources, separately from the latest ones.
+    latched_sources: Tr,
+    compute: Arc<Mutex<F>>,
+    memo: Option<T>,
+}
+
+impl<Tr, F, T> Derive<T> for Derivation<Tr, F, T>
+where
+    Tr: SourceTracker,
+    F: FnMut(&Tr::Value) -> T + Send + 'static,
+    T: Clone + Send + 'static,
+{
+    fn get(&mut self) -> T {
+        if self.memo.is_none() || self.sources.has_changed() {
+            let values = self.sources.read();
+            self.memo = Some((self.compute.lock().unwrap())(&values));
+        }
+
+        self.memo.clone().unwrap()
+    }
+
+    fn get_latched(&mut self) -> T {
+        let values = self.latched_sources.read_latched();
+        (self.compute.lock().unwrap())(&values)
+    }
+
+    fn latch(&mut self) -> Option<T> {
+        self.latched_sources
+            .latched_changed()
+            .then(|| self.get_latched())
+    }
+
+    fn tracker(&self) -> Box<dyn ChangeTracker> {
+        let mut tracker = self.sources.boxed_clone();
+        tracker.mark_seen();
+        tracker
+    }
+
+    fn fork(&self) -> Arc<Mutex<dyn Derive<T>>> {
+        Arc::new(Mutex::new(Derivation {
+            sources: self.sources.clone(),
+            latched_sources: self.latched_sources.clone(),
+            compute: self.compute.clone(),
+            memo: self.memo.clone(),
+        }))
+    }
+}
+
+/// State computed from other states.
+///
+/// The value is only recomputed when one of its sources latches a change, from the values the sources
+/// latched, so a frame never shows a derived value out of step with its sources. Sources must be latched
+/// first, which `view_model!` does by latching fields in declaration order. A source that belongs to no
+/// ViewModel is never latched, its latest value is used instead.
+///
+/// A clone shares the compute function, but tracks the changes of the sources on its own, so each clone
+/// picks up a change when it is latched.
+///
+/// ```ignore
+/// view_model! {
+///     #[viewmodel(default)]
+///     pub struct DownloadViewModel {
+///         pub status: ValState<Option<Status>> = None,
+///         pub is_simulating: DerivedState<bool> = DerivedState::new(&status, |status| {
+///             matches!(status, Some(Status::Preparing | Status::Uploading(..)))
+///         }),
+///     }
+/// }
+/// ```
+pub struct DerivedState<T> {
+    latched: T,
+    /// Set by the latest latch if the recomputed value differs.
+    changed: bool,
+    derivation: Arc<Mutex<dyn Derive<T>>>,
+    tracker: Box<dyn ChangeTracker>,
+    /// The latched value, as seen by the states derived from this one.
+    mirror: LatchedValue<T>,
+}
+
+impl<T: Clone> Clone for DerivedState<T> {
+    fn clone(&self) -> Self {
+        Self {
+            latched: self.latched.clone(),
+            changed: self.changed,
+            derivation: self.derivation.lock().unwrap().fork(),
+            tracker: self.tracker.boxed_clone(),
+            mirror: self.mirror.clone(),
+        }
+    }
+}
+
+impl<T: Clone + PartialEq + Send + Sync + 'static> DerivedState<T> {
+    /// Computes the state from `sources`, a single state or a tuple of states.
+    ///
+    /// `compute` is handed the latched value of each source, a clone for [`ValState`] and the shared
+    /// value for [`RefState`]. [`latest_value`](Self::latest_value) hands it the latest ones instead.
+    ///
+    /// [`ValState`]: crate::val_state::ValState
+    /// [`RefState`]: crate::ref_state::RefState
+    pub fn new<S, F>(sources: S, compute: F) -> Self
+    where
+        S: DerivedSource,
+        F: FnMut(&<S::Tracker as SourceTracker>::Value) -> T + Send + 'static,
+    {
+        let sources = sources.tracker();
+        let mut derivation = Derivation {
+            latched_sources: sources.clone(),
+            sources,
+            compute: Arc::new(Mutex::new(compute)),
+            memo: None,
+        };
+        let latched = derivation.get_latched();
+        let tracker = derivation.tracker();
+
+        Self {
+            latched,
+            changed: false,
+            derivation: Arc::new(Mutex::new(derivation)),
+            tracker,
+            mirror: LatchedValue::default(),
+        }
+    }
+
+    pub fn latch_value(&mut self) {
+        let value = self.derivation.lock().unwrap().latch();
+        self.changed = match value {
+            Some(value) if value != self.latched => {
+                self.latched = value;
+                true
+            }
+            _ => false,
+        };
+        self.mirror.update(self.changed, &self.latched);
+    }
+
+    /// Returns true if the latest latch recomputed a value that differs from the previous one.
+    pub fn changed_this_frame(&self) -> bool {
+        self.changed
+    }
+
+    pub fn latest_value(&self) -> T {
+        self.derivation.lock().unwrap().get()
+    }
+
+    pub fn value(&self) -> &T {
+        &self.latched
+    }
+
+    pub fn change_detector(&self) -> DerivedStateChangeDetector {
+        let mut detectors = Vec::new();
+        self.tracker.change_detectors(&mut detectors);
+
+        DerivedStateChangeDetector {
+            detectors: Arc::new(detectors),
+        }
+    }
+
+    pub fn handle(&self) -> DerivedStateHandle<T> {
+        DerivedStateHandle {
+            latched: self.latched.clone(),
+            derivation: self.derivation.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DerivedStateChangeDetector {
+    detectors: Arc<Vec<Box<dyn ChangeDetector>>>,
+}
+
+impl ChangeDetector for DerivedStateChangeDetector {
+    fn wait_for_change(&self) -> Pin<Box<dyn Future<Output = Option<()>> + Send + 'static>> {
+        // With nothing to derive from, the value can never change.
+        crate::wait_for_any(
+            self.detectors
+                .iter()
+                .map(|detector| detector.wait_for_change())
+                .collect(),
+        )
+    }
+}
+
+pub struct DerivedStateHandle<T> {
+    latched: T,
+    derivation: Arc<Mutex<dyn Derive<T>>>,
+}
+
+impl<T: Clone> Clone for DerivedStateHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            latched: self.latched.clone(),
+            derivation: self.derivation.clone(),
+        }
+    }
+}
+
+impl<T> DerivedStateHandle<T> {
+    pub fn value(&self) -> &T {
+        &self.latched
+    }
+
+    pub fn latest_value(&self) -> T {
+        self.derivation.lock().unwrap().get()
+    }
+}
+
+pub struct DerivedStateTracker<T> {
+    derivation: Arc<Mutex<dyn Derive<T>>>,
+    sources: Box<dyn ChangeTracker>,
+    latched: LatchedReceiver<T>,
+}
+
+impl<T> Clone for DerivedStateTracker<T> {
+    fn clone(&self) -> Self {
+        Self {
+            derivation: self.derivation.clone(),
+            sources: self.sources.boxed_clone(),
+            latched: self.latched.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> ChangeTracker for DerivedStateTracker<T> {
+    fn has_changed(&self) -> bool {
+        self.sources.has_changed()
+    }
+
+    fn mark_seen(&mut self) {
+        self.sources.mark_seen()
+    }
+
+    fn change_detectors(&self, detectors: &mut Vec<Box<dyn ChangeDetector>>) {
+        self.sources.change_detectors(detectors)
+    }
+
+    fn boxed_clone(&self) -> Box<dyn ChangeTracker> {
+        Box::new(self.clone())
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        self.sources.connect(notifier)
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> SourceTracker for DerivedStateTracker<T> {
+    type Value = T;
+
+    fn read(&mut self) -> Self::Value {
+        self.mark_seen();
+        self.derivation.lock().unwrap().get()
+    }
+
+    fn latched_changed(&self) -> bool {
+        self.latched
+            .has_changed()
+            .unwrap_or_else(|| self.has_changed())
+    }
+
+    fn read_latched(&mut self) -> Self::Value {
+        match self.latched.read() {
+            Some(value) => value,
+            None => self.read(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> DerivedSource for DerivedState<T> {
+    type Tracker = DerivedStateTracker<T>;
+
+    fn tracker(&self) -> Self::Tracker {
+        DerivedStateTracker {
+            derivation: self.derivation.clone(),
+            sources: self.derivation.lock().unwrap().tracker(),
+            latched: self.mirror.subscribe(),
+        }
+    }
+}
+
+impl<T: Clone + PartialEq + Send + Sync + 'static> Stateful for DerivedState<T> {
+    type ChangeDetector = DerivedStateChangeDetector;
+    type Handle = DerivedStateHandle<T>;
+
+    fn latch(&mut self) {
+        self.latch_value()
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+
+    /// Connects the sources the state is derived from, so a change of a source that belongs to no other
+    /// ViewModel repaints.
+    fn connect(&self, notifier: &ChangeNotifier) {
+        self.tracker.connect(notifier);
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        self.changed
+    }
+}
+
+impl<T: Clone + PartialEq + Send + Sync + 'static> ViewModelLike for DerivedState<T> {
+    fn latch_state(&mut self) {
+        self.latch_value()
+    }
+
+    fn change_detector_boxed(&self) -> Box<dyn ChangeDetector> {
+        Box::new(self.change_detector())
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(self, notifier);
+    }
+}
+
+impl<T: Clone + PartialEq + Send + Sync + 'static> ViewModel for DerivedState<T> {
+    type Model = DerivedStateHandle<T>;
+    type ChangeDetector = DerivedStateChangeDetector;
+
+    fn make_model(&self) -> Self::Model {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::ref_state::RefState;
+    use crate::val_state::ValState;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn recomputes_only_when_a_source_latches_a_change() {
+        let computed = Arc::new(AtomicUsize::new(0));
+        let mut count = ValState::new(1);
+        let mut doubled = DerivedState::new(&count, {
+            let computed = computed.clone();
+            move |count| {
+                computed.fetch_add(1, Ordering::Relaxed);
+                count * 2
+            }
+        });
+        // Sources latch before the states derived from them, like in a ViewModel.
+        count.latch_value();
+        doubled.latch_value();
+        let initial = computed.load(Ordering::Relaxed);
+        assert_eq!(*doubled.value(), 2);
+
+        count.send_value(2);
+        doubled.latch_value();
+        assert_eq!(*doubled.value(), 2);
+        assert_eq!(computed.load(Ordering::Relaxed), initial);
+
+        count.latch_value();
+        doubled.latch_value();
+        assert_eq!(*doubled.value(), 4);
+        assert!(doubled.changed_this_frame());
+        assert_eq!(computed.load(Ordering::Relaxed), initial + 1);
+
+        count.latch_value();
+        doubled.latch_value();
+        assert!(!doubled.changed_this_frame());
+        assert_eq!(computed.load(Ordering::Relaxed), initial + 1);
+    }
+
+    #[test]
+    fn unchanged_result_is_not_a_change() {
+        let mut count = ValState::new(1);
+        let mut positive = DerivedState::new(&count, |count| *count > 0);
+
+        count.send_value(2);
+        count.latch_value();
+        positive.latch_value();
+
+        assert!(count.changed_this_frame());
+        assert!(!positive.changed_this_frame());
+    }
+
+    #[test]
+    fn derives_from_several_sources() {
+        let mut first = ValState::new("Ada".to_string());
+        let mut last = RefState::new("Lovelace".to_string());
+        let mut name = DerivedState::new((&first, &last), |(first, last)| {
+            format!("{first} {}", last.lock().unwrap())
+        });
+        let mut initials = DerivedState::new(&name, |name| {
+            name.split(' ')
+                .filter_map(|word| word.chars().next())
+                .collect::<String>()
+        });
+
+        last.send_value("Byron".to_string());
+        assert_eq!(name.latest_value(), "Ada Byron");
+
+        first.latch_value();
+        last.latch_value();
+        name.latch_value();
+        initials.latch_value();
+        assert_eq!(name.value(), "Ada Byron");
+        assert_eq!(initials.value(), "AB");
+    }
+
+    #[test]
+    fn clones_are_latched_independently() {
+        let mut count = ValState::new(1);
+        let mut doubled = DerivedState::new(&count, |count| count * 2);
+        let mut clone = doubled.clone();
+
+        count.send_value(2);
+        count.latch_value();
+        doubled.latch_value();
+        clone.latch_value();
+
+        assert_eq!((*doubled.value(), *clone.value()), (4, 4));
+        assert!(doubled.changed_this_frame());
+        assert!(clone.changed_this_frame());
+    }
+}
@@ -0,0 +1,419 @@
+use crate::async_state::AsyncState;
+use crate::derived_state::DerivedState;
+use crate::history_state::{HistoryState, RefHistoryState};
+use crate::list_state::ListState;
+use crate::map_state::MapState;
+#[cfg(feature = "recorder")]
+use crate::recorder::Recording;
+use crate::ref_state::RefState;
+use crate::val_state::ValState;
+use crate::view_model::{EguiViewModelsExt, ViewModel, ViewModelLike, ViewModelTaskPool};
+use crate::view_model_list::ViewModelList;
+use egui::{CollapsingHeader, Grid, Id, RichText, ViewportId};
+use std::fmt::Debug;
+
+/// A state whose values can be shown by the [`inspector`], enabled with `#[viewmodel(debug)]`.
+pub trait Inspect {
+    fn latched_debug(&self) -> String;
+    fn latest_debug(&self) -> String;
+}
+
+/// A field of a ViewModel, as shown by the [`inspector`].
+#[derive(Debug, Clone)]
+pub struct FieldInspection {
+    pub name: &'static str,
+    pub latched: String,
+    pub latest: String,
+}
+
+impl FieldInspection {
+    pub fn new(name: &'static str, state: &impl Inspect) -> Self {
+        Self {
+            name,
+            latched: state.latched_debug(),
+            latest: state.latest_debug(),
+        }
+    }
+}
+
+impl<S: Debug + Clone + Send + Sync + 'static> Inspect for ValState<S> {
+    fn latched_debug(&self) -> String {
+        format!("{:?}", self.value())
+    }
+
+    fn latest_debug(&self) -> String {
+        format!("{:?}", self.latest_value())
+    }
+}
+
+impl<S: Debug + Send + Sync + 'static> Inspect for RefState<S> {
+    fn latched_debug(&self) -> String {
+        format!("{:?}", &*self.value())
+    }
+
+    fn latest_debug(&self) -> String {
+        format!("{:?}", &*self.latest_value().lock().unwrap())
+    }
+}
+
+impl<S: Debug + Clone + Send + Sync + 'static> Inspect for HistoryState<S> {
+    fn latched_debug(&self) -> String {
+        format!("{:?}", self.value())
+    }
+
+    fn latest_debug(&self) -> String {
+        format!("{:?}", self.latest_value())
+    }
+}
+
+impl<S: Debug + Clone + Send + Sync + 'static> Inspect for RefHistoryState<S> {
+    fn latched_debug(&self) -> String {
+        format!("{:?}", &*self.value())
+    }
+
+    fn latest_debug(&self) -> String {
+        format!("{:?}", &*self.latest_value().lock().unwrap())
+    }
+}
+
+impl<T: Debug + Clone + PartialEq + Send + Sync + 'static> Inspect for DerivedState<T> {
+    fn latched_debug(&self) -> String {
+        format!("{:?}", self.value())
+    }
+
+    fn latest_debug(&self) -> String {
+        format!("{:?}", self.latest_value())
+    }
+}
+
+impl<T: Debug + Clone + Send + Sync + 'static> Inspect for ListState<T> {
+    fn latched_debug(&self) -> String {
+        format!("{:?}", self.iter().collect::<Vec<_>>())
+    }
+
+    fn latest_debug(&self) -> String {
+        format!("{:?}", self.latest_value())
+    }
+}
+
+impl<K, V> Inspect for MapState<K, V>
+where
+    K: Debug + Ord + Clone + Send + Sync + 'static,
+    V: Debug + Clone + Send + Sync + 'static,
+{
+    fn latched_debug(&self) -> String {
+        format!("{:?}", self.iter().collect::<Vec<_>>())
+    }
+
+    fn latest_debug(&self) -> String {
+        format!("{:?}", self.latest_value())
+    }
+}
+
+impl<T, E> Inspect for AsyncState<T, E>
+where
+    T: Debug + Clone + Send + Sync + 'static,
+    E: Debug + Clone + Send + Sync + 'static,
+{
+    fn latched_debug(&self) -> String {
+        format!("{:?}", self.value())
+    }
+
+    fn latest_debug(&self) -> String {
+        format!("{:?}", self.latest_value())
+    }
+}
+
+/// A nested ViewModel shows the fields listed by its own `#[viewmodel(debug)]`.
+impl<T: ViewModelLike + ViewModelTaskPool> Inspect for T {
+    fn latched_debug(&self) -> String {
+        nested_debug(self, |field| &field.latched)
+    }
+
+    fn latest_debug(&self) -> String {
+        nested_debug(self, |field| &field.latest)
+    }
+}
+
+/// The fields of `view_model` as `{ field: value }`, `{ .. }` if it is not declared with `#[viewmodel(debug)]`.
+fn nested_debug(
+    view_model: &dyn ViewModelLike,
+    value: impl Fn(&FieldInspection) -> &String,
+) -> String {
+    let Some(fields) = view_model.inspect() else {
+        return "{ .. }".to_string();
+    };
+    let fields = fields
+        .iter()
+        .map(|field| format!("{}: {}", field.name, value(field)))
+        .collect::<Vec<_>>();
+    format!("{{ {} }}", fields.join(", "))
+}
+
+impl<T: Inspect> Inspect for Vec<T> {
+    fn latched_debug(&self) -> String {
+        list_debug(self.iter().map(T::latched_debug))
+    }
+
+    fn latest_debug(&self) -> String {
+        list_debug(self.iter().map(T::latest_debug))
+    }
+}
+
+impl<T: Inspect> Inspect for Option<T> {
+    fn latched_debug(&self) -> String {
+        self.as_ref().map_or("None".to_string(), T::latched_debug)
+    }
+
+    fn latest_debug(&self) -> String {
+        self.as_ref().map_or("None".to_string(), T::latest_debug)
+    }
+}
+
+impl<T: ViewModel + Inspect> Inspect for ViewModelList<T> {
+    fn latched_debug(&self) -> String {
+        list_debug(self.iter().map(|child| child.get().latched_debug()))
+    }
+
+    fn latest_debug(&self) -> String {
+        list_debug(
+            self.latest_value()
+                .iter()
+                .map(|child| child.get().latest_debug()),
+        )
+    }
+}
+
+fn list_debug(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(", "))
+}
+
+/// What the [`inspector`] shows of a registered ViewModel.
+struct ViewModelInspection {
+    type_name: &'static str,
+    id: Id,
+    visible: bool,
+    viewport: ViewportId,
+    last_seen_frame: u64,
+    changes: u64,
+    running_tasks: usize,
+    /// `None` if the ViewModel was locked, or not declared with `#[viewmodel(debug)]`.
+    fields: Option<Vec<FieldInspection>>,
+}
+
+/// Shows every ViewModel registered in `ctx`, with its fields, change count and running tasks.
+///
+/// Fields are only listed for ViewModels declared with `#[viewmodel(debug)]`, which needs every field
+/// to implement [`Inspect`].
+///
+/// ```ignore
+/// egui::Window::new("ViewModels").show(ctx, |ui| egui_mvvm::devtools::inspector(ctx, ui));
+/// ```
+pub fn inspector(ctx: &egui::Context, ui: &mut egui::Ui) {
+    let view_models = ctx.memory_mut(|mem| mem.view_models());
+    let frame = view_models.frame();
+
+    // Collected first, so no lock is held while drawing.
+    let inspections = view_models
+        .0
+        .lock()
+        .unwrap()
+        .view_models
+        .iter()
+        .filter_map(|entry| {
+            let vm = entry.view_model.upgrade()?;
+            let vm = vm.try_read().ok();
+            Some(ViewModelInspection {
+                type_name: entry.type_name,
+                id: entry.id,
+                visible: entry.visible,
+                viewport: entry.viewport,
+                last_seen_frame: entry.last_seen_frame,
+                changes: entry.changes(),
+                running_tasks: vm.as_ref().map_or(0, |vm| vm.running_tasks()),
+                fields: vm.and_then(|vm| vm.inspect()),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    ui.label(format!("Frame {frame}, {} ViewModels", inspections.len()));
+    let metrics = view_models.repaint_metrics();
+    ui.label(format!(
+        "Repaints for {} changes: {} immediate, {} delayed, {} coalesced",
+        metrics.notifications, metrics.immediate, metrics.delayed, metrics.coalesced
+    ));
+
+    for (index, vm) in inspections.iter().enumerate() {
+        let title = format!("{} {:?}", short_type_name(vm.type_name), vm.id);
+        CollapsingHeader::new(title)
+            .id_salt(("egui_mvvm::inspector", index))
+            .show(ui, |ui| {
+                ui.label(RichText::new(vm.type_name).weak());
+                ui.label(format!(
+                    "{} in {:?}, last seen in frame {}",
+                    if vm.visible { "Visible" } else { "Hidden" },
+                    vm.viewport,
+                    vm.last_seen_frame
+                ));
+                ui.label(format!("Changes: {}", vm.changes));
+                ui.label(format!("Running tasks: {}", vm.running_tasks));
+
+                let Some(fields) = &vm.fields else {
+                    ui.label(
+                        RichText::new("Declare with #[viewmodel(debug)] to see fields").weak(),
+                    );
+                    return;
+                };
+
+                Grid::new(("egui_mvvm::inspector::fields", index))
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Field");
+                        ui.strong("Latched");
+                        ui.strong("Latest");
+                        ui.end_row();
+
+                        for field in fields {
+                            ui.label(field.name);
+                            ui.label(&field.latched);
+                            if field.latest == field.latched {
+                                ui.label(&field.latest);
+                            } else {
+                                // Published but not latched yet.
+                                ui.colored_label(ui.visuals().warn_fg_color, &field.latest);
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+    }
+}
+
+/// Shows the [`inspector`] in its own window, closed by the window's close button.
+pub fn inspector_window(ctx: &egui::Context, open: &mut bool) {
+    egui::Window::new("ViewModels")
+        .open(open)
+        .vscroll(true)
+        .show(ctx, |ui| inspector(ctx, ui));
+}
+
+/// What the [`timeline`] remembers between frames.
+#[cfg(feature = "recorder")]
+#[derive(Clone, Default)]
+struct TimelineState {
+    frame: u64,
+    export_path: String,
+    /// The result of the last export, shown under the export button.
+    exported: Option<Result<String, String>>,
+    /// The last stopped recording, kept for export.
+    stopped: Option<Recording>,
+}
+
+/// Records the values latched by the ViewModels declared with `#[viewmodel(record)]`, and travels back to
+/// any frame of the current or stopped recording by latching those values, see
+/// [`ViewModels::travel_through`](crate::view_model::ViewModels::travel_through).
+///
+/// Recordings can be exported to a file, and replayed in a test with [`Recording::replay`].
+#[cfg(feature = "recorder")]
+pub fn timeline(ctx: &egui::Context, ui: &mut egui::Ui) {
+    let view_models = ctx.memory_mut(|mem| mem.view_models());
+    let id = Id::new("egui_mvvm::timeline");
+    let mut state = ui.data_mut(|data| data.get_temp_mut_or_default::<TimelineState>(id).clone());
+
+    ui.horizontal(|ui| {
+        if view_models.is_recording() {
+            if ui.button("⏹ Stop").clicked() {
+                state.stopped = view_models.stop_recording();
+            }
+        } else if ui.button("⏺ Record").clicked() {
+            state.stopped = None;
+            view_models.start_recording();
+        }
+
+        if let Some(frame) = view_models.traveling() {
+            ui.label(format!("Showing frame {frame}"));
+            if ui.button("▶ Resume").clicked() {
+                view_models.stop_travel();
+            }
+        }
+    });
+
+    let recording = view_models.recording().or_else(|| state.stopped.clone());
+    let Some(recording) = recording else {
+        ui.label(RichText::new("Nothing recorded").weak());
+        ui.data_mut(|data| data.insert_temp(id, state));
+        return;
+    };
+
+    let frames = recording.frames();
+    if let (Some(&first), Some(&last)) = (frames.first(), frames.last()) {
+        state.frame = state.frame.clamp(first, last);
+        let slider = ui.add(egui::Slider::new(&mut state.frame, first..=last).text("Frame"));
+        if slider.changed() {
+            view_models.travel_through(&recording, state.frame);
+        }
+
+        Grid::new("egui_mvvm::timeline::changes")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("ViewModel");
+                ui.strong("Field");
+                ui.strong("Value");
+                ui.strong("Task");
+                ui.end_row();
+
+                for change in recording.changes.iter().filter(|c| c.frame == state.frame) {
+                    ui.label(short_type_name(&change.view_model));
+                    ui.label(&change.field);
+                    ui.label(change.value.to_string());
+                    ui.label(change.task.as_deref().unwrap_or("UI"));
+                    ui.end_row();
+                }
+            });
+    }
+
+    ui.label(format!(
+        "{} changes in {} frames",
+        recording.changes.len(),
+        frames.len()
+    ));
+
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(&mut state.export_path);
+        if ui.button("Export").clicked() {
+            state.exported = Some(
+                recording
+                    .save(&state.export_path)
+                    .map(|()| format!("Saved to {}", state.export_path))
+                    .map_err(|err| err.to_string()),
+            );
+        }
+    });
+    match &state.exported {
+        Some(Ok(message)) => {
+            ui.label(message);
+        }
+        Some(Err(err)) => {
+            ui.colored_label(ui.visuals().error_fg_color, err);
+        }
+        None => {}
+    }
+
+    ui.data_mut(|data| data.insert_temp(id, state));
+}
+
+/// Shows the [`timeline`] in its own window, closed by the window's close button.
+#[cfg(feature = "recorder")]
+pub fn timeline_window(ctx: &egui::Context, open: &mut bool) {
+    egui::Window::new("Timeline")
+        .open(open)
+        .vscroll(true)
+        .show(ctx, |ui| timeline(ctx, ui));
+}
+
+/// Strips the module path, keeping generic arguments readable.
+fn short_type_name(type_name: &str) -> &str {
+    let end = type_name.find('<').unwrap_or(type_name.len());
+    let start = type_name[..end].rfind("::").map_or(0, |i| i + 2);
+    &type_name[start..]
+}
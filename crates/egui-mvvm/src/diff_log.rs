@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+
+/// The most operations kept for a state that is not latched, older ones are dropped.
+const MAX_PENDING: usize = 1024;
+
+/// The operations published to a collection state, numbered by version.
+///
+/// Operations are kept until a latch has seen them. A latch that is too far behind, because operations
+/// were dropped, gets no diff and has to treat the collection as reset.
+#[derive(Clone)]
+pub(crate) struct DiffLog<D> {
+    ops: VecDeque<(u64, D)>,
+    version: u64,
+    /// The latest version whose operation was dropped.
+    dropped: u64,
+}
+
+impl<D> Default for DiffLog<D> {
+    fn default() -> Self {
+        Self {
+            ops: VecDeque::new(),
+            version: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl<D: Clone> DiffLog<D> {
+    pub(crate) fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Records `op` and returns its version.
+    pub(crate) fn push(&mut self, op: D) -> u64 {
+        self.version += 1;
+        self.ops.push_back((self.version, op));
+
+        if self.ops.len() > MAX_PENDING
+            && let Some((version, _)) = self.ops.pop_front()
+        {
+            self.dropped = version;
+        }
+
+        self.version
+    }
+
+    /// The operations published after `version`, `None` if some of them were dropped.
+    pub(crate) fn since(&self, version: u64) -> Option<Vec<D>> {
+        if version < self.dropped {
+            return None;
+        }
+
+        Some(
+            self.ops
+                .iter()
+                .filter(|(v, _)| *v > version)
+                .map(|(_, op)| op.clone())
+                .collect(),
+        )
+    }
+
+    /// Drops the operations up to `version`, once they have been latched.
+    pub(crate) fn trim(&mut self, version: u64) {
+        while let Some((v, _)) = self.ops.front()
+            && *v <= version
+        {
+            self.dropped = *v;
+            self.ops.pop_front();
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use futures::future::{BoxFuture, LocalBoxFuture};
+use futures::stream::FuturesUnordered;
+use futures::task::{ArcWake, waker};
+use futures::{Stream, StreamExt};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+
+/// Runs the tasks of a [`TaskPool`](crate::task_pool::TaskPool).
+///
+/// With the `tokio` feature, enabled by default, pools use [`TokioExecutor`] unless another executor was set
+/// with [`set_default_executor`] or [`TaskPool::with_executor`](crate::task_pool::TaskPool::with_executor).
+/// On `wasm32` with the `web` feature, they use `WebExecutor`.
+pub trait Executor: Send + Sync + 'static {
+    fn spawn(&self, task: BoxFuture<'static, ()>);
+
+    /// Spawns a task that is not `Send`, on the current thread.
+    fn spawn_local(&self, task: LocalBoxFuture<'static, ()>);
+}
+
+static DEFAULT_EXECUTOR: RwLock<Option<Arc<dyn Executor>>> = RwLock::new(None);
+
+/// Sets the executor used by every [`TaskPool`](crate::task_pool::TaskPool) created without one.
+pub fn set_default_executor(executor: impl Executor) {
+    *DEFAULT_EXECUTOR.write().unwrap() = Some(Arc::new(executor));
+}
+
+pub(crate) fn default_executor() -> Arc<dyn Executor> {
+    if let Some(executor) = &*DEFAULT_EXECUTOR.read().unwrap() {
+        return executor.clone();
+    }
+
+    #[cfg(all(feature = "web", target_arch = "wasm32"))]
+    return Arc::new(WebExecutor);
+
+    #[cfg(all(feature = "tokio", not(all(feature = "web", target_arch = "wasm32"))))]
+    return Arc::new(TokioExecutor);
+
+    #[cfg(not(any(feature = "tokio", all(feature = "web", target_arch = "wasm32"))))]
+    panic!(
+        "no executor to spawn the task on, call `set_default_executor` or enable the `tokio` feature"
+    );
+}
+
+/// Spawns tasks on the tokio runtime of the calling thread.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioExecutor;
+
+#[cfg(feature = "tokio")]
+impl Executor for TokioExecutor {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        tokio::spawn(task);
+    }
+
+    /// Needs to be called from inside a `tokio::task::LocalSet`.
+    fn spawn_local(&self, task: LocalBoxFuture<'static, ()>) {
+        tokio::task::spawn_local(task);
+    }
+}
+
+/// Spawns tasks on the browser's event loop with `wasm_bindgen_futures::spawn_local`.
+///
+/// The default executor on `wasm32` with the `web` feature.
+#[cfg(feature = "web")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebExecutor;
+
+#[cfg(feature = "web")]
+impl Executor for WebExecutor {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        wasm_bindgen_futures::spawn_local(task);
+    }
+
+    fn spawn_local(&self, task: LocalBoxFuture<'static, ()>) {
+        wasm_bindgen_futures::spawn_local(task);
+    }
+}
+
+thread_local! {
+    /// The local tasks spawned from this thread, by the id of their [`FrameExecutor`].
+    static LOCAL_QUEUES: RefCell<HashMap<u64, Rc<LocalQueue>>> = RefCell::default();
+}
+
+/// The local tasks of a [`FrameExecutor`] on one thread.
+#[derive(Default)]
+struct LocalQueue {
+    incoming: RefCell<Vec<LocalBoxFuture<'static, ()>>>,
+    tasks: RefCell<FuturesUnordered<LocalBoxFuture<'static, ()>>>,
+}
+
+/// A single threaded executor driven by the egui frame loop, without any async runtime.
+///
+/// Tasks only make progress inside [`FrameExecutor::run`], which should be called once per frame.
+/// When a task is woken, a repaint is requested so the next frame runs it.
+///
+/// ```ignore
+/// let executor = FrameExecutor::new(ctx.clone());
+/// set_default_executor(executor.clone());
+///
+/// // In `eframe::App::update`:
+/// executor.run();
+/// ctx.memory_mut(|mem| mem.view_models().latch_values());
+/// ```
+///
+/// Every executor runs its own tasks. Local tasks are run by the thread that spawned them, on its next call
+/// to `run`.
+#[derive(Clone)]
+pub struct FrameExecutor {
+    inner: Arc<FrameExecutorInner>,
+}
+
+struct FrameExecutorInner {
+    /// Keys the local tasks of the executor in [`LOCAL_QUEUES`].
+    id: u64,
+    ctx: egui::Context,
+    incoming: Mutex<Vec<BoxFuture<'static, ()>>>,
+    tasks: Mutex<FuturesUnordered<BoxFuture<'static, ()>>>,
+}
+
+/// Only the local tasks of the dropping thread can be dropped with the executor, the ones spawned from other
+/// threads stay until those threads exit.
+impl Drop for FrameExecutorInner {
+    fn drop(&mut self) {
+        let _ = LOCAL_QUEUES.try_with(|queues| queues.borrow_mut().remove(&self.id));
+    }
+}
+
+impl ArcWake for FrameExecutorInner {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.ctx.request_repaint();
+    }
+}
+
+impl FrameExecutor {
+    pub fn new(ctx: egui::Context) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        Self {
+            inner: Arc::new(FrameExecutorInner {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                ctx,
+                incoming: Default::default(),
+                tasks: Default::default(),
+            }),
+        }
+    }
+
+    /// Polls every task until none can make progress.
+    pub fn run(&self) {
+        let waker = waker(self.inner.clone());
+        let mut cx = Context::from_waker(&waker);
+        let local = self.local_queue();
+
+        loop {
+            let incoming = std::mem::take(&mut *self.inner.incoming.lock().unwrap());
+            let local_incoming = std::mem::take(&mut *local.incoming.borrow_mut());
+
+            {
+                let mut tasks = self.inner.tasks.lock().unwrap();
+                tasks.extend(incoming);
+                poll_until_stalled(&mut *tasks, &mut cx);
+            }
+
+            {
+                let mut tasks = local.tasks.borrow_mut();
+                tasks.extend(local_incoming);
+                poll_until_stalled(&mut *tasks, &mut cx);
+            }
+
+            // Tasks spawned while polling have not run yet.
+            let pending = !self.inner.incoming.lock().unwrap().is_empty()
+                || !local.incoming.borrow().is_empty();
+            if !pending {
+                break;
+            }
+        }
+    }
+
+    /// The local tasks of the executor on the current thread, shared so tasks can spawn others while
+    /// being polled.
+    fn local_queue(&self) -> Rc<LocalQueue> {
+        LOCAL_QUEUES.with_borrow_mut(|queues| queues.entry(self.inner.id).or_default().clone())
+    }
+}
+
+fn poll_until_stalled(tasks: &mut (impl Stream<Item = ()> + Unpin), cx: &mut Context<'_>) {
+    while let Poll::Ready(Some(())) = tasks.poll_next_unpin(cx) {}
+}
+
+impl Executor for FrameExecutor {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        self.inner.incoming.lock().unwrap().push(task);
+        self.inner.ctx.request_repaint();
+    }
+
+    fn spawn_local(&self, task: LocalBoxFuture<'static, ()>) {
+        self.local_queue().incoming.borrow_mut().push(task);
+        self.inner.ctx.request_repaint();
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::task_pool::{TaskError, TaskPool};
+    use futures::channel::oneshot;
+
+    #[test]
+    fn tasks_only_run_when_the_executor_is_run() {
+        let executor = FrameExecutor::new(egui::Context::default());
+        let (tx, rx) = oneshot::channel();
+        let received = Arc::new(Mutex::new(None));
+        executor.spawn(Box::pin({
+            let received = received.clone();
+            async move {
+                *received.lock().unwrap() = rx.await.ok();
+            }
+        }));
+
+        tx.send(1).unwrap();
+        assert_eq!(*received.lock().unwrap(), None);
+
+        executor.run();
+        assert_eq!(*received.lock().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn executors_run_their_own_tasks() {
+        let first = FrameExecutor::new(egui::Context::default());
+        let second = FrameExecutor::new(egui::Context::default());
+        let log = Rc::new(RefCell::new(Vec::new()));
+        for (executor, name) in [(&first, "first"), (&second, "second")] {
+            let log = log.clone();
+            executor.spawn_local(Box::pin(async move { log.borrow_mut().push(name) }));
+        }
+
+        first.run();
+        assert_eq!(*log.borrow(), ["first"]);
+
+        second.run();
+        assert_eq!(*log.borrow(), ["first", "second"]);
+    }
+
+    #[test]
+    fn aborted_task_is_dropped_without_running() {
+        let executor = FrameExecutor::new(egui::Context::default());
+        let pool = TaskPool::with_executor(executor.clone());
+        let resource = Arc::new(());
+        let handle = pool.spawn({
+            let resource = resource.clone();
+            async move {
+                let _resource = resource;
+                unreachable!("aborted before its first poll");
+            }
+        });
+
+        handle.abort();
+        executor.run();
+
+        assert_eq!(Arc::strong_count(&resource), 1);
+        assert_eq!(handle.try_take_result(), Some(Err(TaskError::Aborted)));
+    }
+}
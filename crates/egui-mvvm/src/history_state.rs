@@ -0,0 +1,1189 @@
+use crate::derived_state::DerivedSource;
+use crate::notify::{ChangeNotifier, StateSignal};
+#[cfg(feature = "recorder")]
+use crate::recorder::{FieldRecorder, Value};
+use crate::ref_state::{
+    RefState, RefStateChangeDetector, RefStateHandle, RefStateHandleRef, RefStateMutRef,
+    RefStateRef, RefStateTracker,
+};
+use crate::snapshot::{self, in_mutable_snapshot};
+use crate::time::Instant;
+use crate::val_state::{ValState, ValStateChangeDetector, ValStateHandle, ValStateTracker};
+use crate::view_model::{ViewModel, ViewModelLike};
+use crate::{ChangeDetector, Stateful};
+use egui::TextBuffer;
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::watch;
+
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Clone)]
+struct History<S> {
+    undo: VecDeque<S>,
+    redo: Vec<S>,
+    limit: usize,
+    coalesce_window: Duration,
+    /// When the first edit of the latest undo step was recorded.
+    group_start: Option<Instant>,
+}
+
+impl<S> History<S> {
+    fn new() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit: DEFAULT_LIMIT,
+            coalesce_window: Duration::ZERO,
+            group_start: None,
+        }
+    }
+
+    fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.undo.len() > limit {
+            self.undo.pop_front();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.group_start = None;
+    }
+
+    /// Records `previous` as the value to return to, unless the first edit of the latest undo step was
+    /// recorded within the coalesce window.
+    fn record(&mut self, previous: S) {
+        let now = Instant::now();
+        let coalesced = self
+            .group_start
+            .is_some_and(|start| now.duration_since(start) < self.coalesce_window);
+
+        if !coalesced {
+            if self.undo.len() == self.limit {
+                self.undo.pop_front();
+            }
+            if self.limit > 0 {
+                self.undo.push_back(previous);
+            }
+            self.group_start = Some(now);
+        }
+
+        self.redo.clear();
+    }
+
+    fn undo(&mut self, current: &mut S) -> bool {
+        let Some(previous) = self.undo.pop_back() else {
+            return false;
+        };
+
+        self.redo.push(std::mem::replace(current, previous));
+        self.group_start = None;
+        true
+    }
+
+    fn redo(&mut self, current: &mut S) -> bool {
+        let Some(next) = self.redo.pop() else {
+            return false;
+        };
+
+        self.undo.push_back(std::mem::replace(current, next));
+        self.group_start = None;
+        true
+    }
+}
+
+/// A history shared by a state with its clones and handles.
+///
+/// Written through the snapshot system like the value it records: inside a mutable snapshot, edits, undos
+/// and redos move the snapshot's copy of the history, the shared one only moves when the writes are
+/// published on commit.
+struct SharedHistory<H> {
+    tx: watch::Sender<H>,
+    signal: StateSignal,
+}
+
+impl<H> Clone for SharedHistory<H> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            signal: self.signal.clone(),
+        }
+    }
+}
+
+impl<H: Clone + Send + Sync + 'static> SharedHistory<H> {
+    fn new(history: H) -> Self {
+        Self {
+            tx: watch::Sender::new(history),
+            signal: StateSignal::default(),
+        }
+    }
+
+    /// Applies `f` to the history, or to the snapshot's copy of it inside a mutable snapshot.
+    ///
+    /// Called by the writes of the value, which run again on commit.
+    fn update<R>(&self, f: impl FnOnce(&mut H) -> R) -> R {
+        if in_mutable_snapshot() {
+            return snapshot::update_view(&self.tx, &self.signal, f);
+        }
+
+        let mut result = None;
+        self.tx.send_if_modified(|history| {
+            result = Some(f(history));
+            false
+        });
+        result.expect("applied once")
+    }
+
+    /// Applies `f` to the history, and to the shared one on commit inside a mutable snapshot.
+    fn modify(&self, op: &'static str, mut f: impl FnMut(&mut H) + Send + 'static) {
+        if !in_mutable_snapshot() {
+            return self.update(f);
+        }
+
+        self.signal.publish(&self.tx, op, move |history| {
+            f(history);
+            Some(())
+        });
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&H) -> R) -> R {
+        self.signal.read(&self.tx, f)
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> SharedHistory<History<S>> {
+    fn can_undo(&self) -> bool {
+        self.read(|history| !history.undo.is_empty())
+    }
+
+    fn can_redo(&self) -> bool {
+        self.read(|history| !history.redo.is_empty())
+    }
+}
+
+/// A [`ValState`] that remembers its published values so they can be undone and redone.
+///
+/// Every value published through [`send_value`](HistoryState::send_value),
+/// [`send_modify`](HistoryState::send_modify), [`mark_changed`](HistoryState::mark_changed),
+/// [`value_mut`](HistoryState::value_mut) or a text edit is recorded, edits made within the coalesce
+/// window of the first one of a step are undone together. Inside a mutable snapshot, edits, undos and redos
+/// only move the shared history when the snapshot is committed.
+/// Like any other write, an undo or redo is seen by the view on the next latch.
+///
+/// ```ignore
+/// view_model! {
+///     #[viewmodel(default)]
+///     pub struct EditorViewModel {
+///         pub document: HistoryState<Document> = HistoryState::new(Document::default())
+///             .with_limit(50)
+///             .with_coalesce_window(Duration::from_millis(500)),
+///     }
+/// }
+/// ```
+pub struct HistoryState<S> {
+    state: ValState<S>,
+    history: SharedHistory<History<S>>,
+}
+
+impl<S: Clone> Clone for HistoryState<S> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            history: self.history.clone(),
+        }
+    }
+}
+
+impl<S: Default + Send + Sync + Clone + 'static> Default for HistoryState<S> {
+    fn default() -> Self {
+        Self::new(S::default())
+    }
+}
+
+impl<S: 'static + Send + Sync + Clone> HistoryState<S> {
+    pub fn new(value: S) -> Self {
+        Self {
+            state: ValState::new(value),
+            history: SharedHistory::new(History::new()),
+        }
+    }
+
+    /// Keeps at most `limit` undo steps, 100 by default.
+    pub fn with_limit(self, limit: usize) -> Self {
+        self.history
+            .tx
+            .send_modify(|history| history.set_limit(limit));
+        self
+    }
+
+    /// Records edits closer together than `window` as a single undo step, disabled by default.
+    pub fn with_coalesce_window(self, window: Duration) -> Self {
+        self.history
+            .tx
+            .send_modify(|history| history.coalesce_window = window);
+        self
+    }
+
+    pub fn latch_value(&mut self) {
+        self.state.latch_value()
+    }
+
+    /// Latches `value` without publishing or recording it, or the latest published value if `None`.
+    #[cfg(feature = "recorder")]
+    pub(crate) fn latch_replayed(&mut self, value: Option<S>) {
+        self.state.latch_replayed(value);
+    }
+
+    /// Returns true if the latest latch picked up a new value.
+    pub fn changed_this_frame(&self) -> bool {
+        self.state.changed_this_frame()
+    }
+
+    pub fn latest_value(&self) -> S {
+        self.state.latest_value()
+    }
+
+    pub fn value(&self) -> &S {
+        self.state.value()
+    }
+
+    /// Publishes and records the edit when the returned guard is dropped, if it was mutably dereferenced.
+    pub fn value_mut(&mut self) -> HistoryStateMutRef<'_, S> {
+        HistoryStateMutRef {
+            state: self,
+            changed: false,
+        }
+    }
+
+    /// Untracked access, call [`HistoryState::mark_changed`] to publish and record the edit.
+    pub fn value_mut_untracked(&mut self) -> &mut S {
+        self.state.value_mut_untracked()
+    }
+
+    pub fn send_value(&self, value: S) {
+        send_recorded(
+            self.state.channel(),
+            &self.history,
+            "send_value",
+            |latest| *latest = value,
+        );
+    }
+
+    pub fn send_modify(&self, f: impl FnOnce(&mut S)) {
+        send_recorded(self.state.channel(), &self.history, "send_modify", f);
+    }
+
+    pub fn mark_changed(&mut self) {
+        let value = self.state.value().clone();
+        send_recorded(
+            self.state.channel(),
+            &self.history,
+            "mark_changed",
+            |latest| *latest = value,
+        );
+    }
+
+    /// Publishes the value before the last recorded edit, returns false if there is none.
+    pub fn undo(&self) -> bool {
+        send_step(self.state.channel(), &self.history, "undo", History::undo)
+    }
+
+    /// Publishes the value undone last, returns false if there is none.
+    pub fn redo(&self) -> bool {
+        send_step(self.state.channel(), &self.history, "redo", History::redo)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    pub fn clear_history(&self) {
+        self.history.modify("clear_history", History::clear);
+    }
+
+    /// Records every value published to the state, see
+    /// [`Persistable::connect_recorder`](crate::persistence::Persistable::connect_recorder).
+    #[cfg(feature = "recorder")]
+    pub(crate) fn record_with(
+        &self,
+        recorder: FieldRecorder,
+        serialize: impl Fn(&S) -> Option<Value> + Send + Sync + 'static,
+    ) {
+        self.state.record_with(recorder, serialize);
+    }
+
+    pub fn change_detector(&self) -> ValStateChangeDetector<S> {
+        self.state.change_detector()
+    }
+
+    pub fn handle(&self) -> HistoryStateHandle<S> {
+        HistoryStateHandle {
+            handle: self.state.handle(),
+            history: self.history.clone(),
+        }
+    }
+}
+
+pub struct HistoryStateMutRef<'a, S: Clone + Send + Sync + 'static> {
+    state: &'a mut HistoryState<S>,
+    changed: bool,
+}
+
+impl<S: Clone + Send + Sync + 'static> Drop for HistoryStateMutRef<'_, S> {
+    fn drop(&mut self) {
+        if self.changed {
+            self.state.mark_changed();
+        }
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> Deref for HistoryStateMutRef<'_, S> {
+    type Target = S;
+    fn deref(&self) -> &Self::Target {
+        self.state.value()
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> DerefMut for HistoryStateMutRef<'_, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.changed = true;
+        self.state.value_mut_untracked()
+    }
+}
+
+/// Publishes a modification of the latest value and records the value it replaced.
+///
+/// The history is only written while the channel is locked, so concurrent edits and undos are ordered.
+/// Inside a mutable snapshot, `f` modifies a copy of the value, which is recorded in the shared history
+/// once it replaces the latest value on commit.
+fn send_recorded<S: Clone + Send + Sync + 'static>(
+    (tx, signal): (&watch::Sender<S>, &StateSignal),
+    history: &SharedHistory<History<S>>,
+    op: &'static str,
+    f: impl FnOnce(&mut S),
+) {
+    if !in_mutable_snapshot() {
+        signal.send(tx, op, |latest| {
+            let previous = latest.clone();
+            f(latest);
+            history.update(|history| history.record(previous));
+            true
+        });
+        return;
+    }
+
+    let mut value = signal.read(tx, S::clone);
+    f(&mut value);
+    let history = history.clone();
+    signal.publish(tx, op, move |latest| {
+        let previous = std::mem::replace(latest, value.clone());
+        history.update(|history| history.record(previous));
+        Some(())
+    });
+}
+
+/// Publishes the value `step` moves the history to, returns false if there is none.
+fn send_step<S: Clone + Send + Sync + 'static>(
+    (tx, signal): (&watch::Sender<S>, &StateSignal),
+    history: &SharedHistory<History<S>>,
+    op: &'static str,
+    step: fn(&mut History<S>, &mut S) -> bool,
+) -> bool {
+    let history = history.clone();
+    signal
+        .publish(tx, op, move |latest| {
+            history
+                .update(|history| step(history, latest))
+                .then_some(())
+        })
+        .is_some()
+}
+
+pub struct HistoryStateHandle<S> {
+    handle: ValStateHandle<S>,
+    history: SharedHistory<History<S>>,
+}
+
+impl<S: Clone> Clone for HistoryStateHandle<S> {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle.clone(),
+            history: self.history.clone(),
+        }
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> HistoryStateHandle<S> {
+    pub fn value(&self) -> &S {
+        self.handle.value()
+    }
+
+    pub fn latest_value(&self) -> S {
+        self.handle.latest_value()
+    }
+
+    pub fn send_value(&self, value: S) {
+        self.send_update(|latest| *latest = value);
+    }
+
+    pub fn send_update(&self, f: impl FnOnce(&mut S)) {
+        send_recorded(self.handle.channel(), &self.history, "send_update", f);
+    }
+
+    /// Publishes the value before the last recorded edit, returns false if there is none.
+    pub fn undo(&self) -> bool {
+        send_step(self.handle.channel(), &self.history, "undo", History::undo)
+    }
+
+    /// Publishes the value undone last, returns false if there is none.
+    pub fn redo(&self) -> bool {
+        send_step(self.handle.channel(), &self.history, "redo", History::redo)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> DerivedSource for HistoryState<S> {
+    type Tracker = ValStateTracker<S>;
+
+    fn tracker(&self) -> Self::Tracker {
+        self.state.tracker()
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> Stateful for HistoryState<S> {
+    type ChangeDetector = ValStateChangeDetector<S>;
+    type Handle = HistoryStateHandle<S>;
+
+    fn latch(&mut self) {
+        self.latch_value()
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+
+    fn set_name(&self, name: &'static str) {
+        self.state.set_name(name);
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(&self.state, notifier);
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        self.state.changed_this_frame()
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> ViewModelLike for HistoryState<S> {
+    fn latch_state(&mut self) {
+        self.latch_value()
+    }
+
+    fn change_detector_boxed(&self) -> Box<dyn ChangeDetector> {
+        Box::new(self.change_detector())
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(self, notifier);
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> ViewModel for HistoryState<S> {
+    type Model = HistoryStateHandle<S>;
+    type ChangeDetector = ValStateChangeDetector<S>;
+
+    fn make_model(&self) -> Self::Model {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+}
+
+impl TextBuffer for HistoryState<String> {
+    fn is_mutable(&self) -> bool {
+        true
+    }
+
+    fn as_str(&self) -> &str {
+        self.value().as_str()
+    }
+
+    fn insert_text(&mut self, text: &str, char_index: usize) -> usize {
+        let inserted = self.value_mut_untracked().insert_text(text, char_index);
+        if inserted > 0 {
+            self.mark_changed();
+        }
+        inserted
+    }
+
+    fn delete_char_range(&mut self, char_range: Range<usize>) {
+        if char_range.is_empty() {
+            return;
+        }
+
+        self.value_mut_untracked().delete_char_range(char_range);
+        self.mark_changed();
+    }
+}
+
+impl<T: Send + Sync + Clone + 'static> From<T> for HistoryState<T> {
+    fn from(value: T) -> Self {
+        HistoryState::new(value)
+    }
+}
+
+/// The history of a [`RefHistoryState`], with a copy of the value published last to record, since the
+/// shared value is edited in place.
+#[derive(Clone)]
+struct RefHistory<S> {
+    history: History<S>,
+    published: S,
+}
+
+impl<S: Clone> RefHistory<S> {
+    /// Records the value `value` replaces.
+    fn record(&mut self, value: S) {
+        let previous = std::mem::replace(&mut self.published, value);
+        self.history.record(previous);
+    }
+
+    fn undo(&mut self) -> Option<S> {
+        self.history
+            .undo(&mut self.published)
+            .then(|| self.published.clone())
+    }
+
+    fn redo(&mut self) -> Option<S> {
+        self.history
+            .redo(&mut self.published)
+            .then(|| self.published.clone())
+    }
+
+    fn clear(&mut self) {
+        self.history.clear();
+    }
+}
+
+/// A [`RefState`] that remembers its published values so they can be undone and redone.
+///
+/// Records like [`HistoryState`], through [`send_value`](RefHistoryState::send_value),
+/// [`send_modify`](RefHistoryState::send_modify), [`mark_changed`](RefHistoryState::mark_changed) and
+/// [`value_mut`](RefHistoryState::value_mut). Every recorded step is a copy of the value, and an undo or
+/// redo publishes a new shared value instead of editing it in place.
+pub struct RefHistoryState<S> {
+    state: RefState<S>,
+    history: SharedHistory<RefHistory<S>>,
+}
+
+impl<S: Clone> Clone for RefHistoryState<S> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            history: self.history.clone(),
+        }
+    }
+}
+
+impl<S: Default + Send + Sync + Clone + 'static> Default for RefHistoryState<S> {
+    fn default() -> Self {
+        Self::new(S::default())
+    }
+}
+
+impl<S: 'static + Send + Sync + Clone> RefHistoryState<S> {
+    pub fn new(value: S) -> Self {
+        Self {
+            history: SharedHistory::new(RefHistory {
+                history: History::new(),
+                published: value.clone(),
+            }),
+            state: RefState::new(value),
+        }
+    }
+
+    /// Keeps at most `limit` undo steps, 100 by default.
+    pub fn with_limit(self, limit: usize) -> Self {
+        self.history
+            .tx
+            .send_modify(|history| history.history.set_limit(limit));
+        self
+    }
+
+    /// Records edits closer together than `window` as a single undo step, disabled by default.
+    pub fn with_coalesce_window(self, window: Duration) -> Self {
+        self.history
+            .tx
+            .send_modify(|history| history.history.coalesce_window = window);
+        self
+    }
+
+    pub fn latch_value(&mut self) {
+        self.state.latch_value()
+    }
+
+    /// Latches `value` without publishing or recording it, or the latest published value if `None`.
+    #[cfg(feature = "recorder")]
+    pub(crate) fn latch_replayed(&mut self, value: Option<S>) {
+        self.state.latch_replayed(value);
+    }
+
+    /// Returns true if the latest latch picked up a new value.
+    pub fn changed_this_frame(&self) -> bool {
+        self.state.changed_this_frame()
+    }
+
+    pub fn latest_value(&self) -> Arc<Mutex<S>> {
+        self.state.latest_value()
+    }
+
+    pub fn value(&self) -> RefStateRef<'_, S> {
+        self.state.value()
+    }
+
+    /// Publishes and records the edit when the returned guard is dropped, if it was mutably dereferenced.
+    pub fn value_mut(&mut self) -> RefHistoryStateMutRef<'_, S> {
+        let (tx, signal) = self.state.channel();
+        RefHistoryStateMutRef {
+            tx: tx.clone(),
+            signal: signal.clone(),
+            history: self.history.clone(),
+            value: self.state.latched().clone(),
+            state: Some(self.state.latched().lock().unwrap()),
+            changed: false,
+        }
+    }
+
+    /// Untracked access, call [`RefHistoryState::mark_changed`] to publish and record the edit.
+    pub fn value_mut_untracked(&mut self) -> RefStateMutRef<'_, S> {
+        self.state.value_mut_untracked()
+    }
+
+    pub fn send_value(&self, value: S) {
+        send_copy(self.state.channel(), &self.history, "send_value", value);
+    }
+
+    pub fn send_modify(&self, f: impl FnOnce(&mut S)) {
+        modify_copy(self.state.channel(), &self.history, "send_modify", f);
+    }
+
+    /// Publishes and records the latched value, after it was edited in place.
+    pub fn mark_changed(&mut self) {
+        let value = self.state.latched().clone();
+        send_edited(self.state.channel(), &self.history, "mark_changed", value);
+    }
+
+    /// Publishes the value before the last recorded edit, returns false if there is none.
+    pub fn undo(&self) -> bool {
+        send_ref_step(
+            self.state.channel(),
+            &self.history,
+            "undo",
+            RefHistory::undo,
+        )
+    }
+
+    /// Publishes the value undone last, returns false if there is none.
+    pub fn redo(&self) -> bool {
+        send_ref_step(
+            self.state.channel(),
+            &self.history,
+            "redo",
+            RefHistory::redo,
+        )
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history
+            .read(|history| !history.history.undo.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history
+            .read(|history| !history.history.redo.is_empty())
+    }
+
+    pub fn clear_history(&self) {
+        self.history.modify("clear_history", RefHistory::clear);
+    }
+
+    /// Records every value published to the state, see
+    /// [`Persistable::connect_recorder`](crate::persistence::Persistable::connect_recorder).
+    #[cfg(feature = "recorder")]
+    pub(crate) fn record_with(
+        &self,
+        recorder: FieldRecorder,
+        serialize: impl Fn(&S) -> Option<Value> + Send + Sync + 'static,
+    ) {
+        self.state.record_with(recorder, serialize);
+    }
+
+    pub fn change_detector(&self) -> RefStateChangeDetector<S> {
+        self.state.change_detector()
+    }
+
+    pub fn handle(&self) -> RefHistoryStateHandle<S> {
+        RefHistoryStateHandle {
+            handle: self.state.handle(),
+            history: self.history.clone(),
+        }
+    }
+}
+
+pub struct RefHistoryStateMutRef<'a, S: Clone + Send + Sync + 'static> {
+    /// Released before the value is published, so it can be read while it is recorded.
+    state: Option<MutexGuard<'a, S>>,
+    value: Arc<Mutex<S>>,
+    changed: bool,
+    tx: watch::Sender<Arc<Mutex<S>>>,
+    signal: StateSignal,
+    history: SharedHistory<RefHistory<S>>,
+}
+
+impl<S: Clone + Send + Sync + 'static> Drop for RefHistoryStateMutRef<'_, S> {
+    fn drop(&mut self) {
+        self.state.take();
+        if self.changed {
+            send_edited(
+                (&self.tx, &self.signal),
+                &self.history,
+                "value_mut",
+                self.value.clone(),
+            );
+        }
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> Deref for RefHistoryStateMutRef<'_, S> {
+    type Target = S;
+    fn deref(&self) -> &Self::Target {
+        self.state.as_deref().expect("only released on drop")
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> DerefMut for RefHistoryStateMutRef<'_, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.changed = true;
+        self.state.as_deref_mut().expect("only released on drop")
+    }
+}
+
+/// Lets `ui.text_edit_singleline(&mut vm.text.value_mut())` edit a `RefHistoryState<String>`, the edit is
+/// published and recorded when the reference is dropped.
+impl TextBuffer for RefHistoryStateMutRef<'_, String> {
+    fn is_mutable(&self) -> bool {
+        true
+    }
+
+    fn as_str(&self) -> &str {
+        String::as_str(self)
+    }
+
+    fn insert_text(&mut self, text: &str, char_index: usize) -> usize {
+        if text.is_empty() {
+            return 0;
+        }
+
+        self.deref_mut().insert_text(text, char_index)
+    }
+
+    fn delete_char_range(&mut self, char_range: Range<usize>) {
+        if char_range.is_empty() {
+            return;
+        }
+
+        self.deref_mut().delete_char_range(char_range)
+    }
+}
+
+type RefChannel<'a, S> = (&'a watch::Sender<Arc<Mutex<S>>>, &'a StateSignal);
+
+/// Publishes `value` as a new shared value and records the value it replaces.
+fn send_copy<S: Clone + Send + Sync + 'static>(
+    (tx, signal): RefChannel<'_, S>,
+    history: &SharedHistory<RefHistory<S>>,
+    op: &'static str,
+    value: S,
+) {
+    let history = history.clone();
+    signal.publish(tx, op, move |latest| {
+        history.update(|history| history.record(value.clone()));
+        *latest = Arc::new(Mutex::new(value.clone()));
+        Some(())
+    });
+}
+
+/// Publishes a modified copy of the latest value and records the value it replaces, see
+/// [`send_recorded`].
+fn modify_copy<S: Clone + Send + Sync + 'static>(
+    (tx, signal): RefChannel<'_, S>,
+    history: &SharedHistory<RefHistory<S>>,
+    op: &'static str,
+    f: impl FnOnce(&mut S),
+) {
+    if !in_mutable_snapshot() {
+        signal.send(tx, op, |latest| {
+            let mut value = latest.lock().unwrap().clone();
+            f(&mut value);
+            history.update(|history| history.record(value.clone()));
+            *latest = Arc::new(Mutex::new(value));
+            true
+        });
+        return;
+    }
+
+    let mut value = signal.read(tx, |latest| latest.lock().unwrap().clone());
+    f(&mut value);
+    send_copy((tx, signal), history, op, value);
+}
+
+/// Publishes `value`, edited in place, and records the value published before the edit.
+fn send_edited<S: Clone + Send + Sync + 'static>(
+    (tx, signal): RefChannel<'_, S>,
+    history: &SharedHistory<RefHistory<S>>,
+    op: &'static str,
+    value: Arc<Mutex<S>>,
+) {
+    let edited = value.lock().unwrap().clone();
+    let history = history.clone();
+    signal.publish(tx, op, move |latest| {
+        history.update(|history| history.record(edited.clone()));
+        *latest = value.clone();
+        Some(())
+    });
+}
+
+/// Publishes the value `step` moves the history to as a new shared value, returns false if there is none.
+fn send_ref_step<S: Clone + Send + Sync + 'static>(
+    (tx, signal): RefChannel<'_, S>,
+    history: &SharedHistory<RefHistory<S>>,
+    op: &'static str,
+    step: fn(&mut RefHistory<S>) -> Option<S>,
+) -> bool {
+    let history = history.clone();
+    signal
+        .publish(tx, op, move |latest| {
+            let value = history.update(step)?;
+            *latest = Arc::new(Mutex::new(value));
+            Some(())
+        })
+        .is_some()
+}
+
+pub struct RefHistoryStateHandle<S> {
+    handle: RefStateHandle<S>,
+    history: SharedHistory<RefHistory<S>>,
+}
+
+impl<S: Clone> Clone for RefHistoryStateHandle<S> {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle.clone(),
+            history: self.history.clone(),
+        }
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> RefHistoryStateHandle<S> {
+    pub fn value(&self) -> RefStateHandleRef<'_, S> {
+        self.handle.value()
+    }
+
+    pub fn latest_value(&self) -> Arc<Mutex<S>> {
+        self.handle.latest_value()
+    }
+
+    pub fn send_value(&self, value: S) {
+        send_copy(self.handle.channel(), &self.history, "send_value", value);
+    }
+
+    pub fn send_update(&self, f: impl FnOnce(&mut S)) {
+        modify_copy(self.handle.channel(), &self.history, "send_update", f);
+    }
+
+    /// Publishes the value before the last recorded edit, returns false if there is none.
+    pub fn undo(&self) -> bool {
+        send_ref_step(
+            self.handle.channel(),
+            &self.history,
+            "undo",
+            RefHistory::undo,
+        )
+    }
+
+    /// Publishes the value undone last, returns false if there is none.
+    pub fn redo(&self) -> bool {
+        send_ref_step(
+            self.handle.channel(),
+            &self.history,
+            "redo",
+            RefHistory::redo,
+        )
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history
+            .read(|history| !history.history.undo.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history
+            .read(|history| !history.history.redo.is_empty())
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> DerivedSource for RefHistoryState<S> {
+    type Tracker = RefStateTracker<S>;
+
+    fn tracker(&self) -> Self::Tracker {
+        self.state.tracker()
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> Stateful for RefHistoryState<S> {
+    type ChangeDetector = RefStateChangeDetector<S>;
+    type Handle = RefHistoryStateHandle<S>;
+
+    fn latch(&mut self) {
+        self.latch_value()
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+
+    fn set_name(&self, name: &'static str) {
+        Stateful::set_name(&self.state, name);
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(&self.state, notifier);
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        self.state.changed_this_frame()
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> ViewModelLike for RefHistoryState<S> {
+    fn latch_state(&mut self) {
+        self.latch_value()
+    }
+
+    fn change_detector_boxed(&self) -> Box<dyn ChangeDetector> {
+        Box::new(self.change_detector())
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(self, notifier);
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> ViewModel for RefHistoryState<S> {
+    type Model = RefHistoryStateHandle<S>;
+    type ChangeDetector = RefStateChangeDetector<S>;
+
+    fn make_model(&self) -> Self::Model {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+}
+
+impl<T: Send + Sync + Clone + 'static> From<T> for RefHistoryState<T> {
+    fn from(value: T) -> Self {
+        RefHistoryState::new(value)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::snapshot::{exclusive, with_mutable_snapshot};
+
+    #[test]
+    fn undo_and_redo_publish_recorded_values() {
+        let mut text = HistoryState::new(0);
+        text.send_value(1);
+        text.send_value(2);
+
+        assert!(text.undo());
+        assert_eq!(text.latest_value(), 1);
+        assert_eq!(*text.value(), 0);
+        text.latch_value();
+        assert_eq!(*text.value(), 1);
+
+        assert!(text.undo());
+        assert!(!text.undo());
+        assert_eq!(text.latest_value(), 0);
+
+        assert!(text.redo());
+        assert!(text.redo());
+        assert!(!text.redo());
+        assert_eq!(text.latest_value(), 2);
+    }
+
+    #[test]
+    fn new_edit_drops_redo() {
+        let history = HistoryState::new(0);
+        history.send_value(1);
+        history.undo();
+        assert!(history.can_redo());
+
+        history.handle().send_value(2);
+        assert!(!history.can_redo());
+        assert!(history.undo());
+        assert_eq!(history.latest_value(), 0);
+    }
+
+    #[test]
+    fn limit_keeps_latest_steps() {
+        let history = HistoryState::new(0).with_limit(2);
+        for value in 1..=3 {
+            history.send_value(value);
+        }
+
+        assert!(history.undo());
+        assert!(history.undo());
+        assert!(!history.undo());
+        assert_eq!(history.latest_value(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesces_edits_within_window() {
+        let history = HistoryState::new(String::new()).with_coalesce_window(Duration::from_secs(1));
+        history.send_modify(|text| text.push('a'));
+        tokio::time::advance(Duration::from_millis(500)).await;
+        history.send_modify(|text| text.push('b'));
+        tokio::time::advance(Duration::from_secs(2)).await;
+        history.send_modify(|text| text.push('c'));
+
+        assert!(history.undo());
+        assert_eq!(history.latest_value(), "ab");
+        assert!(history.undo());
+        assert_eq!(history.latest_value(), "");
+        assert!(!history.can_undo());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesce_window_starts_at_the_first_edit() {
+        let history = HistoryState::new(String::new()).with_coalesce_window(Duration::from_secs(1));
+        for letter in ['a', 'b', 'c'] {
+            history.send_modify(|text| text.push(letter));
+            tokio::time::advance(Duration::from_millis(600)).await;
+        }
+
+        assert!(history.undo());
+        assert_eq!(history.latest_value(), "ab");
+        assert!(history.undo());
+        assert_eq!(history.latest_value(), "");
+    }
+
+    #[test]
+    fn value_mut_without_write_keeps_redo() {
+        let mut history = HistoryState::new(0);
+        history.send_value(1);
+        history.undo();
+        history.latch_value();
+
+        assert_eq!(*history.value_mut(), 0);
+        assert!(history.can_redo());
+        assert!(history.redo());
+        assert_eq!(history.latest_value(), 1);
+    }
+
+    #[test]
+    fn snapshot_edits_are_recorded_on_commit() {
+        let _exclusive = exclusive();
+        let history = HistoryState::new(0);
+        with_mutable_snapshot(|| {
+            history.send_value(1);
+            history.handle().send_update(|value| *value += 1);
+            assert!(history.can_undo());
+            std::thread::scope(|scope| {
+                scope.spawn(|| assert!(!history.can_undo()));
+            });
+        });
+
+        assert_eq!(history.latest_value(), 2);
+        assert!(history.undo());
+        assert_eq!(history.latest_value(), 1);
+    }
+
+    #[test]
+    fn snapshot_undo_moves_the_history_on_commit() {
+        let _exclusive = exclusive();
+        let history = HistoryState::new(0);
+        for value in 1..=3 {
+            history.send_value(value);
+        }
+
+        with_mutable_snapshot(|| {
+            assert!(history.undo());
+            assert!(history.undo());
+            assert_eq!(history.latest_value(), 1);
+            assert!(history.can_redo());
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    assert_eq!(history.latest_value(), 3);
+                    assert!(!history.can_redo());
+                });
+            });
+        });
+
+        assert_eq!(history.latest_value(), 1);
+        assert!(history.redo());
+        assert_eq!(history.latest_value(), 2);
+    }
+
+    #[test]
+    fn ref_history_records_edits_in_place() {
+        let mut text = RefHistoryState::new(String::new());
+        text.send_modify(|text| text.push('a'));
+        text.latch_value();
+        text.value_mut().push('b');
+        text.latch_value();
+        text.value_mut_untracked().push('c');
+        text.mark_changed();
+
+        assert!(text.undo());
+        assert_eq!(*text.latest_value().lock().unwrap(), "ab");
+        assert_eq!(*text.value(), "abc");
+        assert!(text.undo());
+        assert!(text.undo());
+        assert!(!text.undo());
+        assert_eq!(*text.latest_value().lock().unwrap(), "");
+
+        assert!(text.handle().redo());
+        text.latch_value();
+        assert_eq!(*text.value(), "a");
+    }
+}
@@ -12,7 +12,7 @@ where
         let handle = (*state.get()).handle();
         ui.use_effect((val, delay), |(val, delay)| {
             Box::pin(async move {
-                tokio::time::sleep(delay).await;
+                crate::time::sleep(delay).await;
                 handle.send_update(|v| *v = val);
             })
         });
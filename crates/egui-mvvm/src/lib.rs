@@ -1,10 +1,33 @@
+use crate::notify::ChangeNotifier;
+use crate::view_model::LifecycleEvent;
 use std::pin::Pin;
 
+pub mod app;
+pub mod async_state;
+pub mod derived_state;
+pub mod devtools;
+mod diff_log;
+pub mod executor;
+pub mod history_state;
 pub mod hooks;
+pub mod list_state;
+pub mod map_state;
+pub mod notify;
+#[cfg(feature = "persistence")]
+pub mod persistence;
+pub mod query;
+#[cfg(feature = "recorder")]
+pub mod recorder;
 pub mod ref_state;
+pub mod snapshot;
 pub mod task_pool;
+#[cfg(any(feature = "testing", all(test, not(target_arch = "wasm32"))))]
+pub mod testing;
+mod time;
+mod trace;
 pub mod val_state;
 pub mod view_model;
+pub mod view_model_list;
 
 pub use egui_mvvm_macro::view_model;
 
@@ -12,7 +35,132 @@ pub trait ChangeDetector: Sync + Send + 'static {
     fn wait_for_change(&self) -> Pin<Box<dyn Future<Output = Option<()>> + Send + 'static>>;
 }
 
+/// Completes with the result of the first of `changes` to complete, never if there are none.
+pub fn wait_for_any(
+    changes: Vec<Pin<Box<dyn Future<Output = Option<()>> + Send + 'static>>>,
+) -> Pin<Box<dyn Future<Output = Option<()>> + Send + 'static>> {
+    Box::pin(async move {
+        if changes.is_empty() {
+            std::future::pending().await
+        } else {
+            futures::future::select_all(changes).await.0
+        }
+    })
+}
+
+/// A field of a `view_model!` struct: a state primitive, a nested ViewModel, or a `Vec` or `Option` of them.
 pub trait Stateful {
     type ChangeDetector: ChangeDetector;
     type Handle;
+
+    /// Latches the latest value, called by `view_model!` at the start of every frame.
+    fn latch(&mut self);
+
+    /// The handle the field is reached through from the ViewModel's model, in spawned tasks.
+    fn handle(&self) -> Self::Handle;
+
+    fn change_detector(&self) -> Self::ChangeDetector;
+
+    /// Names the state in the events of the `tracing` feature, set by `view_model!` to `Type.field`.
+    fn set_name(&self, _name: &'static str) {}
+
+    /// Signals `notifier` after every published value, set by `view_model!` when the ViewModel is registered.
+    fn connect(&self, _notifier: &ChangeNotifier) {}
+
+    /// Returns true if the latest latch picked up a new value, read by the change set of
+    /// [`ViewModels::latch_values`](crate::view_model::ViewModels::latch_values).
+    fn changed_this_frame(&self) -> bool {
+        false
+    }
+
+    /// Runs a lifecycle callback on the ViewModels nested in the field, called by `view_model!` after the
+    /// callback of the ViewModel holding it.
+    fn run_lifecycle(&mut self, _event: LifecycleEvent) {}
+}
+
+impl<D: ChangeDetector> ChangeDetector for Vec<D> {
+    fn wait_for_change(&self) -> Pin<Box<dyn Future<Output = Option<()>> + Send + 'static>> {
+        wait_for_any(self.iter().map(D::wait_for_change).collect())
+    }
+}
+
+impl<D: ChangeDetector> ChangeDetector for Option<D> {
+    fn wait_for_change(&self) -> Pin<Box<dyn Future<Output = Option<()>> + Send + 'static>> {
+        wait_for_any(self.iter().map(D::wait_for_change).collect())
+    }
+}
+
+/// A list of nested ViewModels or states, fixed once the ViewModel is constructed.
+///
+/// Pushing or removing items is not a change: it is not published, so it neither repaints nor shows up in the
+/// change set. Spawned tasks and change detectors only see the items present when the model or detector is
+/// made. ViewModels that come and go belong in a [`ViewModelList`](crate::view_model_list::ViewModelList),
+/// plain values in a [`ListState`](crate::list_state::ListState).
+impl<T: Stateful> Stateful for Vec<T> {
+    type ChangeDetector = Vec<T::ChangeDetector>;
+    type Handle = Vec<T::Handle>;
+
+    fn latch(&mut self) {
+        self.iter_mut().for_each(T::latch);
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.iter().map(T::handle).collect()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.iter().map(T::change_detector).collect()
+    }
+
+    fn set_name(&self, name: &'static str) {
+        self.iter().for_each(|item| item.set_name(name));
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        self.iter().for_each(|item| item.connect(notifier));
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        self.iter().any(T::changed_this_frame)
+    }
+
+    fn run_lifecycle(&mut self, event: LifecycleEvent) {
+        self.iter_mut().for_each(|item| item.run_lifecycle(event));
+    }
+}
+
+/// An optional nested ViewModel or state, fixed once the ViewModel is constructed like the items of a
+/// `Vec`: setting or taking it is not published, and tasks only see the value present when the model is made.
+/// A child that comes and goes is a [`ViewModelList`](crate::view_model_list::ViewModelList) of at most one.
+impl<T: Stateful> Stateful for Option<T> {
+    type ChangeDetector = Option<T::ChangeDetector>;
+    type Handle = Option<T::Handle>;
+
+    fn latch(&mut self) {
+        self.iter_mut().for_each(T::latch);
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.as_ref().map(T::handle)
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.as_ref().map(T::change_detector)
+    }
+
+    fn set_name(&self, name: &'static str) {
+        self.iter().for_each(|item| item.set_name(name));
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        self.iter().for_each(|item| item.connect(notifier));
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        self.as_ref().is_some_and(T::changed_this_frame)
+    }
+
+    fn run_lifecycle(&mut self, event: LifecycleEvent) {
+        self.iter_mut().for_each(|item| item.run_lifecycle(event));
+    }
 }
@@ -0,0 +1,667 @@
+use crate::diff_log::DiffLog;
+use crate::notify::{ChangeNotifier, StateSignal};
+#[cfg(feature = "recorder")]
+use crate::recorder::{FieldRecorder, Value};
+use crate::snapshot::in_mutable_snapshot;
+use crate::view_model::{ViewModel, ViewModelLike};
+use crate::{ChangeDetector, Stateful};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// A change made to a [`ListState`], with indices as they were when the change was made.
+///
+/// Applying the diffs of a frame in order to the previous frame's list gives the current one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListDiff {
+    Inserted {
+        index: usize,
+    },
+    Removed {
+        index: usize,
+    },
+    Updated {
+        index: usize,
+    },
+    Moved {
+        from: usize,
+        to: usize,
+    },
+    /// The whole list was replaced, or too many changes were made since the last latch.
+    Reset,
+}
+
+/// Identifies an item of a [`ListState`] for as long as it stays in the list, e.g. to salt its row's `egui::Id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ListItemId(u64);
+
+struct ListEntry<T> {
+    id: ListItemId,
+    /// The log version of the last insert or update of the item.
+    version: u64,
+    value: Arc<T>,
+}
+
+impl<T> Clone for ListEntry<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            version: self.version,
+            value: self.value.clone(),
+        }
+    }
+}
+
+struct ListLog<T> {
+    entries: Vec<ListEntry<T>>,
+    diffs: DiffLog<ListDiff>,
+    next_id: u64,
+}
+
+impl<T> Clone for ListLog<T> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+            diffs: self.diffs.clone(),
+            next_id: self.next_id,
+        }
+    }
+}
+
+impl<T: Clone> ListLog<T> {
+    fn new(values: Vec<T>) -> Self {
+        let mut log = Self {
+            entries: Vec::new(),
+            diffs: DiffLog::default(),
+            next_id: 0,
+        };
+        log.replace(values);
+        log
+    }
+
+    fn entry(&mut self, value: T, version: u64) -> ListEntry<T> {
+        self.next_id += 1;
+        ListEntry {
+            id: ListItemId(self.next_id),
+            version,
+            value: Arc::new(value),
+        }
+    }
+
+    fn replace(&mut self, values: Vec<T>) {
+        let version = self.diffs.push(ListDiff::Reset);
+        self.entries = values
+            .into_iter()
+            .map(|value| self.entry(value, version))
+            .collect();
+    }
+
+    fn insert(&mut self, index: usize, value: T) -> bool {
+        if index > self.entries.len() {
+            return false;
+        }
+
+        let version = self.diffs.push(ListDiff::Inserted { index });
+        let entry = self.entry(value, version);
+        self.entries.insert(index, entry);
+        true
+    }
+
+    fn remove(&mut self, index: usize) -> Option<T> {
+        if index >= self.entries.len() {
+            return None;
+        }
+
+        self.diffs.push(ListDiff::Removed { index });
+        Some(Arc::unwrap_or_clone(self.entries.remove(index).value))
+    }
+
+    fn update(&mut self, index: usize, f: impl FnOnce(&mut T)) -> bool {
+        if index >= self.entries.len() {
+            return false;
+        }
+
+        let version = self.diffs.push(ListDiff::Updated { index });
+        let entry = &mut self.entries[index];
+        entry.version = version;
+        f(Arc::make_mut(&mut entry.value));
+        true
+    }
+
+    fn move_item(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.entries.len() || to >= self.entries.len() {
+            return false;
+        }
+
+        self.diffs.push(ListDiff::Moved { from, to });
+        let entry = self.entries.remove(from);
+        self.entries.insert(to, entry);
+        true
+    }
+
+    fn clear(&mut self) -> bool {
+        if self.entries.is_empty() {
+            return false;
+        }
+
+        self.replace(Vec::new());
+        true
+    }
+}
+
+/// A list whose changes are published one operation at a time.
+///
+/// Unlike a `RefState<Vec<T>>`, the view can tell which rows changed: [`ListState::diff`] lists the
+/// operations latched this frame and [`ListState::is_changed`] tells if a row was inserted or updated.
+/// Items are kept behind an [`Arc`], so latching a changed list clones pointers rather than values.
+pub struct ListState<T> {
+    latched: Vec<ListEntry<T>>,
+    diff: Vec<ListDiff>,
+    /// The log version latched before the current one, items changed after it are new this frame.
+    previous_version: u64,
+    version: u64,
+    tx: watch::Sender<ListLog<T>>,
+    rx: watch::Receiver<ListLog<T>>,
+    signal: StateSignal,
+}
+
+impl<T> Clone for ListState<T> {
+    fn clone(&self) -> Self {
+        Self {
+            latched: self.latched.clone(),
+            diff: self.diff.clone(),
+            previous_version: self.previous_version,
+            version: self.version,
+            tx: self.tx.clone(),
+            rx: self.rx.clone(),
+            signal: self.signal.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Default for ListState<T> {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> ListState<T> {
+    pub fn new(values: Vec<T>) -> Self {
+        let mut log = ListLog::new(values);
+        let version = log.diffs.version();
+        log.diffs.trim(version);
+
+        let latched = log.entries.clone();
+        let (tx, rx) = watch::channel(log);
+        Self {
+            latched,
+            diff: Vec::new(),
+            previous_version: version,
+            version,
+            tx,
+            rx,
+            signal: StateSignal::default(),
+        }
+    }
+
+    /// Latches the latest list and the operations that led to it.
+    ///
+    /// Operations are dropped from the log once latched, so another clone of this state latching later
+    /// sees a [`ListDiff::Reset`] instead.
+    pub fn latch_value(&mut self) {
+        self.previous_version = self.version;
+
+        if !self.rx.has_changed().unwrap_or(true) {
+            self.diff.clear();
+            return;
+        }
+
+        let log = self.rx.borrow_and_update();
+        self.diff = log
+            .diffs
+            .since(self.version)
+            .unwrap_or_else(|| vec![ListDiff::Reset]);
+        self.version = log.diffs.version();
+        self.latched = log.entries.clone();
+        drop(log);
+
+        let version = self.version;
+        self.tx.send_if_modified(|log| {
+            log.diffs.trim(version);
+            false
+        });
+    }
+
+    /// Latches `values` without publishing them, or the latest published list if `None`, for time travel.
+    ///
+    /// Reported as a [`ListDiff::Reset`], like the next latch.
+    #[cfg(feature = "recorder")]
+    pub(crate) fn latch_replayed(&mut self, values: Option<Vec<T>>) {
+        self.previous_version = 0;
+        self.diff = vec![ListDiff::Reset];
+        match values {
+            Some(values) => {
+                self.latched = ListLog::new(values).entries;
+                // Older than any log, so the next latch resets the list.
+                self.version = 0;
+            }
+            None => {
+                let log = self.rx.borrow_and_update();
+                self.version = log.diffs.version();
+                self.latched = log.entries.clone();
+            }
+        }
+    }
+
+    /// Returns true if a list was published since the latest latch.
+    pub(crate) fn has_changed(&self) -> bool {
+        self.rx.has_changed().unwrap_or(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.latched.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latched.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.latched.get(index).map(|entry| &*entry.value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.latched.iter().map(|entry| &*entry.value)
+    }
+
+    pub fn item_id(&self, index: usize) -> Option<ListItemId> {
+        self.latched.get(index).map(|entry| entry.id)
+    }
+
+    /// Returns true if the item at `index` was inserted or updated in the latest latch.
+    pub fn is_changed(&self, index: usize) -> bool {
+        self.latched
+            .get(index)
+            .is_some_and(|entry| entry.version > self.previous_version)
+    }
+
+    /// Returns true if the latest latch applied any operation, see [`ListState::diff`].
+    pub fn changed_this_frame(&self) -> bool {
+        !self.diff.is_empty()
+    }
+
+    /// The operations applied by the latest latch, empty if the list did not change.
+    pub fn diff(&self) -> &[ListDiff] {
+        &self.diff
+    }
+
+    pub fn latest_value(&self) -> Vec<T> {
+        latest_value(&self.tx, &self.signal)
+    }
+
+    pub fn send_value(&self, values: Vec<T>) {
+        send_value(&self.tx, &self.signal, values);
+    }
+
+    pub fn push(&self, value: T) {
+        push(&self.tx, &self.signal, value);
+    }
+
+    /// Inserts `value` at `index` of the latest list, returns false if `index` is out of bounds.
+    pub fn insert(&self, index: usize, value: T) -> bool {
+        insert(&self.tx, &self.signal, index, value)
+    }
+
+    pub fn remove(&self, index: usize) -> Option<T> {
+        remove(&self.tx, &self.signal, index)
+    }
+
+    /// Modifies the item at `index` of the latest list, returns false if `index` is out of bounds.
+    pub fn update(&self, index: usize, f: impl FnOnce(&mut T)) -> bool {
+        update(&self.tx, &self.signal, index, f)
+    }
+
+    pub fn move_item(&self, from: usize, to: usize) -> bool {
+        move_item(&self.tx, &self.signal, from, to)
+    }
+
+    pub fn clear(&self) {
+        clear(&self.tx, &self.signal);
+    }
+
+    /// Records every list published to the state, see
+    /// [`Persistable::connect_recorder`](crate::persistence::Persistable::connect_recorder).
+    #[cfg(feature = "recorder")]
+    pub(crate) fn record_with(
+        &self,
+        recorder: FieldRecorder,
+        serialize: impl Fn(Vec<&T>) -> Option<Value> + Send + Sync + 'static,
+    ) {
+        self.signal.record(recorder, move |log: &ListLog<T>| {
+            serialize(log.entries.iter().map(|entry| &*entry.value).collect())
+        });
+    }
+
+    pub fn change_detector(&self) -> ListStateChangeDetector<T> {
+        ListStateChangeDetector {
+            rx: self.tx.subscribe(),
+        }
+    }
+
+    pub fn handle(&self) -> ListStateHandle<T> {
+        ListStateHandle {
+            tx: self.tx.clone(),
+            signal: self.signal.clone(),
+        }
+    }
+}
+
+fn latest_value<T: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<ListLog<T>>,
+    signal: &StateSignal,
+) -> Vec<T> {
+    signal.read(tx, |log| {
+        log.entries
+            .iter()
+            .map(|entry| (*entry.value).clone())
+            .collect()
+    })
+}
+
+fn send_value<T: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<ListLog<T>>,
+    signal: &StateSignal,
+    values: Vec<T>,
+) {
+    signal.publish(tx, "send_value", move |log| {
+        log.replace(values.clone());
+        Some(())
+    });
+}
+
+fn push<T: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<ListLog<T>>,
+    signal: &StateSignal,
+    value: T,
+) {
+    signal.publish(tx, "push", move |log| {
+        log.insert(log.entries.len(), value.clone());
+        Some(())
+    });
+}
+
+fn insert<T: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<ListLog<T>>,
+    signal: &StateSignal,
+    index: usize,
+    value: T,
+) -> bool {
+    signal
+        .publish(tx, "insert", move |log| {
+            log.insert(index, value.clone()).then_some(())
+        })
+        .is_some()
+}
+
+fn remove<T: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<ListLog<T>>,
+    signal: &StateSignal,
+    index: usize,
+) -> Option<T> {
+    signal.publish(tx, "remove", move |log| log.remove(index))
+}
+
+fn update<T: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<ListLog<T>>,
+    signal: &StateSignal,
+    index: usize,
+    f: impl FnOnce(&mut T),
+) -> bool {
+    if !in_mutable_snapshot() {
+        return signal.publish_with(tx, "update", |log| log.update(index, f));
+    }
+
+    // `f` only runs once, the updated item is written again on commit.
+    let Some(mut item) = signal.read(tx, |log| log.entries.get(index).map(|e| (*e.value).clone()))
+    else {
+        return false;
+    };
+    f(&mut item);
+    signal
+        .publish(tx, "update", move |log| {
+            log.update(index, |latest| *latest = item.clone())
+                .then_some(())
+        })
+        .is_some()
+}
+
+fn move_item<T: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<ListLog<T>>,
+    signal: &StateSignal,
+    from: usize,
+    to: usize,
+) -> bool {
+    signal
+        .publish(tx, "move_item", move |log| {
+            log.move_item(from, to).then_some(())
+        })
+        .is_some()
+}
+
+fn clear<T: Clone + Send + Sync + 'static>(tx: &watch::Sender<ListLog<T>>, signal: &StateSignal) {
+    signal.publish(tx, "clear", |log| log.clear().then_some(()));
+}
+
+pub struct ListStateChangeDetector<T> {
+    rx: watch::Receiver<ListLog<T>>,
+}
+
+impl<T> Clone for ListStateChangeDetector<T> {
+    fn clone(&self) -> Self {
+        Self {
+            rx: self.rx.clone(),
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> ChangeDetector for ListStateChangeDetector<T> {
+    fn wait_for_change(&self) -> Pin<Box<dyn Future<Output = Option<()>> + Send + 'static>> {
+        let mut this = self.clone();
+        Box::pin(async move { this.rx.changed().await.ok() })
+    }
+}
+
+/// Publishes changes to a [`ListState`] from tasks, indices refer to the latest list.
+pub struct ListStateHandle<T> {
+    tx: watch::Sender<ListLog<T>>,
+    signal: StateSignal,
+}
+
+impl<T> Clone for ListStateHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            signal: self.signal.clone(),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> ListStateHandle<T> {
+    pub fn latest_value(&self) -> Vec<T> {
+        latest_value(&self.tx, &self.signal)
+    }
+
+    pub fn latest_len(&self) -> usize {
+        self.signal.read(&self.tx, |log| log.entries.len())
+    }
+
+    pub fn send_value(&self, values: Vec<T>) {
+        send_value(&self.tx, &self.signal, values);
+    }
+
+    pub fn push(&self, value: T) {
+        push(&self.tx, &self.signal, value);
+    }
+
+    pub fn insert(&self, index: usize, value: T) -> bool {
+        insert(&self.tx, &self.signal, index, value)
+    }
+
+    pub fn remove(&self, index: usize) -> Option<T> {
+        remove(&self.tx, &self.signal, index)
+    }
+
+    pub fn update(&self, index: usize, f: impl FnOnce(&mut T)) -> bool {
+        update(&self.tx, &self.signal, index, f)
+    }
+
+    pub fn move_item(&self, from: usize, to: usize) -> bool {
+        move_item(&self.tx, &self.signal, from, to)
+    }
+
+    pub fn clear(&self) {
+        clear(&self.tx, &self.signal);
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Stateful for ListState<T> {
+    type ChangeDetector = ListStateChangeDetector<T>;
+    type Handle = ListStateHandle<T>;
+
+    fn latch(&mut self) {
+        self.latch_value()
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+
+    fn set_name(&self, name: &'static str) {
+        self.signal.set_name(name);
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        self.signal.connect(notifier);
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        !self.diff.is_empty()
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> ViewModelLike for ListState<T> {
+    fn latch_state(&mut self) {
+        self.latch_value()
+    }
+
+    fn change_detector_boxed(&self) -> Box<dyn ChangeDetector> {
+        Box::new(self.change_detector())
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(self, notifier);
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> ViewModel for ListState<T> {
+    type Model = ListStateHandle<T>;
+    type ChangeDetector = ListStateChangeDetector<T>;
+
+    fn make_model(&self) -> Self::Model {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> From<Vec<T>> for ListState<T> {
+    fn from(values: Vec<T>) -> Self {
+        ListState::new(values)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latch_lists_the_operations_in_order() {
+        let mut list = ListState::new(vec!["a", "b", "c"]);
+        let first = list.item_id(0);
+
+        list.push("d");
+        list.remove(1);
+        list.update(0, |item| *item = "A");
+        list.move_item(0, 2);
+        assert_eq!(list.iter().copied().collect::<Vec<_>>(), ["a", "b", "c"]);
+
+        list.latch_value();
+        assert_eq!(
+            list.diff(),
+            [
+                ListDiff::Inserted { index: 3 },
+                ListDiff::Removed { index: 1 },
+                ListDiff::Updated { index: 0 },
+                ListDiff::Moved { from: 0, to: 2 },
+            ]
+        );
+        assert_eq!(list.iter().copied().collect::<Vec<_>>(), ["c", "d", "A"]);
+        assert_eq!(list.item_id(2), first);
+        assert!(!list.is_changed(0));
+        assert!(list.is_changed(1));
+        assert!(list.is_changed(2));
+
+        list.latch_value();
+        assert!(list.diff().is_empty());
+        assert!(!list.is_changed(1));
+    }
+
+    #[test]
+    fn out_of_bounds_operations_are_not_published() {
+        let mut list = ListState::new(vec![1]);
+
+        assert!(!list.insert(2, 3));
+        assert_eq!(list.remove(1), None);
+        assert!(!list.update(1, |item| *item += 1));
+        assert!(!list.move_item(0, 1));
+
+        list.latch_value();
+        assert!(!list.changed_this_frame());
+    }
+
+    #[test]
+    fn replaced_list_is_a_reset() {
+        let mut list = ListState::new(vec![1, 2]);
+        let handle = list.handle();
+
+        handle.push(3);
+        handle.send_value(vec![4]);
+        list.latch_value();
+
+        assert_eq!(
+            list.diff(),
+            [ListDiff::Inserted { index: 2 }, ListDiff::Reset]
+        );
+        assert!(list.is_changed(0));
+    }
+
+    #[test]
+    fn clone_latching_late_sees_a_reset() {
+        let mut list = ListState::new(vec![1]);
+        let mut late = list.clone();
+
+        list.push(2);
+        list.latch_value();
+        late.latch_value();
+
+        assert_eq!(list.diff(), [ListDiff::Inserted { index: 1 }]);
+        assert_eq!(late.diff(), [ListDiff::Reset]);
+        assert_eq!(late.iter().copied().collect::<Vec<_>>(), [1, 2]);
+    }
+}
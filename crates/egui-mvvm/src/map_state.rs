@@ -0,0 +1,611 @@
+use crate::diff_log::DiffLog;
+use crate::notify::{ChangeNotifier, StateSignal};
+#[cfg(feature = "recorder")]
+use crate::recorder::{FieldRecorder, Value};
+use crate::snapshot::in_mutable_snapshot;
+use crate::view_model::{ViewModel, ViewModelLike};
+use crate::{ChangeDetector, Stateful};
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// A change made to a [`MapState`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapDiff<K> {
+    Inserted(K),
+    Removed(K),
+    Updated(K),
+    /// The whole map was replaced, or too many changes were made since the last latch.
+    Reset,
+}
+
+struct MapEntry<V> {
+    /// The log version of the last insert or update of the value.
+    version: u64,
+    value: Arc<V>,
+}
+
+impl<V> Clone for MapEntry<V> {
+    fn clone(&self) -> Self {
+        Self {
+            version: self.version,
+            value: self.value.clone(),
+        }
+    }
+}
+
+struct MapLog<K, V> {
+    entries: BTreeMap<K, MapEntry<V>>,
+    diffs: DiffLog<MapDiff<K>>,
+}
+
+impl<K: Clone, V> Clone for MapLog<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+            diffs: self.diffs.clone(),
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> MapLog<K, V> {
+    fn new(values: BTreeMap<K, V>) -> Self {
+        let mut log = Self {
+            entries: BTreeMap::new(),
+            diffs: DiffLog::default(),
+        };
+        log.replace(values);
+        log
+    }
+
+    fn replace(&mut self, values: BTreeMap<K, V>) {
+        let version = self.diffs.push(MapDiff::Reset);
+        self.entries = values
+            .into_iter()
+            .map(|(key, value)| {
+                let value = Arc::new(value);
+                (key, MapEntry { version, value })
+            })
+            .collect();
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let replaced = self.entries.contains_key(&key);
+        let version = self.diffs.push(if replaced {
+            MapDiff::Updated(key.clone())
+        } else {
+            MapDiff::Inserted(key.clone())
+        });
+
+        let value = Arc::new(value);
+        self.entries
+            .insert(key, MapEntry { version, value })
+            .map(|entry| Arc::unwrap_or_clone(entry.value))
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.diffs.push(MapDiff::Removed(key.clone()));
+        Some(Arc::unwrap_or_clone(entry.value))
+    }
+
+    fn update(&mut self, key: &K, f: impl FnOnce(&mut V)) -> bool {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return false;
+        };
+
+        entry.version = self.diffs.push(MapDiff::Updated(key.clone()));
+        f(Arc::make_mut(&mut entry.value));
+        true
+    }
+
+    fn clear(&mut self) -> bool {
+        if self.entries.is_empty() {
+            return false;
+        }
+
+        self.replace(BTreeMap::new());
+        true
+    }
+}
+
+/// A sorted map whose changes are published one key at a time.
+///
+/// The view can tell which keys changed: [`MapState::diff`] lists the operations latched this frame and
+/// [`MapState::is_changed`] tells if a value was inserted or updated.
+/// Values are kept behind an [`Arc`], so latching a changed map clones pointers rather than values.
+pub struct MapState<K, V> {
+    latched: BTreeMap<K, MapEntry<V>>,
+    diff: Vec<MapDiff<K>>,
+    /// The log version latched before the current one, values changed after it are new this frame.
+    previous_version: u64,
+    version: u64,
+    tx: watch::Sender<MapLog<K, V>>,
+    rx: watch::Receiver<MapLog<K, V>>,
+    signal: StateSignal,
+}
+
+impl<K: Clone, V> Clone for MapState<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            latched: self.latched.clone(),
+            diff: self.diff.clone(),
+            previous_version: self.previous_version,
+            version: self.version,
+            tx: self.tx.clone(),
+            rx: self.rx.clone(),
+            signal: self.signal.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for MapState<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new(BTreeMap::new())
+    }
+}
+
+impl<K, V> MapState<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new(values: BTreeMap<K, V>) -> Self {
+        let mut log = MapLog::new(values);
+        let version = log.diffs.version();
+        log.diffs.trim(version);
+
+        let latched = log.entries.clone();
+        let (tx, rx) = watch::channel(log);
+        Self {
+            latched,
+            diff: Vec::new(),
+            previous_version: version,
+            version,
+            tx,
+            rx,
+            signal: StateSignal::default(),
+        }
+    }
+
+    /// Latches the latest map and the operations that led to it.
+    ///
+    /// Operations are dropped from the log once latched, so another clone of this state latching later
+    /// sees a [`MapDiff::Reset`] instead.
+    pub fn latch_value(&mut self) {
+        self.previous_version = self.version;
+
+        if !self.rx.has_changed().unwrap_or(true) {
+            self.diff.clear();
+            return;
+        }
+
+        let log = self.rx.borrow_and_update();
+        self.diff = log
+            .diffs
+            .since(self.version)
+            .unwrap_or_else(|| vec![MapDiff::Reset]);
+        self.version = log.diffs.version();
+        self.latched = log.entries.clone();
+        drop(log);
+
+        let version = self.version;
+        self.tx.send_if_modified(|log| {
+            log.diffs.trim(version);
+            false
+        });
+    }
+
+    /// Latches `values` without publishing them, or the latest published map if `None`, for time travel.
+    ///
+    /// Reported as a [`MapDiff::Reset`], like the next latch.
+    #[cfg(feature = "recorder")]
+    pub(crate) fn latch_replayed(&mut self, values: Option<BTreeMap<K, V>>) {
+        self.previous_version = 0;
+        self.diff = vec![MapDiff::Reset];
+        match values {
+            Some(values) => {
+                self.latched = MapLog::new(values).entries;
+                // Older than any log, so the next latch resets the map.
+                self.version = 0;
+            }
+            None => {
+                let log = self.rx.borrow_and_update();
+                self.version = log.diffs.version();
+                self.latched = log.entries.clone();
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.latched.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latched.is_empty()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.latched.get(key).map(|entry| &*entry.value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.latched.contains_key(key)
+    }
+
+    /// Iterates over the latched entries, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.latched.iter().map(|(key, entry)| (key, &*entry.value))
+    }
+
+    /// Returns true if the value of `key` was inserted or updated in the latest latch.
+    pub fn is_changed(&self, key: &K) -> bool {
+        self.latched
+            .get(key)
+            .is_some_and(|entry| entry.version > self.previous_version)
+    }
+
+    /// Returns true if the latest latch applied any operation, see [`MapState::diff`].
+    pub fn changed_this_frame(&self) -> bool {
+        !self.diff.is_empty()
+    }
+
+    /// The operations applied by the latest latch, empty if the map did not change.
+    pub fn diff(&self) -> &[MapDiff<K>] {
+        &self.diff
+    }
+
+    pub fn latest_value(&self) -> BTreeMap<K, V> {
+        latest_value(&self.tx, &self.signal)
+    }
+
+    pub fn send_value(&self, values: BTreeMap<K, V>) {
+        send_value(&self.tx, &self.signal, values);
+    }
+
+    /// Inserts `value` in the latest map, returning the value it replaced.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        insert(&self.tx, &self.signal, key, value)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        remove(&self.tx, &self.signal, key)
+    }
+
+    /// Modifies the value of `key` in the latest map, returns false if there is none.
+    pub fn update(&self, key: &K, f: impl FnOnce(&mut V)) -> bool {
+        update(&self.tx, &self.signal, key, f)
+    }
+
+    pub fn clear(&self) {
+        clear(&self.tx, &self.signal);
+    }
+
+    /// Records every map published to the state, see
+    /// [`Persistable::connect_recorder`](crate::persistence::Persistable::connect_recorder).
+    #[cfg(feature = "recorder")]
+    pub(crate) fn record_with(
+        &self,
+        recorder: FieldRecorder,
+        serialize: impl Fn(Vec<(&K, &V)>) -> Option<Value> + Send + Sync + 'static,
+    ) {
+        self.signal.record(recorder, move |log: &MapLog<K, V>| {
+            serialize(
+                log.entries
+                    .iter()
+                    .map(|(key, entry)| (key, &*entry.value))
+                    .collect(),
+            )
+        });
+    }
+
+    pub fn change_detector(&self) -> MapStateChangeDetector<K, V> {
+        MapStateChangeDetector {
+            rx: self.tx.subscribe(),
+        }
+    }
+
+    pub fn handle(&self) -> MapStateHandle<K, V> {
+        MapStateHandle {
+            tx: self.tx.clone(),
+            signal: self.signal.clone(),
+        }
+    }
+}
+
+fn latest_value<K: Ord + Clone + Send + Sync + 'static, V: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<MapLog<K, V>>,
+    signal: &StateSignal,
+) -> BTreeMap<K, V> {
+    signal.read(tx, |log| {
+        log.entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), (*entry.value).clone()))
+            .collect()
+    })
+}
+
+fn send_value<K: Ord + Clone + Send + Sync + 'static, V: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<MapLog<K, V>>,
+    signal: &StateSignal,
+    values: BTreeMap<K, V>,
+) {
+    signal.publish(tx, "send_value", move |log| {
+        log.replace(values.clone());
+        Some(())
+    });
+}
+
+fn insert<K: Ord + Clone + Send + Sync + 'static, V: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<MapLog<K, V>>,
+    signal: &StateSignal,
+    key: K,
+    value: V,
+) -> Option<V> {
+    signal
+        .publish(tx, "insert", move |log| {
+            Some(log.insert(key.clone(), value.clone()))
+        })
+        .flatten()
+}
+
+fn remove<K: Ord + Clone + Send + Sync + 'static, V: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<MapLog<K, V>>,
+    signal: &StateSignal,
+    key: &K,
+) -> Option<V> {
+    let key = key.clone();
+    signal.publish(tx, "remove", move |log| log.remove(&key))
+}
+
+fn update<K: Ord + Clone + Send + Sync + 'static, V: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<MapLog<K, V>>,
+    signal: &StateSignal,
+    key: &K,
+    f: impl FnOnce(&mut V),
+) -> bool {
+    if !in_mutable_snapshot() {
+        return signal.publish_with(tx, "update", |log| log.update(key, f));
+    }
+
+    // `f` only runs once, the updated value is written again on commit.
+    let Some(mut value) = signal.read(tx, |log| log.entries.get(key).map(|e| (*e.value).clone()))
+    else {
+        return false;
+    };
+    f(&mut value);
+    let key = key.clone();
+    signal
+        .publish(tx, "update", move |log| {
+            log.update(&key, |latest| *latest = value.clone())
+                .then_some(())
+        })
+        .is_some()
+}
+
+fn clear<K: Ord + Clone + Send + Sync + 'static, V: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<MapLog<K, V>>,
+    signal: &StateSignal,
+) {
+    signal.publish(tx, "clear", |log| log.clear().then_some(()));
+}
+
+pub struct MapStateChangeDetector<K, V> {
+    rx: watch::Receiver<MapLog<K, V>>,
+}
+
+impl<K, V> Clone for MapStateChangeDetector<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            rx: self.rx.clone(),
+        }
+    }
+}
+
+impl<K: Send + Sync + 'static, V: Send + Sync + 'static> ChangeDetector
+    for MapStateChangeDetector<K, V>
+{
+    fn wait_for_change(&self) -> Pin<Box<dyn Future<Output = Option<()>> + Send + 'static>> {
+        let mut this = self.clone();
+        Box::pin(async move { this.rx.changed().await.ok() })
+    }
+}
+
+/// Publishes changes to a [`MapState`] from tasks.
+pub struct MapStateHandle<K, V> {
+    tx: watch::Sender<MapLog<K, V>>,
+    signal: StateSignal,
+}
+
+impl<K, V> Clone for MapStateHandle<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            signal: self.signal.clone(),
+        }
+    }
+}
+
+impl<K, V> MapStateHandle<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn latest_value(&self) -> BTreeMap<K, V> {
+        latest_value(&self.tx, &self.signal)
+    }
+
+    pub fn latest_get(&self, key: &K) -> Option<V> {
+        self.signal.read(&self.tx, |log| {
+            log.entries.get(key).map(|e| (*e.value).clone())
+        })
+    }
+
+    pub fn send_value(&self, values: BTreeMap<K, V>) {
+        send_value(&self.tx, &self.signal, values);
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        insert(&self.tx, &self.signal, key, value)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        remove(&self.tx, &self.signal, key)
+    }
+
+    pub fn update(&self, key: &K, f: impl FnOnce(&mut V)) -> bool {
+        update(&self.tx, &self.signal, key, f)
+    }
+
+    pub fn clear(&self) {
+        clear(&self.tx, &self.signal);
+    }
+}
+
+impl<K, V> Stateful for MapState<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    type ChangeDetector = MapStateChangeDetector<K, V>;
+    type Handle = MapStateHandle<K, V>;
+
+    fn latch(&mut self) {
+        self.latch_value()
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+
+    fn set_name(&self, name: &'static str) {
+        self.signal.set_name(name);
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        self.signal.connect(notifier);
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        !self.diff.is_empty()
+    }
+}
+
+impl<K, V> ViewModelLike for MapState<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn latch_state(&mut self) {
+        self.latch_value()
+    }
+
+    fn change_detector_boxed(&self) -> Box<dyn ChangeDetector> {
+        Box::new(self.change_detector())
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(self, notifier);
+    }
+}
+
+impl<K, V> ViewModel for MapState<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    type Model = MapStateHandle<K, V>;
+    type ChangeDetector = MapStateChangeDetector<K, V>;
+
+    fn make_model(&self) -> Self::Model {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+}
+
+impl<K, V> From<BTreeMap<K, V>> for MapState<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn from(values: BTreeMap<K, V>) -> Self {
+        MapState::new(values)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latch_lists_the_operations_in_order() {
+        let mut map = MapState::new(BTreeMap::from([("a", 1), ("b", 2)]));
+
+        assert_eq!(map.insert("c", 3), None);
+        assert_eq!(map.insert("a", 10), Some(1));
+        assert_eq!(map.remove(&"b"), Some(2));
+        assert!(map.update(&"c", |value| *value += 1));
+        assert!(!map.update(&"b", |value| *value += 1));
+        assert_eq!(map.get(&"b"), Some(&2));
+
+        map.latch_value();
+        assert_eq!(
+            map.diff(),
+            [
+                MapDiff::Inserted("c"),
+                MapDiff::Updated("a"),
+                MapDiff::Removed("b"),
+                MapDiff::Updated("c"),
+            ]
+        );
+        assert_eq!(
+            map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+            [("a", 10), ("c", 4)]
+        );
+        assert!(map.is_changed(&"a"));
+        assert!(!map.contains_key(&"b"));
+
+        map.latch_value();
+        assert!(!map.changed_this_frame());
+        assert!(!map.is_changed(&"a"));
+    }
+
+    #[test]
+    fn cleared_map_is_a_reset() {
+        let mut map = MapState::new(BTreeMap::from([(1, "one")]));
+        let handle = map.handle();
+
+        handle.clear();
+        handle.clear();
+        map.latch_value();
+
+        assert_eq!(map.diff(), [MapDiff::Reset]);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn clone_latching_late_sees_a_reset() {
+        let mut map = MapState::new(BTreeMap::from([(1, "one")]));
+        let mut late = map.clone();
+
+        map.insert(2, "two");
+        map.latch_value();
+        late.latch_value();
+
+        assert_eq!(map.diff(), [MapDiff::Inserted(2)]);
+        assert_eq!(late.diff(), [MapDiff::Reset]);
+        assert_eq!(late.get(&2), Some(&"two"));
+    }
+}
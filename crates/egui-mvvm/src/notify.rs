@@ -0,0 +1,310 @@
+//! The notification hub of a [`ViewModels`](crate::view_model::ViewModels), signaled by every state of its
+//! ViewModels so a change is found without waiting on each of them.
+
+#[cfg(feature = "recorder")]
+use crate::recorder::{FieldRecorder, Value};
+use crate::snapshot::{self, in_mutable_snapshot};
+use crate::trace::TraceName;
+#[cfg(feature = "recorder")]
+use std::any::Any;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::watch;
+
+/// Collects which ViewModels changed, and bumps an epoch for every change.
+#[derive(Clone)]
+pub(crate) struct ChangeHub(Arc<ChangeHubInner>);
+
+struct ChangeHubInner {
+    epoch: watch::Sender<u64>,
+    /// The keys of the ViewModels that changed since the last [`ChangeHub::take_changed`].
+    changed: Mutex<HashSet<usize>>,
+}
+
+impl Default for ChangeHub {
+    fn default() -> Self {
+        Self(Arc::new(ChangeHubInner {
+            epoch: watch::Sender::new(0),
+            changed: Mutex::default(),
+        }))
+    }
+}
+
+impl ChangeHub {
+    /// A notifier for the ViewModel identified by `key`.
+    pub(crate) fn notifier(&self, key: usize) -> ChangeNotifier {
+        ChangeNotifier(Arc::new(ChangeNotifierInner {
+            hub: self.clone(),
+            key,
+            notifications: AtomicU64::new(0),
+            connected: AtomicBool::new(true),
+        }))
+    }
+
+    /// Receives the epoch, which changes with every notification.
+    pub(crate) fn subscribe(&self) -> watch::Receiver<u64> {
+        self.0.epoch.subscribe()
+    }
+
+    /// The keys of the ViewModels that changed since the last call.
+    pub(crate) fn take_changed(&self) -> HashSet<usize> {
+        std::mem::take(&mut self.0.changed.lock().unwrap())
+    }
+}
+
+/// Signals the [`ViewModels`](crate::view_model::ViewModels) a ViewModel is registered in that it changed.
+///
+/// Handed by `view_model!` to every field through [`Stateful::connect`](crate::Stateful::connect).
+#[derive(Clone)]
+pub struct ChangeNotifier(Arc<ChangeNotifierInner>);
+
+struct ChangeNotifierInner {
+    hub: ChangeHub,
+    key: usize,
+    notifications: AtomicU64,
+    /// Cleared when the ViewModel is unregistered.
+    connected: AtomicBool,
+}
+
+impl ChangeNotifier {
+    /// Signals a change, once the new value can be latched.
+    pub fn notify(&self) {
+        let inner = &*self.0;
+        if !inner.connected.load(Ordering::Relaxed) {
+            return;
+        }
+        inner.notifications.fetch_add(1, Ordering::Relaxed);
+        inner.hub.0.changed.lock().unwrap().insert(inner.key);
+        inner.hub.0.epoch.send_modify(|epoch| *epoch += 1);
+    }
+
+    /// Stops signaling, once the ViewModel is unregistered.
+    pub(crate) fn disconnect(&self) {
+        self.0.connected.store(false, Ordering::Relaxed);
+    }
+
+    /// The number of changes signaled so far.
+    pub(crate) fn notifications(&self) -> u64 {
+        self.0.notifications.load(Ordering::Relaxed)
+    }
+}
+
+/// Records a published value, given as `&dyn Any` so the signal does not depend on the state's type.
+#[cfg(feature = "recorder")]
+type Probe = Box<dyn Fn(&dyn Any) + Send + Sync>;
+
+/// Shared by a state with its clones and handles, names it for tracing and notifies the ViewModel it
+/// belongs to after every published value.
+#[derive(Clone, Default)]
+pub(crate) struct StateSignal {
+    name: TraceName,
+    /// Set once, a state shared by several ViewModels notifies the first one registered.
+    notifier: Arc<OnceLock<ChangeNotifier>>,
+    /// Set once like the notifier, see [`StateSignal::record`].
+    #[cfg(feature = "recorder")]
+    probe: Arc<OnceLock<Probe>>,
+}
+
+impl StateSignal {
+    pub(crate) fn set_name(&self, name: &'static str) {
+        self.name.set(name);
+    }
+
+    pub(crate) fn connect(&self, notifier: &ChangeNotifier) {
+        let _ = self.notifier.set(notifier.clone());
+    }
+
+    /// Records every value published from now on to `recorder`, serialized with `serialize`.
+    ///
+    /// `T` is the type of the state's channel, the value is serialized while it is being sent so values are
+    /// recorded in the order receivers see them.
+    #[cfg(feature = "recorder")]
+    pub(crate) fn record<T: 'static>(
+        &self,
+        recorder: FieldRecorder,
+        serialize: impl Fn(&T) -> Option<Value> + Send + Sync + 'static,
+    ) {
+        let _ = self.probe.set(Box::new(move |value| {
+            if recorder.is_recording()
+                && let Some(value) = value.downcast_ref::<T>().and_then(&serialize)
+            {
+                recorder.write(value);
+            }
+        }));
+    }
+
+    /// Returns true if `self` and `other` belong to the same state.
+    pub(crate) fn same(&self, other: &StateSignal) -> bool {
+        Arc::ptr_eq(&self.notifier, &other.notifier)
+    }
+
+    /// Applies `f` to the latest value of `tx` and publishes it if `f` returns `Some`.
+    ///
+    /// Inside a mutable snapshot, `f` is applied to the snapshot's copy of the value instead, and applied
+    /// again to the latest value when the snapshot is committed.
+    pub(crate) fn publish<T, R>(
+        &self,
+        tx: &watch::Sender<T>,
+        op: &'static str,
+        mut f: impl FnMut(&mut T) -> Option<R> + Send + 'static,
+    ) -> Option<R>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        if in_mutable_snapshot() {
+            return snapshot::write(tx, self, op, f);
+        }
+
+        let mut result = None;
+        self.send(tx, op, |value| {
+            result = f(value);
+            result.is_some()
+        });
+        result
+    }
+
+    /// Like [`StateSignal::publish`], for a `f` that can only run once, publishes the value if it returns
+    /// true.
+    ///
+    /// Inside a mutable snapshot, `f` modifies a copy of the value, which replaces the latest value when
+    /// the snapshot is committed.
+    pub(crate) fn publish_with<T>(
+        &self,
+        tx: &watch::Sender<T>,
+        op: &'static str,
+        f: impl FnOnce(&mut T) -> bool,
+    ) -> bool
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        if !in_mutable_snapshot() {
+            return self.send(tx, op, f);
+        }
+
+        let mut value = self.read(tx, T::clone);
+        if !f(&mut value) {
+            return false;
+        }
+        self.publish(tx, op, move |latest| {
+            *latest = value.clone();
+            Some(())
+        })
+        .is_some()
+    }
+
+    /// Reads the latest value of `tx`, with the writes of the current mutable snapshot.
+    pub(crate) fn read<T: 'static, R>(&self, tx: &watch::Sender<T>, f: impl FnOnce(&T) -> R) -> R {
+        snapshot::read(tx, self, f)
+    }
+
+    /// Applies `f` to the latest value of `tx`, and publishes it if `f` returns true.
+    ///
+    /// Every published value goes through here, whether from a snapshot or not.
+    pub(crate) fn send<T: 'static>(
+        &self,
+        tx: &watch::Sender<T>,
+        op: &'static str,
+        f: impl FnOnce(&mut T) -> bool,
+    ) -> bool {
+        let modified = tx.send_if_modified(|value| {
+            let modified = f(value);
+            #[cfg(feature = "recorder")]
+            if modified && let Some(probe) = self.probe.get() {
+                probe(value);
+            }
+            modified
+        });
+        if modified {
+            self.sent(op);
+        }
+        modified
+    }
+
+    /// Notifies that a value was published by `op`, once it is visible to receivers.
+    fn sent(&self, op: &'static str) {
+        self.name.sent(op);
+        if let Some(notifier) = self.notifier.get() {
+            notifier.notify();
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    /// A state of the ViewModel identified by `key`, connected to `hub`.
+    fn state(hub: &ChangeHub, key: usize) -> (watch::Sender<u32>, StateSignal, ChangeNotifier) {
+        let signal = StateSignal::default();
+        let notifier = hub.notifier(key);
+        signal.connect(&notifier);
+        (watch::Sender::new(0), signal, notifier)
+    }
+
+    fn send(tx: &watch::Sender<u32>, signal: &StateSignal, value: u32) {
+        signal.send(tx, "send", |latest| {
+            *latest = value;
+            true
+        });
+    }
+
+    #[test]
+    fn any_view_model_wakes_up_the_hub() {
+        let hub = ChangeHub::default();
+        let (first_tx, first, _) = state(&hub, 1);
+        let (second_tx, second, _) = state(&hub, 2);
+        let mut epoch = hub.subscribe();
+
+        send(&second_tx, &second, 1);
+        assert!(epoch.has_changed().unwrap());
+        assert_eq!(hub.take_changed(), HashSet::from([2]));
+
+        epoch.mark_unchanged();
+        send(&first_tx, &first, 1);
+        assert!(epoch.has_changed().unwrap());
+        assert_eq!(hub.take_changed(), HashSet::from([1]));
+
+        // Nothing is published, nothing is signaled.
+        epoch.mark_unchanged();
+        first.send(&first_tx, "send", |_| false);
+        assert!(!epoch.has_changed().unwrap());
+        assert!(hub.take_changed().is_empty());
+    }
+
+    #[test]
+    fn changes_are_kept_until_taken() {
+        let hub = ChangeHub::default();
+        let (first_tx, first, first_notifier) = state(&hub, 1);
+        let (second_tx, second, _) = state(&hub, 2);
+        let mut epoch = hub.subscribe();
+
+        // Changes made while nobody waits add up.
+        send(&first_tx, &first, 1);
+        send(&first_tx, &first, 2);
+        send(&second_tx, &second, 1);
+        assert_eq!(*epoch.borrow_and_update(), 3);
+        assert_eq!(first_notifier.notifications(), 2);
+
+        // A change made after the epoch is read is seen by the next wait, and taken with the others.
+        send(&second_tx, &second, 2);
+        assert!(epoch.has_changed().unwrap());
+        assert_eq!(*epoch.borrow_and_update(), 4);
+        assert_eq!(hub.take_changed(), HashSet::from([1, 2]));
+        assert!(hub.take_changed().is_empty());
+    }
+
+    #[test]
+    fn disconnected_view_model_stops_signaling() {
+        let hub = ChangeHub::default();
+        let (tx, signal, notifier) = state(&hub, 1);
+        let epoch = hub.subscribe();
+
+        notifier.disconnect();
+        send(&tx, &signal, 1);
+        assert_eq!(*tx.borrow(), 1);
+        assert!(!epoch.has_changed().unwrap());
+        assert!(hub.take_changed().is_empty());
+        assert_eq!(notifier.notifications(), 0);
+    }
+}
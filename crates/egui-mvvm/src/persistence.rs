@@ -0,0 +1,451 @@
+use crate::async_state::AsyncState;
+use crate::derived_state::DerivedState;
+use crate::history_state::{HistoryState, RefHistoryState};
+use crate::list_state::ListState;
+use crate::map_state::MapState;
+#[cfg(feature = "recorder")]
+use crate::recorder::FieldRecorder;
+use crate::ref_state::RefState;
+use crate::val_state::ValState;
+use crate::view_model::{ViewModel, ViewModelLike, ViewModelTaskPool};
+use crate::view_model_list::ViewModelList;
+use egui::Id;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+/// Key-value storage for persisted ViewModels, mirrors `eframe::Storage`.
+pub trait Storage: Send {
+    fn get_string(&self, key: &str) -> Option<String>;
+    fn set_string(&mut self, key: &str, value: String);
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Stores every key in a single JSON file, which is read once when created and written on flush.
+///
+/// The file is replaced atomically on flush, through a temporary file next to it.
+pub struct FileStorage {
+    path: PathBuf,
+    values: HashMap<String, String>,
+    dirty: bool,
+}
+
+impl FileStorage {
+    /// Reads the file at `path`, starting empty if it does not exist.
+    ///
+    /// Fails if the file cannot be read or is not a JSON object of strings, instead of overwriting it on
+    /// the next flush.
+    pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let values = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            path,
+            values,
+            dirty: false,
+        })
+    }
+}
+
+impl Storage for FileStorage {
+    fn get_string(&self, key: &str) -> Option<String> {
+        self.values.get(key).cloned()
+    }
+
+    fn set_string(&mut self, key: &str, value: String) {
+        if self.values.get(key) != Some(&value) {
+            self.values.insert(key.to_string(), value);
+            self.dirty = true;
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        std::fs::write(&temp, serde_json::to_string(&self.values)?)?;
+        std::fs::rename(&temp, &self.path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// The storage key of the ViewModel of type `type_name` fetched with `id`.
+pub fn storage_key(type_name: &str, id: Id) -> String {
+    format!("egui_mvvm::{type_name}::{:016x}", id.value())
+}
+
+/// A state that can be saved with `#[viewmodel(persist)]` or `#[persist]`.
+pub trait Persistable {
+    /// Serializes the latched value, `None` if there is nothing to save.
+    fn save(&self) -> Option<serde_json::Value>;
+
+    /// Publishes and latches a saved value.
+    fn restore(&mut self, value: serde_json::Value);
+
+    /// Records every value published to the state from now on, serialized like [`Persistable::save`].
+    ///
+    /// Called by `#[viewmodel(record)]` when the ViewModel is registered and on every latch.
+    #[cfg(feature = "recorder")]
+    fn connect_recorder(&self, _recorder: FieldRecorder) {}
+
+    /// Latches a recorded value without publishing it, or the latest published value if `None`.
+    ///
+    /// Called by `#[viewmodel(record)]` to travel through a recording, see
+    /// [`ViewModels::travel_to`](crate::view_model::ViewModels::travel_to).
+    #[cfg(feature = "recorder")]
+    fn replay(&mut self, _value: Option<serde_json::Value>) {}
+}
+
+impl<S> Persistable for ValState<S>
+where
+    S: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    fn save(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self.value()).ok()
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        if let Ok(value) = serde_json::from_value(value) {
+            self.send_value(value);
+            self.latch_value();
+        }
+    }
+
+    #[cfg(feature = "recorder")]
+    fn connect_recorder(&self, recorder: FieldRecorder) {
+        self.record_with(recorder, |value| serde_json::to_value(value).ok());
+    }
+
+    #[cfg(feature = "recorder")]
+    fn replay(&mut self, value: Option<serde_json::Value>) {
+        self.latch_replayed(value.and_then(|value| serde_json::from_value(value).ok()));
+    }
+}
+
+impl<S> Persistable for RefState<S>
+where
+    S: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    fn save(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(&*self.value()).ok()
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        if let Ok(value) = serde_json::from_value(value) {
+            self.send_value(value);
+            self.latch_value();
+        }
+    }
+
+    #[cfg(feature = "recorder")]
+    fn connect_recorder(&self, recorder: FieldRecorder) {
+        self.record_with(recorder, |value| serde_json::to_value(value).ok());
+    }
+
+    #[cfg(feature = "recorder")]
+    fn replay(&mut self, value: Option<serde_json::Value>) {
+        self.latch_replayed(value.and_then(|value| serde_json::from_value(value).ok()));
+    }
+}
+
+/// Only the value is saved, the history starts empty after a restore.
+impl<S> Persistable for HistoryState<S>
+where
+    S: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    fn save(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self.value()).ok()
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        if let Ok(value) = serde_json::from_value(value) {
+            self.send_value(value);
+            self.clear_history();
+            self.latch_value();
+        }
+    }
+
+    #[cfg(feature = "recorder")]
+    fn connect_recorder(&self, recorder: FieldRecorder) {
+        self.record_with(recorder, |value| serde_json::to_value(value).ok());
+    }
+
+    #[cfg(feature = "recorder")]
+    fn replay(&mut self, value: Option<serde_json::Value>) {
+        self.latch_replayed(value.and_then(|value| serde_json::from_value(value).ok()));
+    }
+}
+
+/// Only the value is saved, the history starts empty after a restore.
+impl<S> Persistable for RefHistoryState<S>
+where
+    S: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    fn save(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(&*self.value()).ok()
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        if let Ok(value) = serde_json::from_value(value) {
+            self.send_value(value);
+            self.clear_history();
+            self.latch_value();
+        }
+    }
+
+    #[cfg(feature = "recorder")]
+    fn connect_recorder(&self, recorder: FieldRecorder) {
+        self.record_with(recorder, |value| serde_json::to_value(value).ok());
+    }
+
+    #[cfg(feature = "recorder")]
+    fn replay(&mut self, value: Option<serde_json::Value>) {
+        self.latch_replayed(value.and_then(|value| serde_json::from_value(value).ok()));
+    }
+}
+
+impl<T> Persistable for ListState<T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    fn save(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self.iter().collect::<Vec<_>>()).ok()
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        if let Ok(value) = serde_json::from_value(value) {
+            self.send_value(value);
+            self.latch_value();
+        }
+    }
+
+    #[cfg(feature = "recorder")]
+    fn connect_recorder(&self, recorder: FieldRecorder) {
+        self.record_with(recorder, |items| serde_json::to_value(items).ok());
+    }
+
+    #[cfg(feature = "recorder")]
+    fn replay(&mut self, value: Option<serde_json::Value>) {
+        self.latch_replayed(value.and_then(|value| serde_json::from_value(value).ok()));
+    }
+}
+
+impl<K, V> Persistable for MapState<K, V>
+where
+    K: Serialize + DeserializeOwned + Ord + Clone + Send + Sync + 'static,
+    V: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    fn save(&self) -> Option<serde_json::Value> {
+        // As a list of pairs, JSON objects only have string keys.
+        serde_json::to_value(self.iter().collect::<Vec<_>>()).ok()
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        if let Ok(value) = serde_json::from_value::<Vec<(K, V)>>(value) {
+            self.send_value(value.into_iter().collect());
+            self.latch_value();
+        }
+    }
+
+    #[cfg(feature = "recorder")]
+    fn connect_recorder(&self, recorder: FieldRecorder) {
+        self.record_with(recorder, |entries| serde_json::to_value(entries).ok());
+    }
+
+    #[cfg(feature = "recorder")]
+    fn replay(&mut self, value: Option<serde_json::Value>) {
+        let value = value.and_then(|value| serde_json::from_value::<Vec<(K, V)>>(value).ok());
+        self.latch_replayed(value.map(|value| value.into_iter().collect()));
+    }
+}
+
+/// Only the loaded value is saved, it is restored as ready.
+impl<T, E> Persistable for AsyncState<T, E>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    fn save(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self.data()?).ok()
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        if let Ok(value) = serde_json::from_value(value) {
+            self.send_ready(value);
+            self.latch_value();
+        }
+    }
+
+    #[cfg(feature = "recorder")]
+    fn connect_recorder(&self, recorder: FieldRecorder) {
+        self.record_with(recorder, |data| serde_json::to_value(data).ok());
+    }
+
+    #[cfg(feature = "recorder")]
+    fn replay(&mut self, value: Option<serde_json::Value>) {
+        self.latch_replayed(value.and_then(|value| serde_json::from_value(value).ok()));
+    }
+}
+
+/// Derived values are recomputed from their restored sources instead.
+impl<T: Clone + PartialEq + Send + Sync + 'static> Persistable for DerivedState<T> {
+    fn save(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    fn restore(&mut self, _value: serde_json::Value) {}
+
+    /// Recomputed from the replayed values of its sources, which are declared before it.
+    #[cfg(feature = "recorder")]
+    fn replay(&mut self, _value: Option<serde_json::Value>) {
+        self.latch_value();
+    }
+}
+
+/// A nested ViewModel saves the fields marked by its own `#[viewmodel(persist)]` or `#[persist]`.
+impl<T: ViewModelLike + ViewModelTaskPool> Persistable for T {
+    fn save(&self) -> Option<serde_json::Value> {
+        serde_json::from_str(&self.persist()?).ok()
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        ViewModelLike::restore(self, &value.to_string());
+    }
+}
+
+/// Saved item by item, `null` for the items with nothing to save. Restores the items present, a fixed list
+/// does not grow or shrink.
+impl<T: Persistable> Persistable for Vec<T> {
+    fn save(&self) -> Option<serde_json::Value> {
+        let items = self.iter().map(|item| item.save().unwrap_or_default());
+        Some(serde_json::Value::Array(items.collect()))
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        if let serde_json::Value::Array(values) = value {
+            for (item, value) in self.iter_mut().zip(values) {
+                if !value.is_null() {
+                    item.restore(value);
+                }
+            }
+        }
+    }
+}
+
+impl<T: Persistable> Persistable for Option<T> {
+    fn save(&self) -> Option<serde_json::Value> {
+        self.as_ref()?.save()
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        if let Some(item) = self {
+            item.restore(value);
+        }
+    }
+}
+
+/// Saved child by child, restored by creating a default child for each saved one.
+impl<T: ViewModel + Persistable + Default> Persistable for ViewModelList<T> {
+    fn save(&self) -> Option<serde_json::Value> {
+        let children = self
+            .iter()
+            .map(|child| child.get().save().unwrap_or_default());
+        Some(serde_json::Value::Array(children.collect()))
+    }
+
+    fn restore(&mut self, value: serde_json::Value) {
+        let serde_json::Value::Array(values) = value else {
+            return;
+        };
+        let children = values.into_iter().map(|value| {
+            let mut child = T::default();
+            if !value.is_null() {
+                Persistable::restore(&mut child, value);
+            }
+            child
+        });
+        self.send_value(children.collect());
+        self.latch_value();
+    }
+}
+
+/// The persisted fields of a ViewModel, keyed by field name.
+#[derive(Default)]
+pub struct PersistedState(serde_json::Map<String, serde_json::Value>);
+
+impl PersistedState {
+    pub fn parse(state: &str) -> Option<Self> {
+        serde_json::from_str(state).ok().map(Self)
+    }
+
+    pub fn save(&mut self, field: &str, state: &impl Persistable) {
+        if let Some(value) = state.save() {
+            self.0.insert(field.to_string(), value);
+        }
+    }
+
+    /// Restores `field`, fields missing from the saved state keep their current value.
+    pub fn restore(&self, field: &str, state: &mut impl Persistable) {
+        if let Some(value) = self.0.get(field) {
+            state.restore(value.clone());
+        }
+    }
+}
+
+impl Display for PersistedState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = serde_json::to_string(&self.0).map_err(|_| std::fmt::Error)?;
+        f.write_str(&state)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("egui-mvvm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn flush_writes_the_values_read_back() {
+        let path = temp_path("flush.json");
+        let _ = std::fs::remove_file(&path);
+
+        let mut storage = FileStorage::new(&path).unwrap();
+        storage.set_string("key", "value".to_string());
+        storage.flush().unwrap();
+
+        let storage = FileStorage::new(&path).unwrap();
+        assert_eq!(storage.get_string("key").as_deref(), Some("value"));
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn corrupt_file_is_not_overwritten() {
+        let path = temp_path("corrupt.json");
+        std::fs::write(&path, "{\"key\": oops}").unwrap();
+
+        let error = FileStorage::new(&path).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"key\": oops}");
+    }
+}
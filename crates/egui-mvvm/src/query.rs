@@ -0,0 +1,568 @@
+use crate::async_state::{AsyncValue, Resource};
+use crate::task_pool::{TaskHandle, TaskPool};
+use crate::time::Instant;
+use egui::Id;
+use futures::future::BoxFuture;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Identifies a query, as a path of segments so related queries can be invalidated together.
+///
+/// ```ignore
+/// let key = QueryKey::new(["users", &id.to_string()]);
+/// client.invalidate("users"); // Invalidates every user.
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QueryKey(Vec<String>);
+
+impl QueryKey {
+    pub fn new(segments: impl IntoIterator<Item = impl ToString>) -> Self {
+        Self(segments.into_iter().map(|s| s.to_string()).collect())
+    }
+
+    /// Returns a key with `segment` appended.
+    pub fn child(&self, segment: impl ToString) -> Self {
+        let mut key = self.clone();
+        key.0.push(segment.to_string());
+        key
+    }
+
+    pub fn segments(&self) -> &[String] {
+        &self.0
+    }
+
+    pub fn starts_with(&self, prefix: &QueryKey) -> bool {
+        self.0.starts_with(&prefix.0)
+    }
+}
+
+impl Display for QueryKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.join("/"))
+    }
+}
+
+impl From<&str> for QueryKey {
+    fn from(segment: &str) -> Self {
+        Self::new([segment])
+    }
+}
+
+impl From<String> for QueryKey {
+    fn from(segment: String) -> Self {
+        Self(vec![segment])
+    }
+}
+
+impl<S: ToString, const N: usize> From<[S; N]> for QueryKey {
+    fn from(segments: [S; N]) -> Self {
+        Self::new(segments)
+    }
+}
+
+type Fetcher<T, E> = Arc<dyn Fn() -> BoxFuture<'static, Result<T, E>> + Send + Sync>;
+
+/// A cached query, with the type of its value erased.
+trait Entry: Any + Send {
+    /// Returns true if a [`QuerySubscription`] of the entry is alive.
+    fn is_used(&self) -> bool;
+
+    /// Marks the value as stale, fetching it again if it is used.
+    fn invalidate(&mut self, pool: &TaskPool);
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+struct QueryEntry<T, E> {
+    tx: watch::Sender<Resource<T, E>>,
+    fetcher: Fetcher<T, E>,
+    /// Cloned by every subscription, the entry is unused when it is the only owner.
+    users: Arc<()>,
+    /// When the last successful fetch finished, `None` if the value was invalidated or never fetched.
+    fetched_at: Arc<Mutex<Option<Instant>>>,
+    task: Option<TaskHandle>,
+}
+
+impl<T, E> QueryEntry<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    fn is_fetching(&self) -> bool {
+        self.task.as_ref().is_some_and(|task| !task.is_finished())
+    }
+
+    fn is_stale(&self, stale_time: Duration) -> bool {
+        self.fetched_at
+            .lock()
+            .unwrap()
+            .is_none_or(|fetched_at| fetched_at.elapsed() >= stale_time)
+    }
+
+    fn fetch(&mut self, pool: &TaskPool) {
+        self.tx.send_modify(Resource::start);
+
+        let tx = self.tx.clone();
+        let fetched_at = self.fetched_at.clone();
+        let fetch = (self.fetcher)();
+        self.task = Some(pool.spawn(async move {
+            let result = fetch.await;
+            // A failed fetch is tried again by the next subscription.
+            if result.is_ok() {
+                *fetched_at.lock().unwrap() = Some(Instant::now());
+            }
+            tx.send_modify(|resource| resource.finish(result));
+        }));
+    }
+}
+
+impl<T, E> Entry for QueryEntry<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    E: Clone + Send + Sync + 'static,
+{
+    fn is_used(&self) -> bool {
+        Arc::strong_count(&self.users) > 1
+    }
+
+    fn invalidate(&mut self, pool: &TaskPool) {
+        *self.fetched_at.lock().unwrap() = None;
+
+        if self.is_used() {
+            if let Some(task) = self.task.take() {
+                task.abort();
+            }
+            self.fetch(pool);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl<T, E> Drop for QueryEntry<T, E> {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// Caches the results of queries shared by several ViewModels.
+///
+/// A query is fetched once for all of its subscribers, fetched again when subscribed to after its
+/// stale time, and dropped as soon as no subscription to it is left.
+///
+/// The client of a context is kept in its memory, next to the [`ViewModels`](crate::view_model::ViewModels):
+///
+/// ```ignore
+/// let client = ctx.memory_mut(|mem| mem.query_client());
+/// self.user.subscribe(self, client.query(["users", &id], move || api.fetch_user(id)));
+/// ```
+#[derive(Clone, Default)]
+pub struct QueryClient(Arc<Mutex<QueryClientInner>>);
+
+/// How long a fetched value stays fresh unless set with [`QueryClient::set_stale_time`].
+pub const DEFAULT_STALE_TIME: Duration = Duration::from_secs(30);
+
+struct QueryClientInner {
+    entries: HashMap<(QueryKey, TypeId), Box<dyn Entry>>,
+    stale_time: Duration,
+    task_pool: TaskPool,
+}
+
+impl Default for QueryClientInner {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            stale_time: DEFAULT_STALE_TIME,
+            task_pool: TaskPool::new(),
+        }
+    }
+}
+
+impl QueryClientInner {
+    /// Removes the queries that are no longer subscribed to.
+    ///
+    /// They are returned to be dropped once the client is unlocked, as dropping a fetcher can drop a
+    /// subscription, which locks the client.
+    #[must_use]
+    fn collect_garbage(&mut self) -> Vec<Box<dyn Entry>> {
+        self.entries
+            .extract_if(|_, entry| !entry.is_used())
+            .map(|(_, entry)| entry)
+            .collect()
+    }
+}
+
+impl QueryClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long a fetched value stays fresh, [`DEFAULT_STALE_TIME`] by default.
+    ///
+    /// Subscribing to a query whose value is stale fetches it again, the stale value stays available meanwhile.
+    /// With [`Duration::ZERO`] every new subscription fetches again, unless a fetch is already in flight.
+    pub fn set_stale_time(&self, stale_time: Duration) {
+        self.0.lock().unwrap().stale_time = stale_time;
+    }
+
+    /// Subscribes to the query of `key`, fetching it with `fetch` unless its value is fresh or a fetch
+    /// is already in flight.
+    ///
+    /// `fetch` replaces the one given by earlier subscriptions, and is called again on invalidation.
+    pub fn query<T, E, F>(
+        &self,
+        key: impl Into<QueryKey>,
+        fetch: impl Fn() -> F + Send + Sync + 'static,
+    ) -> QuerySubscription<T, E>
+    where
+        T: Clone + Send + Sync + 'static,
+        E: Clone + Send + Sync + 'static,
+        F: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        let key = key.into();
+        let fetcher: Fetcher<T, E> = Arc::new(move || Box::pin(fetch()));
+
+        let mut this = self.0.lock().unwrap();
+        let garbage = this.collect_garbage();
+
+        let QueryClientInner {
+            entries,
+            stale_time,
+            task_pool,
+        } = &mut *this;
+        let entry = entries
+            .entry((key.clone(), TypeId::of::<QueryEntry<T, E>>()))
+            .or_insert_with(|| {
+                Box::new(QueryEntry {
+                    tx: watch::Sender::new(Resource {
+                        value: AsyncValue::Idle,
+                        stale: None,
+                    }),
+                    fetcher: fetcher.clone(),
+                    users: Arc::new(()),
+                    fetched_at: Default::default(),
+                    task: None,
+                })
+            });
+        let entry = entry
+            .as_any_mut()
+            .downcast_mut::<QueryEntry<T, E>>()
+            .unwrap();
+
+        let previous = std::mem::replace(&mut entry.fetcher, fetcher);
+        if !entry.is_fetching() && entry.is_stale(*stale_time) {
+            entry.fetch(task_pool);
+        }
+
+        let subscription = QuerySubscription {
+            key,
+            rx: entry.tx.subscribe(),
+            users: Some(entry.users.clone()),
+            client: Arc::downgrade(&self.0),
+        };
+
+        drop(this);
+        drop((garbage, previous));
+        subscription
+    }
+
+    /// Invalidates every query whose key starts with `prefix`, fetching again the ones still subscribed to.
+    pub fn invalidate(&self, prefix: impl Into<QueryKey>) {
+        let prefix = prefix.into();
+        let mut this = self.0.lock().unwrap();
+        let garbage = this.collect_garbage();
+
+        let QueryClientInner {
+            entries, task_pool, ..
+        } = &mut *this;
+        for ((key, _), entry) in entries.iter_mut() {
+            if key.starts_with(&prefix) {
+                entry.invalidate(task_pool);
+            }
+        }
+
+        drop(this);
+        drop(garbage);
+    }
+
+    /// Returns true if the query of `key` is cached, with any value type.
+    pub fn contains(&self, key: impl Into<QueryKey>) -> bool {
+        let key = key.into();
+        self.0
+            .lock()
+            .unwrap()
+            .entries
+            .keys()
+            .any(|(k, _)| *k == key)
+    }
+
+    /// The number of cached queries.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops the queries that are no longer subscribed to, aborting their fetch.
+    ///
+    /// Done on its own when a subscription is dropped, and before every query and invalidation.
+    pub fn collect_garbage(&self) {
+        let garbage = self.0.lock().unwrap().collect_garbage();
+        drop(garbage);
+    }
+}
+
+/// A subscription to a query of a [`QueryClient`], keeping it cached while alive.
+///
+/// Usually handed to [`AsyncState::subscribe`](crate::async_state::AsyncState::subscribe), which shows the
+/// query's value in a ViewModel.
+pub struct QuerySubscription<T, E> {
+    key: QueryKey,
+    rx: watch::Receiver<Resource<T, E>>,
+    /// Only `None` while dropping.
+    users: Option<Arc<()>>,
+    client: Weak<Mutex<QueryClientInner>>,
+}
+
+impl<T, E> Clone for QuerySubscription<T, E> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            rx: self.rx.clone(),
+            users: self.users.clone(),
+            client: self.client.clone(),
+        }
+    }
+}
+
+impl<T, E> Drop for QuerySubscription<T, E> {
+    fn drop(&mut self) {
+        let Some(users) = self.users.take() else {
+            return;
+        };
+        let entry_users = Arc::downgrade(&users);
+        drop(users);
+
+        // Checked with the client locked, as subscriptions dropped at the same time all see the count
+        // left by the last one, and a new subscription is only made with the client locked.
+        if let Some(client) = self.client.upgrade() {
+            let mut client = client.lock().unwrap();
+            if entry_users.strong_count() == 1 {
+                let garbage = client.collect_garbage();
+                drop(client);
+                drop(garbage);
+            }
+        }
+    }
+}
+
+impl<T: Clone, E: Clone> QuerySubscription<T, E> {
+    pub fn key(&self) -> &QueryKey {
+        &self.key
+    }
+
+    pub fn latest_value(&self) -> AsyncValue<T, E> {
+        self.rx.borrow().value.clone()
+    }
+
+    /// The fetched value, or the previous one while fetching again or after a failed fetch.
+    pub fn latest_data(&self) -> Option<T> {
+        self.rx.borrow().data().cloned()
+    }
+
+    pub(crate) fn receiver(&self) -> watch::Receiver<Resource<T, E>> {
+        self.rx.clone()
+    }
+}
+
+pub trait EguiQueryClientExt {
+    fn query_client(self) -> QueryClient;
+}
+
+impl EguiQueryClientExt for &mut egui::Memory {
+    fn query_client(self) -> QueryClient {
+        self.data
+            .get_temp_mut_or_default::<QueryClient>(Id::NULL)
+            .clone()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::testing::TestHarness;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts the fetches of a query, each taking a second.
+    #[derive(Clone, Default)]
+    struct Api {
+        fetches: Arc<AtomicUsize>,
+    }
+
+    impl Api {
+        fn query(
+            &self,
+            client: &QueryClient,
+            key: impl Into<QueryKey>,
+        ) -> QuerySubscription<usize, String> {
+            let fetches = self.fetches.clone();
+            client.query(key, move || {
+                let fetch = fetches.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    Ok(fetch)
+                }
+            })
+        }
+
+        fn fetches(&self) -> usize {
+            self.fetches.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn subscribers_share_the_fetch_in_flight() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        let (client, api) = (QueryClient::new(), Api::default());
+
+        let first = api.query(&client, ["users", "1"]);
+        let second = api.query(&client, ["users", "1"]);
+        assert_eq!(first.latest_value(), AsyncValue::Loading { progress: None });
+        harness.advance(Duration::from_secs(1));
+
+        assert_eq!(api.fetches(), 1);
+        assert_eq!(first.latest_value(), AsyncValue::Ready(1));
+        assert_eq!(second.latest_value(), AsyncValue::Ready(1));
+    }
+
+    #[test]
+    fn invalidate_fetches_the_matching_queries_again() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        let client = QueryClient::new();
+        let apis = [Api::default(), Api::default(), Api::default()];
+
+        let first = apis[0].query(&client, ["users", "1"]);
+        let second = apis[1].query(&client, ["users", "2"]);
+        let post = apis[2].query(&client, ["posts", "1"]);
+        harness.advance(Duration::from_secs(1));
+
+        client.invalidate("users");
+        assert_eq!(first.latest_value(), AsyncValue::Loading { progress: None });
+        assert_eq!(first.latest_data(), Some(1));
+        harness.advance(Duration::from_secs(1));
+
+        assert_eq!(apis.each_ref().map(Api::fetches), [2, 2, 1]);
+        assert_eq!(first.latest_value(), AsyncValue::Ready(2));
+        assert_eq!(second.latest_value(), AsyncValue::Ready(2));
+        assert_eq!(post.latest_value(), AsyncValue::Ready(1));
+    }
+
+    #[test]
+    fn fresh_value_is_not_fetched_again() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        let (client, api) = (QueryClient::new(), Api::default());
+
+        let _first = api.query(&client, "settings");
+        harness.advance(Duration::from_secs(1));
+        harness.advance(DEFAULT_STALE_TIME - Duration::from_secs(1));
+        let second = api.query(&client, "settings");
+        assert_eq!(api.fetches(), 1);
+        assert_eq!(second.latest_value(), AsyncValue::Ready(1));
+
+        harness.advance(Duration::from_secs(1));
+        let third = api.query(&client, "settings");
+        assert_eq!(api.fetches(), 2);
+        assert_eq!(third.latest_data(), Some(1));
+    }
+
+    #[test]
+    fn last_unsubscribe_drops_the_query() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        let (client, api) = (QueryClient::new(), Api::default());
+
+        let first = api.query(&client, "settings");
+        let second = first.clone();
+        drop(first);
+        assert!(client.contains("settings"));
+
+        drop(second);
+        assert!(client.is_empty());
+
+        // The fetch in flight was aborted, subscribing again starts over.
+        let third = api.query(&client, "settings");
+        harness.advance(Duration::from_secs(1));
+        assert_eq!(api.fetches(), 2);
+        assert_eq!(third.latest_value(), AsyncValue::Ready(2));
+    }
+
+    #[test]
+    fn failed_fetch_is_tried_again() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        let client = QueryClient::new();
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let query = || {
+            let fetches = fetches.clone();
+            client.query("settings", move || {
+                let fetch = fetches.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    if fetch == 1 {
+                        Err("offline".to_string())
+                    } else {
+                        Ok(fetch)
+                    }
+                }
+            })
+        };
+
+        let first = query();
+        harness.yield_to_tasks();
+        assert_eq!(
+            first.latest_value(),
+            AsyncValue::Failed("offline".to_string())
+        );
+
+        let second = query();
+        harness.yield_to_tasks();
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        assert_eq!(second.latest_value(), AsyncValue::Ready(2));
+    }
+
+    #[test]
+    fn concurrent_unsubscribes_drop_the_query() {
+        let harness = TestHarness::new();
+        let _runtime = harness.enter();
+        let (client, api) = (QueryClient::new(), Api::default());
+
+        for _ in 0..100 {
+            let first = api.query(&client, "settings");
+            let second = first.clone();
+            let barrier = std::sync::Barrier::new(2);
+            std::thread::scope(|scope| {
+                for subscription in [first, second] {
+                    let barrier = &barrier;
+                    scope.spawn(move || {
+                        barrier.wait();
+                        drop(subscription);
+                    });
+                }
+            });
+
+            assert!(client.is_empty());
+        }
+    }
+}
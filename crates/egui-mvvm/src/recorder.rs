@@ -0,0 +1,252 @@
+#[cfg(doc)]
+use crate::persistence::Persistable;
+use crate::task_pool::current_task;
+use crate::time::Instant;
+use crate::view_model::ViewModelLike;
+use egui::Id;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub use serde_json::Value;
+
+/// A value published by a field while recording, or the value it had when recording started.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedChange {
+    /// The frame of the latch that picked up the value, see
+    /// [`ViewModels::frame`](crate::view_model::ViewModels::frame).
+    pub frame: u64,
+    /// The time since the recording started.
+    pub elapsed: Duration,
+    /// The type name of the ViewModel.
+    pub view_model: String,
+    /// The `egui::Id` the ViewModel was fetched with, as [`Id::value`].
+    pub id: u64,
+    pub field: String,
+    pub value: Value,
+    /// Where the task that published the value was spawned, `None` if it came from the UI or is the value the
+    /// field had when recording started.
+    pub task: Option<String>,
+}
+
+/// The values published by the ViewModels declared with `#[viewmodel(record)]`, in order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Recording {
+    pub changes: Vec<RecordedChange>,
+}
+
+impl Recording {
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, json)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(std::io::Error::other)
+    }
+
+    /// The frames with at least one change, in order.
+    pub fn frames(&self) -> Vec<u64> {
+        let mut frames = self
+            .changes
+            .iter()
+            .map(|change| change.frame)
+            .collect::<Vec<_>>();
+        frames.sort_unstable();
+        frames.dedup();
+        frames
+    }
+
+    /// The value every field of the ViewModel had at `frame`, keyed by field name.
+    ///
+    /// Of the values latched in the same frame, the last one recorded wins.
+    pub fn values_at(&self, frame: u64, view_model: &str, id: u64) -> HashMap<&str, &Value> {
+        let mut values = HashMap::<&str, &RecordedChange>::new();
+        let changes = self.changes.iter().filter(|change| {
+            change.frame <= frame && change.view_model == view_model && change.id == id
+        });
+        for change in changes {
+            let latest = values.entry(&change.field).or_insert(change);
+            if latest.frame <= change.frame {
+                *latest = change;
+            }
+        }
+
+        values
+            .into_iter()
+            .map(|(field, change)| (field, &change.value))
+            .collect()
+    }
+
+    /// Replays the values the first recorded ViewModel of type `T` had at `frame` into `vm` and latches
+    /// them without publishing them.
+    ///
+    /// Reproduces a recording in a test:
+    ///
+    /// ```ignore
+    /// let recording = Recording::load("bug.json").unwrap();
+    /// let mut vm = CommentViewModel::default();
+    /// recording.replay(42, &mut vm);
+    /// assert!(vm.status.value().is_some());
+    /// ```
+    pub fn replay<T: ViewModelLike>(&self, frame: u64, vm: &mut T) {
+        let view_model = std::any::type_name::<T>();
+        if let Some(change) = self.changes.iter().find(|c| c.view_model == view_model) {
+            self.replay_dyn(frame, view_model, change.id, vm);
+        }
+    }
+
+    pub(crate) fn replay_dyn(
+        &self,
+        frame: u64,
+        view_model: &str,
+        id: u64,
+        vm: &mut dyn ViewModelLike,
+    ) {
+        vm.replay(&self.values_at(frame, view_model, id));
+    }
+}
+
+/// Where the values published by the recorded fields of a [`ViewModels`](crate::view_model::ViewModels) are
+/// written, shared with the fields through [`ViewModelRecorder`].
+#[derive(Clone, Default)]
+pub(crate) struct Tape(Arc<TapeInner>);
+
+#[derive(Default)]
+struct TapeInner {
+    /// Set while recording, checked before serializing a published value.
+    active: AtomicBool,
+    /// The frame of the latch that will pick up the values published now.
+    frame: AtomicU64,
+    reel: Mutex<Option<Reel>>,
+}
+
+struct Reel {
+    started: Instant,
+    recording: Recording,
+}
+
+impl Tape {
+    /// Drops the previous recording and records from `frame` on.
+    pub(crate) fn start(&self, frame: u64) {
+        self.0.frame.store(frame, Ordering::Relaxed);
+        *self.0.reel.lock().unwrap() = Some(Reel {
+            started: Instant::now(),
+            recording: Recording::default(),
+        });
+        self.0.active.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stop(&self) -> Option<Recording> {
+        self.0.active.store(false, Ordering::Relaxed);
+        let reel = self.0.reel.lock().unwrap().take()?;
+        Some(reel.recording)
+    }
+
+    pub(crate) fn recording(&self) -> Option<Recording> {
+        let reel = self.0.reel.lock().unwrap();
+        reel.as_ref().map(|reel| reel.recording.clone())
+    }
+
+    /// Tags the values published from now on with `frame`.
+    pub(crate) fn set_frame(&self, frame: u64) {
+        self.0.frame.store(frame, Ordering::Relaxed);
+    }
+
+    /// A recorder for the fields of the ViewModel of type `view_model` fetched with `id`.
+    pub(crate) fn view_model(&self, view_model: &'static str, id: Id) -> ViewModelRecorder {
+        ViewModelRecorder {
+            tape: self.clone(),
+            view_model,
+            id,
+        }
+    }
+
+    pub(crate) fn is_recording(&self) -> bool {
+        self.0.reel.lock().unwrap().is_some()
+    }
+
+    fn is_active(&self) -> bool {
+        self.0.active.load(Ordering::Relaxed)
+    }
+
+    fn push(&self, frame: Option<u64>, view_model: &str, id: Id, field: &str, value: Value) {
+        let mut reel = self.0.reel.lock().unwrap();
+        let Some(reel) = &mut *reel else {
+            return;
+        };
+
+        reel.recording.changes.push(RecordedChange {
+            frame: frame.unwrap_or_else(|| self.0.frame.load(Ordering::Relaxed)),
+            elapsed: reel.started.elapsed(),
+            view_model: view_model.to_string(),
+            id: id.value(),
+            field: field.to_string(),
+            value,
+            task: frame
+                .is_none()
+                .then(current_task)
+                .flatten()
+                .map(|location| location.to_string()),
+        });
+    }
+}
+
+/// Handed by `view_model!` to the fields of a ViewModel declared with `#[viewmodel(record)]`, through
+/// [`ViewModelLike::connect_recorder`].
+#[derive(Clone)]
+pub struct ViewModelRecorder {
+    tape: Tape,
+    view_model: &'static str,
+    id: Id,
+}
+
+impl ViewModelRecorder {
+    /// The recorder of the field named `field`.
+    pub fn field(&self, field: &'static str) -> FieldRecorder {
+        FieldRecorder {
+            view_model: self.clone(),
+            field,
+        }
+    }
+
+    /// Records the latched value of every field, when recording starts or the ViewModel is registered.
+    pub(crate) fn baseline(&self, frame: u64, vm: &dyn ViewModelLike) {
+        let Some(fields) = vm.record() else {
+            return;
+        };
+
+        for (field, value) in fields {
+            self.tape
+                .push(Some(frame), self.view_model, self.id, field, value);
+        }
+    }
+}
+
+/// Records every value published by one field, see [`Persistable::connect_recorder`].
+#[derive(Clone)]
+pub struct FieldRecorder {
+    view_model: ViewModelRecorder,
+    field: &'static str,
+}
+
+impl FieldRecorder {
+    /// Returns true while recording, before a published value is serialized.
+    pub(crate) fn is_recording(&self) -> bool {
+        self.view_model.tape.is_active()
+    }
+
+    /// Records a published value, with the task publishing it.
+    pub(crate) fn write(&self, value: Value) {
+        let ViewModelRecorder {
+            tape,
+            view_model,
+            id,
+        } = &self.view_model;
+        tape.push(None, view_model, *id, self.field, value);
+    }
+}
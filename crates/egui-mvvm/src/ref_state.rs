@@ -1,6 +1,14 @@
+use crate::derived_state::{
+    ChangeTracker, DerivedSource, LatchedReceiver, LatchedValue, SourceTracker,
+};
+use crate::notify::{ChangeNotifier, StateSignal};
+#[cfg(feature = "recorder")]
+use crate::recorder::{FieldRecorder, Value};
+use crate::snapshot::in_mutable_snapshot;
 use crate::view_model::{ViewModel, ViewModelLike};
 use crate::{ChangeDetector, Stateful};
-use std::ops::{Deref, DerefMut};
+use egui::TextBuffer;
+use std::ops::{Deref, DerefMut, Range};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex, MutexGuard};
 use tokio::sync::watch;
@@ -9,8 +17,14 @@ use tokio::sync::watch;
 #[derive(Clone)]
 pub struct RefState<S> {
     latched: Arc<Mutex<S>>,
+    /// Set by the latest latch if it picked up a new value.
+    changed: bool,
     tx: watch::Sender<Arc<Mutex<S>>>,
     rx: watch::Receiver<Arc<Mutex<S>>>,
+    signal: StateSignal,
+    /// The latched value, as seen by the [`DerivedState`](crate::derived_state::DerivedState)s computed
+    /// from this state.
+    mirror: LatchedValue<Arc<Mutex<S>>>,
 }
 
 impl<S: Default + Send + Sync + 'static> Default for RefState<S> {
@@ -19,35 +33,38 @@ impl<S: Default + Send + Sync + 'static> Default for RefState<S> {
     }
 }
 
-pub struct RefStateMutRef<'a, S> {
-    state: MutexGuard<'a, S>,
+pub struct RefStateMutRef<'a, S: Send + 'static> {
+    /// Released before the value is published, so it can be read while it is sent.
+    state: Option<MutexGuard<'a, S>>,
     value: Arc<Mutex<S>>,
     changed: Option<bool>,
     tx: watch::Sender<Arc<Mutex<S>>>,
+    signal: StateSignal,
 }
 
-impl<S> Drop for RefStateMutRef<'_, S> {
+impl<S: Send + 'static> Drop for RefStateMutRef<'_, S> {
     fn drop(&mut self) {
+        self.state.take();
         if self.changed == Some(true) {
-            let _ = self.tx.send(self.value.clone());
+            send_value(&self.tx, &self.signal, "value_mut", self.value.clone());
         }
     }
 }
 
-impl<S> Deref for RefStateMutRef<'_, S> {
+impl<S: Send + 'static> Deref for RefStateMutRef<'_, S> {
     type Target = S;
     fn deref(&self) -> &Self::Target {
-        &self.state
+        self.state.as_deref().expect("only released on drop")
     }
 }
 
-impl<S> DerefMut for RefStateMutRef<'_, S> {
+impl<S: Send + 'static> DerefMut for RefStateMutRef<'_, S> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         if self.changed.is_some() {
             self.changed.replace(true);
         }
 
-        &mut self.state
+        self.state.as_deref_mut().expect("only released on drop")
     }
 }
 
@@ -57,19 +74,43 @@ impl<S: 'static + Send + Sync> RefState<S> {
         let (tx, rx) = watch::channel(value.clone());
         Self {
             latched: value,
+            changed: false,
             tx,
             rx,
+            signal: StateSignal::default(),
+            mirror: LatchedValue::default(),
         }
     }
 
     pub fn latch_value(&mut self) {
-        if self.rx.has_changed().unwrap_or(true) {
+        self.changed = self.rx.has_changed().unwrap_or(true);
+        if self.changed {
             self.latched = self.rx.borrow_and_update().clone();
         }
+        self.mirror.update(self.changed, &self.latched);
     }
 
+    /// Latches `value` without publishing it, or the latest published value if `None`, for time travel.
+    #[cfg(feature = "recorder")]
+    pub(crate) fn latch_replayed(&mut self, value: Option<S>) {
+        self.latched = match value {
+            Some(value) => Arc::new(Mutex::new(value)),
+            None => self.rx.borrow_and_update().clone(),
+        };
+        self.changed = true;
+        self.mirror.update(true, &self.latched);
+    }
+
+    /// Returns true if the latest latch picked up a new value.
+    ///
+    /// Edits made in place through a shared value are only seen once published.
+    pub fn changed_this_frame(&self) -> bool {
+        self.changed
+    }
+
+    /// The latest published value, with the writes of the current mutable snapshot.
     pub fn latest_value(&self) -> Arc<Mutex<S>> {
-        self.tx.borrow().clone()
+        self.signal.read(&self.tx, Arc::clone)
     }
 
     pub fn value(&self) -> RefStateRef<'_, S> {
@@ -79,31 +120,82 @@ impl<S: 'static + Send + Sync> RefState<S> {
     pub fn value_mut(&mut self) -> RefStateMutRef<'_, S> {
         RefStateMutRef {
             value: self.latched.clone(),
-            state: self.latched.lock().unwrap(),
+            state: Some(self.latched.lock().unwrap()),
             changed: Some(false),
             tx: self.tx.clone(),
+            signal: self.signal.clone(),
         }
     }
 
     pub fn value_mut_untracked(&mut self) -> RefStateMutRef<'_, S> {
         RefStateMutRef {
             value: self.latched.clone(),
-            state: self.latched.lock().unwrap(),
+            state: Some(self.latched.lock().unwrap()),
             changed: None,
             tx: self.tx.clone(),
+            signal: self.signal.clone(),
         }
     }
 
     pub fn send_value(&self, value: S) {
-        let _ = self.tx.send(Arc::new(Mutex::new(value)));
+        send_value(
+            &self.tx,
+            &self.signal,
+            "send_value",
+            Arc::new(Mutex::new(value)),
+        );
+    }
+
+    /// Modifies the latest value in place.
+    ///
+    /// Inside a mutable snapshot the edit is visible as soon as it is made, only the change is published on
+    /// commit. See [`RefState::send_modify_in_snapshot`] to edit a copy instead.
+    pub fn send_modify(&self, f: impl FnOnce(&mut S)) {
+        modify_in_place(&self.tx, &self.signal, "send_modify", |value| {
+            f(value);
+            true
+        });
     }
 
-    pub fn send_modify(&self, f: impl FnOnce(&mut S)) {
-        self.tx.send_modify(|t| f(&mut t.lock().unwrap()));
+    /// Like [`RefState::send_modify`], but modifies a copy of the value inside a mutable snapshot, so readers
+    /// keep seeing the old value until the commit.
+    pub fn send_modify_in_snapshot(&self, f: impl FnOnce(&mut S))
+    where
+        S: Clone,
+    {
+        modify_copy(&self.tx, &self.signal, "send_modify", |value| {
+            f(value);
+            true
+        });
     }
 
     pub fn mark_changed(&mut self) {
-        self.tx.send_replace(self.latched.clone());
+        send_value(&self.tx, &self.signal, "mark_changed", self.latched.clone());
+    }
+
+    /// Records every value published to the state, see
+    /// [`Persistable::connect_recorder`](crate::persistence::Persistable::connect_recorder).
+    ///
+    /// A value edited in place by a reference that is still alive is not recorded.
+    #[cfg(feature = "recorder")]
+    pub(crate) fn record_with(
+        &self,
+        recorder: FieldRecorder,
+        serialize: impl Fn(&S) -> Option<Value> + Send + Sync + 'static,
+    ) {
+        self.signal.record(recorder, move |value: &Arc<Mutex<S>>| {
+            serialize(&*value.try_lock().ok()?)
+        });
+    }
+
+    /// The channel and signal of the state, for wrappers publishing through them.
+    pub(crate) fn channel(&self) -> (&watch::Sender<Arc<Mutex<S>>>, &StateSignal) {
+        (&self.tx, &self.signal)
+    }
+
+    /// The latched value, for wrappers editing it in place.
+    pub(crate) fn latched(&self) -> &Arc<Mutex<S>> {
+        &self.latched
     }
 
     pub fn change_detector(&self) -> RefStateChangeDetector<S> {
@@ -116,6 +208,7 @@ impl<S: 'static + Send + Sync> RefState<S> {
         RefStateHandle {
             latched: self.latched.clone(),
             tx: self.tx.clone(),
+            signal: self.signal.clone(),
         }
     }
 }
@@ -142,11 +235,12 @@ impl<S: 'static + Send + Sync> ChangeDetector for RefStateChangeDetector<S> {
 pub struct RefStateHandle<S> {
     latched: Arc<Mutex<S>>,
     tx: watch::Sender<Arc<Mutex<S>>>,
+    signal: StateSignal,
 }
 
-impl<S> RefStateHandle<S> {
+impl<S: Send + 'static> RefStateHandle<S> {
     pub fn set(&mut self, value: S) {
-        self.tx.send_replace(Arc::new(Mutex::new(value)));
+        send_value(&self.tx, &self.signal, "set", Arc::new(Mutex::new(value)));
     }
 
     pub fn value(&self) -> RefStateHandleRef<'_, S> {
@@ -157,21 +251,93 @@ impl<S> RefStateHandle<S> {
         RefStateHandleMutRef(self.latched.lock().unwrap())
     }
 
+    /// The latest published value, with the writes of the current mutable snapshot.
     pub fn latest_value(&self) -> Arc<Mutex<S>> {
-        self.tx.borrow().clone()
+        self.signal.read(&self.tx, Arc::clone)
     }
 
     pub fn send_value(&self, value: S) {
-        let _ = self.tx.send(Arc::new(Mutex::new(value)));
+        send_value(
+            &self.tx,
+            &self.signal,
+            "send_value",
+            Arc::new(Mutex::new(value)),
+        );
     }
 
+    /// Modifies the latest value in place, see [`RefState::send_modify`].
     pub fn send_update(&self, f: impl FnOnce(&mut S)) {
-        self.tx.send_modify(|t| f(&mut t.lock().unwrap()));
+        modify_in_place(&self.tx, &self.signal, "send_update", |value| {
+            f(value);
+            true
+        });
     }
 
+    /// Like [`RefStateHandle::send_update`], only publishing the value if `f` returns true.
     pub fn maybe_send_update(&self, f: impl FnOnce(&mut S) -> bool) {
-        self.tx.send_if_modified(|t| f(&mut t.lock().unwrap()));
+        modify_in_place(&self.tx, &self.signal, "maybe_send_update", f);
     }
+
+    /// The channel and signal of the state, for wrappers publishing through them.
+    pub(crate) fn channel(&self) -> (&watch::Sender<Arc<Mutex<S>>>, &StateSignal) {
+        (&self.tx, &self.signal)
+    }
+
+    /// Like [`RefStateHandle::send_update`], but modifies a copy of the value inside a mutable snapshot, see
+    /// [`RefState::send_modify_in_snapshot`].
+    pub fn send_update_in_snapshot(&self, f: impl FnOnce(&mut S))
+    where
+        S: Clone,
+    {
+        modify_copy(&self.tx, &self.signal, "send_update", |value| {
+            f(value);
+            true
+        });
+    }
+}
+
+/// Applies `f` to the latest value in place, publishing it if `f` returns true.
+///
+/// Inside a mutable snapshot the value is edited in place all the same, the change is published on commit.
+fn modify_in_place<S: Send + 'static>(
+    tx: &watch::Sender<Arc<Mutex<S>>>,
+    signal: &StateSignal,
+    op: &'static str,
+    f: impl FnOnce(&mut S) -> bool,
+) -> bool {
+    signal.publish_with(tx, op, |value| f(&mut value.lock().unwrap()))
+}
+
+/// Like [`modify_in_place`], but inside a mutable snapshot the value is shared with readers until the
+/// commit, so `f` modifies a copy of it instead, published as a new value.
+fn modify_copy<S: Clone + Send + 'static>(
+    tx: &watch::Sender<Arc<Mutex<S>>>,
+    signal: &StateSignal,
+    op: &'static str,
+    f: impl FnOnce(&mut S) -> bool,
+) -> bool {
+    if !in_mutable_snapshot() {
+        return signal.send(tx, op, |value| f(&mut value.lock().unwrap()));
+    }
+
+    let mut value = signal.read(tx, |value| value.lock().unwrap().clone());
+    if !f(&mut value) {
+        return false;
+    }
+    send_value(tx, signal, op, Arc::new(Mutex::new(value)));
+    true
+}
+
+fn send_value<S: Send + 'static>(
+    tx: &watch::Sender<Arc<Mutex<S>>>,
+    signal: &StateSignal,
+    op: &'static str,
+    value: Arc<Mutex<S>>,
+) {
+    signal.publish(tx, op, move |latest| {
+        *latest = value.clone();
+        Some(())
+    });
 }
 
 pub struct RefStateRef<'a, T>(MutexGuard<'a, T>);
@@ -208,9 +374,106 @@ impl<T> DerefMut for RefStateHandleMutRef<'_, T> {
     }
 }
 
+pub struct RefStateTracker<S> {
+    rx: watch::Receiver<Arc<Mutex<S>>>,
+    signal: StateSignal,
+    latched: LatchedReceiver<Arc<Mutex<S>>>,
+}
+
+impl<S> Clone for RefStateTracker<S> {
+    fn clone(&self) -> Self {
+        Self {
+            rx: self.rx.clone(),
+            signal: self.signal.clone(),
+            latched: self.latched.clone(),
+        }
+    }
+}
+
+impl<S: Send + Sync + 'static> ChangeTracker for RefStateTracker<S> {
+    fn has_changed(&self) -> bool {
+        self.rx.has_changed().unwrap_or(false)
+    }
+
+    fn mark_seen(&mut self) {
+        self.rx.mark_unchanged();
+    }
+
+    fn change_detectors(&self, detectors: &mut Vec<Box<dyn ChangeDetector>>) {
+        let mut rx = self.rx.clone();
+        rx.mark_unchanged();
+        detectors.push(Box::new(RefStateChangeDetector { rx }));
+    }
+
+    fn boxed_clone(&self) -> Box<dyn ChangeTracker> {
+        Box::new(self.clone())
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        self.signal.connect(notifier);
+    }
+}
+
+impl<S: Send + Sync + 'static> SourceTracker for RefStateTracker<S> {
+    type Value = Arc<Mutex<S>>;
+
+    fn read(&mut self) -> Self::Value {
+        self.rx.borrow_and_update().clone()
+    }
+
+    fn latched_changed(&self) -> bool {
+        self.latched
+            .has_changed()
+            .unwrap_or_else(|| self.has_changed())
+    }
+
+    fn read_latched(&mut self) -> Self::Value {
+        match self.latched.read() {
+            Some(value) => value,
+            None => self.read(),
+        }
+    }
+}
+
+impl<S: Send + Sync + 'static> DerivedSource for RefState<S> {
+    type Tracker = RefStateTracker<S>;
+
+    fn tracker(&self) -> Self::Tracker {
+        RefStateTracker {
+            rx: self.tx.subscribe(),
+            signal: self.signal.clone(),
+            latched: self.mirror.subscribe(),
+        }
+    }
+}
+
 impl<S: Send + Sync + 'static> Stateful for RefState<S> {
     type ChangeDetector = RefStateChangeDetector<S>;
     type Handle = RefStateHandle<S>;
+
+    fn latch(&mut self) {
+        self.latch_value()
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+
+    fn set_name(&self, name: &'static str) {
+        self.signal.set_name(name);
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        self.signal.connect(notifier);
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        self.changed
+    }
 }
 
 impl<S: Send + Sync + 'static> ViewModelLike for RefState<S> {
@@ -221,6 +484,10 @@ impl<S: Send + Sync + 'static> ViewModelLike for RefState<S> {
     fn change_detector_boxed(&self) -> Box<dyn ChangeDetector> {
         Box::new(self.change_detector())
     }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(self, notifier);
+    }
 }
 
 impl<S: Send + Sync + 'static> ViewModel for RefState<S> {
@@ -236,6 +503,36 @@ impl<S: Send + Sync + 'static> ViewModel for RefState<S> {
     }
 }
 
+/// Lets `ui.text_edit_singleline(&mut vm.text.value_mut())` edit a `RefState<String>`, the change is
+/// published when the reference is dropped.
+///
+/// Through [`RefState::value_mut_untracked`] edits are not published.
+impl TextBuffer for RefStateMutRef<'_, String> {
+    fn is_mutable(&self) -> bool {
+        true
+    }
+
+    fn as_str(&self) -> &str {
+        String::as_str(self)
+    }
+
+    fn insert_text(&mut self, text: &str, char_index: usize) -> usize {
+        if text.is_empty() {
+            return 0;
+        }
+
+        self.deref_mut().insert_text(text, char_index)
+    }
+
+    fn delete_char_range(&mut self, char_range: Range<usize>) {
+        if char_range.is_empty() {
+            return;
+        }
+
+        self.deref_mut().delete_char_range(char_range)
+    }
+}
+
 impl<T: Send + Sync + 'static> From<T> for RefState<T> {
     fn from(value: T) -> Self {
         RefState::new(value)
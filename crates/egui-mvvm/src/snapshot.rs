@@ -0,0 +1,471 @@
+use crate::notify::StateSignal;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+#[cfg(feature = "recorder")]
+use std::panic::Location;
+#[cfg(test)]
+use std::sync::RwLockReadGuard;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockWriteGuard, TryLockError};
+use tokio::sync::watch;
+
+/// Held for reading while the writes of a snapshot are published, and for writing by
+/// [`ViewModels::latch_values`].
+///
+/// [`ViewModels::latch_values`]: crate::view_model::ViewModels::latch_values
+static BARRIER: RwLock<()> = RwLock::new(());
+
+/// The writes of the snapshots committed while a latch was running, published once it is done.
+static DEFERRED: Mutex<Vec<Vec<Box<dyn PendingWrites>>>> = Mutex::new(Vec::new());
+
+/// Held for reading by every latch in the tests, and for writing by a test of the snapshots so the latches
+/// of the tests running in parallel do not defer its commits.
+#[cfg(test)]
+static TEST_LATCHES: RwLock<()> = RwLock::new(());
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// The writes of the open snapshot, one entry per state written to.
+    static PENDING: RefCell<Vec<Box<dyn PendingWrites>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs `f` inside a mutable snapshot.
+///
+/// Every state write made in `f`, across any number of [`ValState`], [`RefState`], [`ListState`],
+/// [`MapState`] and their handles, is published as one unit when `f` returns: the next
+/// [`ViewModels::latch_values`] either sees all of them or none of them. This lets a task update `status`
+/// and `text` together without the view ever drawing a frame where only one of them has changed.
+///
+/// Until then the writes are only visible from inside the snapshot, through `latest_value()`. Snapshots
+/// nest, the writes of an inner snapshot become part of the outer one. If `f` panics, its writes are
+/// discarded.
+///
+/// Publishing never waits on the latch, writes committed while a latch is running are published right
+/// after it, so a snapshot can be opened while holding a ViewModel's lock. A [`RefState`] edited in place
+/// through `send_modify` is the exception: the edit is visible as soon as it is made, only the change is
+/// published on commit. `send_modify_in_snapshot` edits a copy of the value instead, which replaces the
+/// shared one on commit.
+///
+/// ```ignore
+/// this.status.send_value(Some(Status::Success));
+/// this.text.send_update(|content| *content = content.to_uppercase());
+///
+/// // Can be observed half-applied, instead write:
+/// with_mutable_snapshot(|| {
+///     this.status.send_value(Some(Status::Success));
+///     this.text.send_update_in_snapshot(|content| *content = content.to_uppercase());
+/// });
+/// ```
+///
+/// [`ValState`]: crate::val_state::ValState
+/// [`RefState`]: crate::ref_state::RefState
+/// [`ListState`]: crate::list_state::ListState
+/// [`MapState`]: crate::map_state::MapState
+/// [`ViewModels::latch_values`]: crate::view_model::ViewModels::latch_values
+pub fn with_mutable_snapshot<R>(f: impl FnOnce() -> R) -> R {
+    let _snapshot = MutableSnapshot::enter();
+    f()
+}
+
+/// Returns true if the current thread is inside [`with_mutable_snapshot`].
+pub fn in_mutable_snapshot() -> bool {
+    DEPTH.with(|depth| depth.get() > 0)
+}
+
+struct MutableSnapshot;
+
+impl MutableSnapshot {
+    fn enter() -> Self {
+        DEPTH.with(|depth| depth.set(depth.get() + 1));
+        Self
+    }
+}
+
+impl Drop for MutableSnapshot {
+    fn drop(&mut self) {
+        let depth = DEPTH.with(|depth| {
+            depth.set(depth.get() - 1);
+            depth.get()
+        });
+        if depth > 0 {
+            return;
+        }
+
+        let writes = PENDING.take();
+        if !writes.is_empty() && !std::thread::panicking() {
+            commit(writes);
+        }
+    }
+}
+
+/// The writes of a snapshot to one state.
+trait PendingWrites: Send {
+    fn signal(&self) -> &StateSignal;
+    fn as_any(&mut self) -> &mut dyn Any;
+    fn publish(self: Box<Self>);
+}
+
+type Write<T> = Box<dyn FnMut(&mut T) -> bool + Send>;
+
+struct Pending<T> {
+    tx: watch::Sender<T>,
+    signal: StateSignal,
+    /// The latest value as seen from inside the snapshot.
+    view: T,
+    writes: Vec<(&'static str, Write<T>)>,
+    /// The task the snapshot was opened in, recorded as publishing the writes even if the latch publishes them.
+    #[cfg(feature = "recorder")]
+    task: Option<&'static Location<'static>>,
+}
+
+impl<T: Send + Sync + 'static> PendingWrites for Pending<T> {
+    fn signal(&self) -> &StateSignal {
+        &self.signal
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn publish(self: Box<Self>) {
+        let Pending {
+            tx,
+            signal,
+            writes,
+            #[cfg(feature = "recorder")]
+            task,
+            ..
+        } = *self;
+        let publish = || {
+            for (op, mut write) in writes {
+                signal.send(&tx, op, |value| write(value));
+            }
+        };
+
+        #[cfg(feature = "recorder")]
+        let publish = || crate::task_pool::with_current_task(task, publish);
+        publish();
+    }
+}
+
+/// Applies `f` to the snapshot's copy of the value of `tx`, recording it to be applied again to the
+/// latest value on commit if it returns `Some`.
+pub(crate) fn write<T, R>(
+    tx: &watch::Sender<T>,
+    signal: &StateSignal,
+    op: &'static str,
+    mut f: impl FnMut(&mut T) -> Option<R> + Send + 'static,
+) -> Option<R>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    with_pending(tx, signal, |entry| {
+        let result = f(&mut entry.view);
+        if result.is_some() {
+            entry
+                .writes
+                .push((op, Box::new(move |value| f(value).is_some())));
+        }
+        result
+    })
+}
+
+/// Applies `f` to the snapshot's copy of the value of `tx` without recording it, for bookkeeping that is
+/// done again by the writes published on commit.
+pub(crate) fn update_view<T, R>(
+    tx: &watch::Sender<T>,
+    signal: &StateSignal,
+    f: impl FnOnce(&mut T) -> R,
+) -> R
+where
+    T: Clone + Send + Sync + 'static,
+{
+    with_pending(tx, signal, |entry| f(&mut entry.view))
+}
+
+/// Applies `f` to the writes of the snapshot to the state of `tx`.
+fn with_pending<T, R>(
+    tx: &watch::Sender<T>,
+    signal: &StateSignal,
+    f: impl FnOnce(&mut Pending<T>) -> R,
+) -> R
+where
+    T: Clone + Send + Sync + 'static,
+{
+    // Taken out while `f` runs, so a write made from `f` does not find the list borrowed.
+    let pending = PENDING.with_borrow_mut(|pending| {
+        let index = pending.iter().position(|p| p.signal().same(signal))?;
+        Some(pending.remove(index))
+    });
+    let mut pending = pending.unwrap_or_else(|| {
+        Box::new(Pending {
+            tx: tx.clone(),
+            signal: signal.clone(),
+            view: tx.borrow().clone(),
+            writes: Vec::new(),
+            #[cfg(feature = "recorder")]
+            task: crate::task_pool::current_task(),
+        })
+    });
+
+    let entry = pending
+        .as_any()
+        .downcast_mut::<Pending<T>>()
+        .expect("a state is always written with the same type");
+    let result = f(entry);
+
+    PENDING.with_borrow_mut(|writes| writes.push(pending));
+    result
+}
+
+/// Reads the value of `tx`, as seen from inside the current snapshot.
+pub(crate) fn read<T: 'static, R>(
+    tx: &watch::Sender<T>,
+    signal: &StateSignal,
+    f: impl FnOnce(&T) -> R,
+) -> R {
+    PENDING.with_borrow_mut(|pending| {
+        let view = pending
+            .iter_mut()
+            .find(|p| p.signal().same(signal))
+            .and_then(|p| p.as_any().downcast_mut::<Pending<T>>());
+        match view {
+            Some(view) => f(&view.view),
+            None => f(&tx.borrow()),
+        }
+    })
+}
+
+/// Publishes the writes of a snapshot, or leaves them to the running latch.
+fn commit(writes: Vec<Box<dyn PendingWrites>>) {
+    let mut deferred = DEFERRED.lock().unwrap_or_else(PoisonError::into_inner);
+    deferred.push(writes);
+    publish_deferred(deferred);
+}
+
+/// Publishes the deferred writes, unless a latch is running. `deferred` is held until the barrier is tried,
+/// so a latch that is done always finds the writes left to it.
+fn publish_deferred(mut deferred: MutexGuard<'_, Vec<Vec<Box<dyn PendingWrites>>>>) {
+    let _guard = match BARRIER.try_read() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(err)) => err.into_inner(),
+        // Published by the latch once done, in commit order.
+        Err(TryLockError::WouldBlock) => return,
+    };
+    publish_all(&mut deferred);
+}
+
+fn publish_all(deferred: &mut Vec<Vec<Box<dyn PendingWrites>>>) {
+    for writes in deferred.drain(..) {
+        for pending in writes {
+            pending.publish();
+        }
+    }
+}
+
+/// Keeps snapshots from being published while held, and publishes the ones committed meanwhile when
+/// dropped.
+pub(crate) struct LatchBarrier {
+    /// Only taken on drop.
+    guard: Option<RwLockWriteGuard<'static, ()>>,
+    #[cfg(test)]
+    _test: Option<RwLockReadGuard<'static, ()>>,
+}
+
+impl Drop for LatchBarrier {
+    fn drop(&mut self) {
+        publish_all(&mut DEFERRED.lock().unwrap_or_else(PoisonError::into_inner));
+
+        // A snapshot committed between the drain above and the release of the barrier is left to this
+        // latch as well.
+        self.guard.take();
+        publish_deferred(DEFERRED.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+}
+
+/// Waits for the snapshots being published, and holds back the next ones until dropped.
+pub(crate) fn latch_barrier() -> LatchBarrier {
+    #[cfg(test)]
+    let _test = Some(TEST_LATCHES.read().unwrap_or_else(PoisonError::into_inner));
+    LatchBarrier {
+        guard: Some(BARRIER.write().unwrap_or_else(PoisonError::into_inner)),
+        #[cfg(test)]
+        _test,
+    }
+}
+
+/// Keeps the latches of the other tests from running until dropped, so a snapshot is only deferred by
+/// the latches of the test holding it.
+#[cfg(test)]
+pub(crate) fn exclusive() -> RwLockWriteGuard<'static, ()> {
+    TEST_LATCHES.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::ChangeDetector;
+    use crate::list_state::ListState;
+    use crate::ref_state::RefState;
+    use crate::val_state::ValState;
+    use futures::FutureExt;
+    use std::panic::AssertUnwindSafe;
+
+    /// A latch of the test holding [`exclusive`].
+    fn latch() -> LatchBarrier {
+        LatchBarrier {
+            guard: Some(BARRIER.write().unwrap_or_else(PoisonError::into_inner)),
+            _test: None,
+        }
+    }
+
+    #[test]
+    fn writes_are_published_together_on_commit() {
+        let _exclusive = exclusive();
+        let status = ValState::new(0);
+        let text = ValState::new(String::new());
+        let outside = (status.handle(), text.handle());
+
+        with_mutable_snapshot(|| {
+            status.send_value(1);
+            text.send_value("done".to_string());
+
+            assert_eq!(status.latest_value(), 1);
+            std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    assert_eq!(outside.0.latest_value(), 0);
+                    assert_eq!(outside.1.latest_value(), "");
+                });
+            });
+        });
+
+        assert_eq!(outside.0.latest_value(), 1);
+        assert_eq!(outside.1.latest_value(), "done");
+    }
+
+    #[test]
+    fn commit_during_latch_is_published_after_it() {
+        let _exclusive = exclusive();
+        let mut status = ValState::new(0);
+        let mut text = ValState::new(String::new());
+        let handles = (status.handle(), text.handle());
+
+        let barrier = latch();
+        std::thread::spawn(move || {
+            with_mutable_snapshot(|| {
+                handles.0.send_value(1);
+                handles.1.send_value("done".to_string());
+            });
+        })
+        .join()
+        .unwrap();
+
+        status.latch_value();
+        text.latch_value();
+        assert_eq!((*status.value(), text.value().as_str()), (0, ""));
+
+        drop(barrier);
+        status.latch_value();
+        text.latch_value();
+        assert_eq!((*status.value(), text.value().as_str()), (1, "done"));
+    }
+
+    #[test]
+    fn commit_at_the_end_of_a_latch_is_published() {
+        const COMMITS: usize = 1000;
+        let _exclusive = exclusive();
+
+        let list = ListState::new(Vec::new());
+        let handle = list.handle();
+        let committer = std::thread::spawn(move || {
+            for i in 0..COMMITS {
+                with_mutable_snapshot(|| handle.push(i));
+            }
+        });
+
+        // Commits land in every part of the latch, including between its drain and its release.
+        while !committer.is_finished() {
+            drop(latch());
+        }
+        committer.join().unwrap();
+
+        assert!(
+            DEFERRED.lock().unwrap().is_empty(),
+            "commits left without a latch"
+        );
+        assert_eq!(list.latest_value().len(), COMMITS);
+    }
+
+    #[test]
+    fn ref_state_is_edited_in_a_copy_until_commit() {
+        let _exclusive = exclusive();
+        let mut list = RefState::new(vec![1]);
+        let handle = list.handle();
+
+        with_mutable_snapshot(|| {
+            handle.send_update_in_snapshot(|list| list.push(2));
+
+            assert_eq!(*handle.latest_value().lock().unwrap(), [1, 2]);
+            assert_eq!(*list.value(), [1]);
+            std::thread::scope(|scope| {
+                scope.spawn(|| assert_eq!(*handle.value(), [1]));
+            });
+        });
+
+        list.latch_value();
+        assert_eq!(*list.value(), [1, 2]);
+    }
+
+    #[test]
+    fn ref_state_is_edited_in_place_and_published_on_commit() {
+        struct Counter(u32);
+        let _exclusive = exclusive();
+
+        let mut counter = RefState::new(Counter(0));
+        let detector = counter.change_detector();
+
+        with_mutable_snapshot(|| {
+            counter.send_modify(|counter| counter.0 += 1);
+
+            assert_eq!(counter.value().0, 1);
+            assert!(detector.wait_for_change().now_or_never().is_none());
+        });
+
+        assert!(detector.wait_for_change().now_or_never().is_some());
+        counter.latch_value();
+        assert!(counter.changed_this_frame());
+    }
+
+    #[test]
+    fn nested_snapshots_commit_with_the_outer_one() {
+        let _exclusive = exclusive();
+        let status = ValState::new(0);
+        let handle = status.handle();
+
+        with_mutable_snapshot(|| {
+            with_mutable_snapshot(|| status.send_value(1));
+            std::thread::scope(|scope| {
+                scope.spawn(|| assert_eq!(handle.latest_value(), 0));
+            });
+            status.send_modify(|value| *value += 1);
+        });
+
+        assert_eq!(handle.latest_value(), 2);
+    }
+
+    #[test]
+    fn panic_discards_writes() {
+        let _exclusive = exclusive();
+        let status = ValState::new(0);
+
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            with_mutable_snapshot(|| {
+                status.send_value(1);
+                panic!("discarded");
+            })
+        }));
+
+        assert!(result.is_err());
+        assert!(!in_mutable_snapshot());
+        assert_eq!(status.latest_value(), 0);
+    }
+}
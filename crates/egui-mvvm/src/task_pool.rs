@@ -1,44 +1,513 @@
-use egui::{Ui, UiBuilder};
+use crate::executor::{Executor, default_executor};
+use crate::trace::TraceName;
+use egui::{Id, Ui, UiBuilder};
+use futures::FutureExt;
+use futures::future::{AbortHandle, Abortable};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::Hash;
+use std::panic::{AssertUnwindSafe, Location};
 use std::sync::{Arc, Mutex};
-use tokio::task::{AbortHandle, JoinSet};
+use tokio::sync::watch;
 
-#[derive(Default, Debug, Clone)]
+#[cfg(feature = "recorder")]
+thread_local! {
+    /// Where the task being polled on this thread was spawned.
+    static CURRENT_TASK: std::cell::Cell<Option<&'static Location<'static>>> =
+        const { std::cell::Cell::new(None) };
+}
+
+/// Where the task being polled on the current thread was spawned, `None` outside of a [`TaskPool`] task.
+#[cfg(feature = "recorder")]
+pub(crate) fn current_task() -> Option<&'static Location<'static>> {
+    CURRENT_TASK.get()
+}
+
+/// Runs `f` as if polled by the task spawned at `task`, for [`current_task`].
+#[cfg(feature = "recorder")]
+pub(crate) fn with_current_task<R>(
+    task: Option<&'static Location<'static>>,
+    f: impl FnOnce() -> R,
+) -> R {
+    struct Restore(Option<&'static Location<'static>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT_TASK.set(self.0);
+        }
+    }
+
+    let _restore = Restore(CURRENT_TASK.replace(task));
+    f()
+}
+
+/// Spawns tasks on an [`Executor`], aborting them when the last clone of the pool is dropped.
+#[derive(Default, Clone)]
 pub struct TaskPool {
-    join_set: Arc<Mutex<JoinSet<()>>>,
+    inner: Arc<TaskPoolInner>,
+}
+
+#[derive(Default)]
+struct TaskPoolInner {
+    executor: Option<Arc<dyn Executor>>,
+    tasks: Mutex<Vec<PoolTask>>,
+    /// The unfinished tasks of each key of [`TaskPool::spawn_keyed`], in spawn order.
+    keyed: Mutex<HashMap<Id, Vec<TaskHandle>>>,
+    /// The type of the ViewModel owning the pool, see [`TaskPool::set_name`].
+    name: TraceName,
+}
+
+struct PoolTask {
+    abort: AbortHandle,
+    finished: watch::Receiver<bool>,
+}
+
+impl Drop for TaskPoolInner {
+    fn drop(&mut self) {
+        for task in self.tasks.get_mut().unwrap().drain(..) {
+            task.abort.abort();
+        }
+    }
+}
+
+impl Debug for TaskPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskPool")
+            .field("tasks", &self.inner.tasks.lock().unwrap().len())
+            .finish_non_exhaustive()
+    }
 }
 
-#[derive(Clone)]
-pub struct TaskHandle {
+/// What [`TaskPool::spawn_keyed`] does when a task with the same key is still running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnPolicy {
+    /// Aborts the running task and starts the new one, for search-as-you-type.
+    CancelPrevious,
+    /// Keeps the running task and drops the new one, for submit buttons.
+    IgnoreIfRunning,
+    /// Starts the new task once the running one has finished, for sequential saves.
+    Queue,
+}
+
+/// A handle to a task spawned on a [`TaskPool`].
+///
+/// The result can be awaited with [`TaskHandle::join`] or polled from the UI with
+/// [`TaskHandle::try_take_result`]. Clones share the same result, it can only be taken once.
+pub struct TaskHandle<T = ()> {
     handle: AbortHandle,
+    shared: Arc<TaskShared<T>>,
+    token: CancellationToken,
+}
+
+impl<T> Debug for TaskHandle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskHandle")
+            .field("finished", &self.is_finished())
+            .field("cancelled", &self.is_cancelled())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Clone for TaskHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle.clone(),
+            shared: self.shared.clone(),
+            token: self.token.clone(),
+        }
+    }
+}
+
+/// Why a task did not produce a result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskError {
+    /// The task panicked, with the panic message if it was a string.
+    Panicked(String),
+    /// The task was aborted, or its pool was dropped, before it finished.
+    Aborted,
+    /// The result was already taken through another clone of the handle.
+    Taken,
+}
+
+impl Display for TaskError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskError::Panicked(message) => write!(f, "task panicked: {message}"),
+            TaskError::Aborted => f.write_str("task was aborted"),
+            TaskError::Taken => f.write_str("task result was already taken"),
+        }
+    }
+}
+
+impl std::error::Error for TaskError {}
+
+/// Asks a task to stop, leaving it the chance to clean up first.
+///
+/// Unlike [`TaskHandle::abort`], nothing happens to the task unless it checks
+/// [`CancellationToken::is_cancelled`] or awaits [`CancellationToken::cancelled`].
+#[derive(Clone, Debug)]
+pub struct CancellationToken {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self {
+            tx: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Completes once the token is cancelled.
+    pub async fn cancelled(&self) {
+        let mut rx = self.tx.subscribe();
+        // The sender lives in `self`, so the channel cannot close while waiting.
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+enum TaskSlot<T> {
+    Running,
+    Finished(Result<T, TaskError>),
+    Taken,
+}
+
+struct TaskShared<T> {
+    slot: Mutex<TaskSlot<T>>,
+    finished: watch::Sender<bool>,
+}
+
+impl<T> TaskShared<T> {
+    fn new() -> Self {
+        Self {
+            slot: Mutex::new(TaskSlot::Running),
+            finished: watch::Sender::new(false),
+        }
+    }
+
+    fn finish(&self, result: Result<T, TaskError>) {
+        let mut slot = self.slot.lock().unwrap();
+        if matches!(*slot, TaskSlot::Running) {
+            *slot = TaskSlot::Finished(result);
+        }
+        drop(slot);
+
+        self.finished.send_replace(true);
+    }
+}
+
+/// Reports the task as aborted if its future is dropped before finishing.
+struct FinishGuard<T>(Arc<TaskShared<T>>);
+
+impl<T> Drop for FinishGuard<T> {
+    fn drop(&mut self) {
+        self.0.finish(Err(TaskError::Aborted));
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Ok(message) = payload.downcast::<String>() {
+        *message
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+/// Wraps `task` to store its output, or its panic, in `shared`.
+fn capture<T>(
+    task: impl Future<Output = T>,
+    shared: Arc<TaskShared<T>>,
+) -> impl Future<Output = ()> {
+    // Created outside the future, a task aborted before its first poll is still reported.
+    let guard = FinishGuard(shared);
+
+    async move {
+        let result = AssertUnwindSafe(task)
+            .catch_unwind()
+            .await
+            .map_err(|payload| TaskError::Panicked(panic_message(payload)));
+        guard.0.finish(result);
+    }
 }
 
 impl TaskPool {
+    /// Creates a pool on the default executor, see [`set_default_executor`](crate::executor::set_default_executor).
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_executor(executor: impl Executor) -> Self {
         Self {
-            join_set: Default::default(),
+            inner: Arc::new(TaskPoolInner {
+                executor: Some(Arc::new(executor)),
+                tasks: Default::default(),
+                keyed: Default::default(),
+                name: Default::default(),
+            }),
         }
     }
 
+    #[track_caller]
     pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) -> TaskHandle {
+        self.spawn_with_result(|_| task)
+    }
+
+    #[track_caller]
+    pub fn spawn_local(&self, task: impl Future<Output = ()> + 'static) -> TaskHandle {
+        self.spawn_local_with_result(|_| task)
+    }
+
+    /// Like [`TaskPool::spawn_with_result`], for futures that are not `Send`.
+    #[track_caller]
+    pub fn spawn_local_with_result<T, F>(
+        &self,
+        f: impl FnOnce(CancellationToken) -> F,
+    ) -> TaskHandle<T>
+    where
+        T: 'static,
+        F: Future<Output = T> + 'static,
+    {
+        let token = CancellationToken::new();
+        let shared = Arc::new(TaskShared::new());
+        let (abort, registration) = AbortHandle::new_pair();
+        let task = self.trace(Location::caller(), None, f(token.clone()));
+        let task = Abortable::new(capture(task, shared.clone()), registration).map(|_| ());
+
+        self.register(&abort, &shared);
+        self.executor().spawn_local(Box::pin(task));
+
         TaskHandle {
-            handle: self.join_set.lock().unwrap().spawn(task),
+            handle: abort,
+            shared,
+            token,
         }
     }
 
-    pub fn spawn_local(&self, task: impl Future<Output = ()> + 'static) -> TaskHandle {
+    /// Spawns the future returned by `f`, keeping its output in the returned handle.
+    ///
+    /// `f` is given the token cancelled by [`TaskHandle::cancel`], panics are reported as
+    /// [`TaskError::Panicked`].
+    #[track_caller]
+    pub fn spawn_with_result<T, F>(&self, f: impl FnOnce(CancellationToken) -> F) -> TaskHandle<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = T> + Send + 'static,
+    {
+        self.spawn_in_slot(None, f)
+    }
+
+    /// Spawns the future returned by `f`, `key` is the slot of [`TaskPool::spawn_keyed`] it runs in.
+    #[track_caller]
+    fn spawn_in_slot<T, F>(
+        &self,
+        key: Option<Id>,
+        f: impl FnOnce(CancellationToken) -> F,
+    ) -> TaskHandle<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = T> + Send + 'static,
+    {
+        let token = CancellationToken::new();
+        let shared = Arc::new(TaskShared::new());
+        let (abort, registration) = AbortHandle::new_pair();
+        let task = self.trace(Location::caller(), key, f(token.clone()));
+        let task = Abortable::new(capture(task, shared.clone()), registration).map(|_| ());
+
+        self.register(&abort, &shared);
+        self.executor().spawn(Box::pin(task));
+
         TaskHandle {
-            handle: self.join_set.lock().unwrap().spawn_local(task),
+            handle: abort,
+            shared,
+            token,
         }
     }
+
+    /// Spawns the future returned by `f` in the slot of `key`, following `policy` if the slot is busy.
+    ///
+    /// With [`SpawnPolicy::IgnoreIfRunning`] `f` is not called and the handle of the task spawned last
+    /// under `key` is returned. [`SpawnPolicy::CancelPrevious`] aborts every unfinished task of `key`,
+    /// the running one and the ones queued behind it.
+    ///
+    /// ```ignore
+    /// // Only the latest query matters, older requests are aborted.
+    /// pool.spawn_keyed("search", SpawnPolicy::CancelPrevious, || search(query));
+    /// ```
+    #[track_caller]
+    pub fn spawn_keyed<F>(
+        &self,
+        key: impl Hash,
+        policy: SpawnPolicy,
+        f: impl FnOnce() -> F,
+    ) -> TaskHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let key = Id::new(key);
+        if policy == SpawnPolicy::IgnoreIfRunning
+            && let Some(running) = self.slot(key, |chain| chain.last().cloned())
+        {
+            return running;
+        }
+
+        // Called with the slots unlocked, so `f` can spawn keyed tasks itself.
+        let task = f();
+
+        // The policy is applied to the slot as it is now, another task may have been spawned in it while
+        // `f` ran.
+        self.slot(key, |chain| {
+            let handle = match (chain.last(), policy) {
+                (Some(running), SpawnPolicy::IgnoreIfRunning) => return running.clone(),
+                (Some(_), SpawnPolicy::CancelPrevious) => {
+                    for previous in chain.drain(..) {
+                        previous.abort();
+                    }
+                    self.spawn_in_slot(Some(key), |_| task)
+                }
+                (Some(running), SpawnPolicy::Queue) => {
+                    let running = running.clone();
+                    self.spawn_in_slot(Some(key), |_| async move {
+                        running.finished().await;
+                        task.await
+                    })
+                }
+                (None, _) => self.spawn_in_slot(Some(key), |_| task),
+            };
+
+            chain.push(handle.clone());
+            handle
+        })
+    }
+
+    /// Runs `f` on the unfinished tasks of `key`, with the slots locked.
+    fn slot<R>(&self, key: Id, f: impl FnOnce(&mut Vec<TaskHandle>) -> R) -> R {
+        let mut keyed = self.inner.keyed.lock().unwrap();
+        keyed.retain(|_, chain| {
+            chain.retain(|handle| !handle.is_finished());
+            !chain.is_empty()
+        });
+        f(keyed.entry(key).or_default())
+    }
+
+    /// The number of tasks spawned on the pool that have not finished yet.
+    pub fn running_tasks(&self) -> usize {
+        let tasks = self.inner.tasks.lock().unwrap();
+        tasks.iter().filter(|task| !*task.finished.borrow()).count()
+    }
+
+    /// Names the pool in the spans of the `tracing` feature, set by `view_model!` to the ViewModel's type.
+    pub fn set_name(&self, name: &'static str) {
+        self.inner.name.set(name);
+    }
+
+    /// Wraps `task` in a span with the `tracing` feature, and tracks when it is polled with the `recorder` one.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn trace<F: Future>(
+        &self,
+        location: &'static Location<'static>,
+        key: Option<Id>,
+        task: F,
+    ) -> impl Future<Output = F::Output> + use<F> {
+        #[cfg(feature = "tracing")]
+        let task = tracing::Instrument::instrument(
+            task,
+            tracing::debug_span!(
+                target: "egui_mvvm::task",
+                "task",
+                view_model = self.inner.name.get(),
+                key = key.map(tracing::field::debug),
+                spawned_at = %location,
+            ),
+        );
+
+        // Values published while `task` is polled are recorded as coming from `location`.
+        #[cfg(feature = "recorder")]
+        let task = async move {
+            let mut task = std::pin::pin!(task);
+            std::future::poll_fn(|cx| with_current_task(Some(location), || task.as_mut().poll(cx)))
+                .await
+        };
+
+        task
+    }
+
+    fn executor(&self) -> Arc<dyn Executor> {
+        self.inner.executor.clone().unwrap_or_else(default_executor)
+    }
+
+    /// Keeps the task to abort it with the pool, forgetting the tasks that already finished.
+    fn register<T>(&self, abort: &AbortHandle, shared: &TaskShared<T>) {
+        let mut tasks = self.inner.tasks.lock().unwrap();
+        tasks.retain(|task| !*task.finished.borrow());
+        tasks.push(PoolTask {
+            abort: abort.clone(),
+            finished: shared.finished.subscribe(),
+        });
+    }
 }
 
-impl TaskHandle {
+impl<T> TaskHandle<T> {
+    /// Stops the task at its next await point, without giving it the chance to clean up.
     pub fn abort(&self) {
         self.handle.abort()
     }
 
+    /// Cancels the task's [`CancellationToken`], the task decides when to stop.
+    pub fn cancel(&self) {
+        self.token.cancel()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
     pub fn is_finished(&self) -> bool {
-        self.handle.is_finished()
+        *self.shared.finished.borrow()
+    }
+
+    /// Takes the result if the task has finished, for polling from the UI.
+    pub fn try_take_result(&self) -> Option<Result<T, TaskError>> {
+        let mut slot = self.shared.slot.lock().unwrap();
+        match std::mem::replace(&mut *slot, TaskSlot::Taken) {
+            TaskSlot::Running => {
+                *slot = TaskSlot::Running;
+                None
+            }
+            TaskSlot::Finished(result) => Some(result),
+            TaskSlot::Taken => Some(Err(TaskError::Taken)),
+        }
+    }
+
+    async fn finished(&self) {
+        let mut rx = self.shared.finished.subscribe();
+        // The sender lives in `self.shared`, so the channel cannot close while waiting.
+        let _ = rx.wait_for(|finished| *finished).await;
+    }
+
+    /// Waits for the task to finish and takes its result.
+    pub async fn join(&self) -> Result<T, TaskError> {
+        self.finished().await;
+        self.try_take_result().unwrap_or(Err(TaskError::Aborted))
     }
 }
 
@@ -52,3 +521,216 @@ impl EguiLocalTaskPool for &mut Ui {
         self.memory_mut(|mem| mem.data.get_temp_mut_or_default::<TaskPool>(id).clone())
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Runs `name` for `duration`, logging when it starts and finishes.
+    async fn step(log: Arc<Mutex<Vec<String>>>, name: &'static str, duration: Duration) {
+        log.lock().unwrap().push(format!("{name} started"));
+        tokio::time::sleep(duration).await;
+        log.lock().unwrap().push(format!("{name} finished"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn panic_is_reported_as_an_error() {
+        let pool = TaskPool::new();
+        let handle = pool.spawn(async { panic!("lost connection") });
+
+        assert_eq!(
+            handle.join().await,
+            Err(TaskError::Panicked("lost connection".to_string()))
+        );
+        assert_eq!(pool.running_tasks(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_task_cleans_up() {
+        let pool = TaskPool::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let handle = pool.spawn_with_result(|token| {
+            let log = log.clone();
+            async move {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(10)) => "finished",
+                    _ = token.cancelled() => {
+                        log.lock().unwrap().push("cleaned up");
+                        "cancelled"
+                    }
+                }
+            }
+        });
+        tokio::task::yield_now().await;
+        handle.cancel();
+
+        assert!(handle.is_cancelled());
+        assert_eq!(handle.join().await, Ok("cancelled"));
+        assert_eq!(*log.lock().unwrap(), ["cleaned up"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn result_is_polled_and_taken_once() {
+        let pool = TaskPool::new();
+        let handle = pool.spawn_with_result(|_| async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            42
+        });
+        let other = handle.clone();
+
+        // Like a view checking the handle every frame.
+        assert_eq!(handle.try_take_result(), None);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        tokio::task::yield_now().await;
+
+        assert!(handle.is_finished());
+        assert_eq!(handle.try_take_result(), Some(Ok(42)));
+        assert_eq!(other.try_take_result(), Some(Err(TaskError::Taken)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel_previous_aborts_the_running_task() {
+        let pool = TaskPool::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let first = pool.spawn_keyed("search", SpawnPolicy::CancelPrevious, || {
+            step(log.clone(), "first", Duration::from_secs(1))
+        });
+        tokio::task::yield_now().await;
+        let second = pool.spawn_keyed("search", SpawnPolicy::CancelPrevious, || {
+            step(log.clone(), "second", Duration::from_secs(1))
+        });
+
+        assert_eq!(first.join().await, Err(TaskError::Aborted));
+        assert_eq!(second.join().await, Ok(()));
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["first started", "second started", "second finished"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ignore_if_running_keeps_the_running_task() {
+        let pool = TaskPool::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let first = pool.spawn_keyed("submit", SpawnPolicy::IgnoreIfRunning, || {
+            step(log.clone(), "first", Duration::from_secs(1))
+        });
+        let mut called = false;
+        let second = pool.spawn_keyed("submit", SpawnPolicy::IgnoreIfRunning, || {
+            called = true;
+            step(log.clone(), "second", Duration::from_secs(1))
+        });
+
+        assert!(!called);
+        second.join().await.unwrap();
+        assert!(first.is_finished());
+        assert_eq!(*log.lock().unwrap(), ["first started", "first finished"]);
+
+        // Once finished, the key is free again.
+        pool.spawn_keyed("submit", SpawnPolicy::IgnoreIfRunning, || {
+            step(log.clone(), "third", Duration::ZERO)
+        })
+        .join()
+        .await
+        .unwrap();
+        assert_eq!(log.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queue_runs_tasks_in_order() {
+        let pool = TaskPool::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let handles = [("first", 3), ("second", 1), ("third", 2)].map(|(name, secs)| {
+            pool.spawn_keyed("save", SpawnPolicy::Queue, || {
+                step(log.clone(), name, Duration::from_secs(secs))
+            })
+        });
+        for handle in &handles {
+            handle.join().await.unwrap();
+        }
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "first started",
+                "first finished",
+                "second started",
+                "second finished",
+                "third started",
+                "third finished",
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel_previous_aborts_the_queued_tasks() {
+        let pool = TaskPool::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let queued = ["first", "second"].map(|name| {
+            pool.spawn_keyed("save", SpawnPolicy::Queue, || {
+                step(log.clone(), name, Duration::from_secs(1))
+            })
+        });
+        tokio::task::yield_now().await;
+        pool.spawn_keyed("save", SpawnPolicy::CancelPrevious, || {
+            step(log.clone(), "last", Duration::ZERO)
+        })
+        .join()
+        .await
+        .unwrap();
+
+        for handle in &queued {
+            assert_eq!(handle.join().await, Err(TaskError::Aborted));
+        }
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["first started", "last started", "last finished"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keys_run_independently() {
+        let pool = TaskPool::new();
+        let first = pool.spawn_keyed("a", SpawnPolicy::IgnoreIfRunning, || {
+            tokio::time::sleep(Duration::from_secs(1))
+        });
+        let second = pool.spawn_keyed("b", SpawnPolicy::IgnoreIfRunning, || {
+            tokio::time::sleep(Duration::from_secs(1))
+        });
+
+        assert_eq!(pool.running_tasks(), 2);
+        first.join().await.unwrap();
+        second.join().await.unwrap();
+        assert_eq!(pool.running_tasks(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spawn_keyed_can_be_nested() {
+        let pool = TaskPool::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let outer = pool.spawn_keyed("outer", SpawnPolicy::Queue, || {
+            pool.spawn_keyed("inner", SpawnPolicy::Queue, || {
+                step(log.clone(), "inner", Duration::from_secs(1))
+            });
+            step(log.clone(), "outer", Duration::from_secs(2))
+        });
+
+        outer.join().await.unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "inner started",
+                "outer started",
+                "inner finished",
+                "outer finished"
+            ]
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use crate::ChangeDetector;
+use crate::view_model::{ChangeSet, EguiViewModelsExt, ViewModels};
+use egui::{CentralPanel, Context, RawInput, Ui};
+use std::time::Duration;
+use tokio::runtime::{EnterGuard, Runtime};
+use tokio::time::Instant;
+
+/// How many times [`TestHarness::yield_to_tasks`] yields to spawned tasks.
+pub const YIELD_ROUNDS: usize = 64;
+
+/// Runs ViewModels headlessly, without eframe or a window.
+///
+/// The harness owns an [`egui::Context`] and a single threaded tokio runtime whose clock is paused,
+/// time only moves when advanced or when every task is waiting on a timer.
+///
+/// ```ignore
+/// let mut harness = TestHarness::new();
+/// let vm = harness.run(|ui| ui.fetch_model::<DownloadViewModel>());
+///
+/// let _runtime = harness.enter();
+/// vm.get().simulate_upload();
+/// assert!(vm.get().status.value().is_none());
+/// assert!(vm.get().status.latest_value().is_some());
+///
+/// harness.advance(Duration::from_secs(1));
+/// harness.run(|_| ());
+/// assert!(matches!(vm.get().status.value(), Some(Status::Uploading(_))));
+/// ```
+pub struct TestHarness {
+    ctx: Context,
+    runtime: Runtime,
+    start: Instant,
+}
+
+impl Default for TestHarness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestHarness {
+    pub fn new() -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        let start = runtime.block_on(async { Instant::now() });
+
+        Self {
+            ctx: Context::default(),
+            runtime,
+            start,
+        }
+    }
+
+    pub fn ctx(&self) -> &Context {
+        &self.ctx
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        &self.runtime
+    }
+
+    /// Enters the runtime, needed to spawn ViewModel tasks outside of [`TestHarness::run`].
+    pub fn enter(&self) -> EnterGuard<'_> {
+        self.runtime.enter()
+    }
+
+    pub fn view_models(&self) -> ViewModels {
+        self.ctx.memory_mut(|mem| mem.view_models())
+    }
+
+    /// Latches every ViewModel, like the start of a frame, returning what changed.
+    pub fn latch(&self) -> ChangeSet {
+        self.view_models().latch_values()
+    }
+
+    /// Latches every ViewModel, then runs a frame with `f` drawing into a central panel.
+    ///
+    /// Tasks spawned during the frame are given [`TestHarness::yield_to_tasks`] before returning.
+    pub fn run<R>(&mut self, mut f: impl FnMut(&mut Ui) -> R) -> R {
+        let _guard = self.runtime.enter();
+        self.latch();
+
+        let input = RawInput {
+            time: Some(self.elapsed().as_secs_f64()),
+            ..Default::default()
+        };
+
+        let mut result = None;
+        let _ = self.ctx.run(input, |ctx| {
+            CentralPanel::default().show(ctx, |ui| {
+                result = Some(f(ui));
+            });
+        });
+
+        self.yield_to_tasks();
+        result.unwrap()
+    }
+
+    /// Yields to spawned tasks [`YIELD_ROUNDS`] times without moving time, running every task that is
+    /// ready and the ones they wake in turn.
+    ///
+    /// Tokio does not tell when no task is left to run, so a chain of more than [`YIELD_ROUNDS`] wakeups,
+    /// like two tasks passing a value back and forth through channels, is only run in part. Call it again
+    /// or advance time to run the rest.
+    pub fn yield_to_tasks(&self) {
+        self.runtime.block_on(async {
+            for _ in 0..YIELD_ROUNDS {
+                tokio::task::yield_now().await;
+            }
+        });
+    }
+
+    /// Moves the paused clock forward by `duration`, running every timer that becomes due, then yields to
+    /// the tasks they wake.
+    pub fn advance(&self, duration: Duration) {
+        self.runtime
+            .block_on(async move { tokio::time::sleep(duration).await });
+        self.yield_to_tasks();
+    }
+
+    /// Waits for `detector` to fire, fast-forwarding time while every task is idle.
+    pub fn wait_for_change(&self, detector: &impl ChangeDetector) -> Option<()> {
+        let change = self.runtime.block_on(detector.wait_for_change());
+        self.yield_to_tasks();
+        change
+    }
+
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// Virtual time passed since the harness was created.
+    pub fn elapsed(&self) -> Duration {
+        let _guard = self.runtime.enter();
+        self.start.elapsed()
+    }
+
+    /// Returns true if a repaint was requested, for example by a ViewModel change.
+    pub fn has_requested_repaint(&self) -> bool {
+        self.ctx.has_requested_repaint()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate as egui_mvvm;
+    use crate::val_state::ValState;
+    use crate::view_model;
+    use crate::view_model::{EguiViewModelExt, ViewModel};
+
+    view_model! {
+        #[viewmodel(default)]
+        struct TimerViewModel {
+            ticks: ValState<u32> = 0,
+        }
+    }
+
+    impl TimerViewModel {
+        fn start(&self) {
+            self.spawn(|this| async move {
+                loop {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    this.ticks.send_update(|ticks| *ticks += 1);
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn advance_runs_view_model_tasks_in_virtual_time() {
+        let mut harness = TestHarness::new();
+        let vm = harness.run(|ui| {
+            let vm = ui.fetch_model::<TimerViewModel>();
+            vm.get().start();
+            vm
+        });
+
+        harness.advance(Duration::from_millis(2500));
+        assert_eq!(harness.elapsed(), Duration::from_millis(2500));
+        assert_eq!(*vm.get().ticks.value(), 0);
+        assert_eq!(vm.get().ticks.latest_value(), 2);
+
+        harness.run(|_| ());
+        assert_eq!(*vm.get().ticks.value(), 2);
+    }
+}
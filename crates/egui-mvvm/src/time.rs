@@ -0,0 +1,24 @@
+//! Clock and timers that follow the enabled runtime, so a paused tokio clock also pauses them in tests.
+//!
+//! On `wasm32` the clock is always `web_time`, the clocks of std and tokio panic there, and with the `web` feature
+//! timers use the browser's `setTimeout` even if the `tokio` feature is left enabled.
+
+use std::time::Duration;
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::Instant;
+
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+pub(crate) use tokio::time::Instant;
+
+#[cfg(all(not(feature = "tokio"), not(target_arch = "wasm32")))]
+pub(crate) use std::time::Instant;
+
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(all(feature = "tokio", not(all(feature = "web", target_arch = "wasm32"))))]
+    tokio::time::sleep(duration).await;
+
+    // Backed by `setTimeout` in the browser with the `web` feature.
+    #[cfg(not(all(feature = "tokio", not(all(feature = "web", target_arch = "wasm32")))))]
+    futures_timer::Delay::new(duration).await;
+}
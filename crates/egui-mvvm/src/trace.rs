@@ -0,0 +1,152 @@
+//! Names of states and task pools for the `tracing` feature, compiled away without it.
+
+#[cfg(feature = "tracing")]
+use std::collections::BTreeSet;
+#[cfg(feature = "tracing")]
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The name of a state, shared with its clones and handles, set once by `view_model!`.
+#[derive(Clone, Default)]
+pub(crate) struct TraceName {
+    #[cfg(feature = "tracing")]
+    name: Arc<OnceLock<&'static str>>,
+}
+
+#[cfg(feature = "tracing")]
+impl TraceName {
+    pub(crate) fn set(&self, name: &'static str) {
+        let _ = self.name.set(name);
+    }
+
+    pub(crate) fn get(&self) -> Option<&'static str> {
+        self.name.get().copied()
+    }
+
+    /// Emits an event for a value published by `op`.
+    pub(crate) fn sent(&self, op: &'static str) {
+        tracing::trace!(target: "egui_mvvm::state", field = self.get(), op, "value sent");
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl TraceName {
+    pub(crate) fn set(&self, _name: &'static str) {}
+
+    pub(crate) fn sent(&self, _op: &'static str) {}
+}
+
+/// The name `{prefix}.{field}`, interned so a ViewModel nested in many parents allocates each name once.
+#[cfg(feature = "tracing")]
+pub(crate) fn field_name(prefix: &str, field: &str) -> &'static str {
+    static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+    let name = format!("{prefix}.{field}");
+    let mut names = NAMES.lock().unwrap();
+    if let Some(name) = names.get(name.as_str()) {
+        return name;
+    }
+
+    let name = Box::leak(name.into_boxed_str());
+    names.insert(name);
+    name
+}
+
+/// Names are only read by tracing, nothing is allocated without it.
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn field_name(_prefix: &str, _field: &str) -> &'static str {
+    ""
+}
+
+#[cfg(all(test, feature = "tracing", not(target_arch = "wasm32")))]
+mod tests {
+    use crate as egui_mvvm;
+    use crate::testing::TestHarness;
+    use crate::val_state::ValState;
+    use crate::view_model;
+    use crate::view_model::EguiViewModelExt;
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    view_model! {
+        #[viewmodel(default)]
+        struct CounterViewModel {
+            count: ValState<u32> = 0,
+        }
+    }
+
+    /// Writes the fields it visits as `name=value`.
+    struct Fields<'a>(&'a mut Vec<String>);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.push(format!("{}={value:?}", field.name()));
+        }
+    }
+
+    /// Keeps every span and event, as their name followed by their fields.
+    #[derive(Clone, Default)]
+    struct Capture {
+        spans: Arc<Mutex<Vec<Vec<String>>>>,
+        events: Arc<Mutex<Vec<Vec<String>>>>,
+    }
+
+    impl Subscriber for Capture {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.spans.lock().unwrap();
+            let mut fields = vec![span.metadata().name().to_string()];
+            span.record(&mut Fields(&mut fields));
+            spans.push(fields);
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut Fields(&mut spans[span.into_u64() as usize - 1]));
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Vec::new();
+            event.record(&mut Fields(&mut fields));
+            self.events.lock().unwrap().push(fields);
+        }
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn latch_and_published_value_are_traced() {
+        let capture = Capture::default();
+        tracing::subscriber::with_default(capture.clone(), || {
+            let mut harness = TestHarness::new();
+            let vm = harness.run(|ui| ui.fetch_model::<CounterViewModel>());
+            vm.get().count.send_value(1);
+            harness.latch();
+        });
+
+        let events = capture.events.lock().unwrap();
+        assert!(events.contains(&vec![
+            "message=value sent".to_string(),
+            "field=\"CounterViewModel.count\"".to_string(),
+            "op=\"send_value\"".to_string(),
+        ]));
+
+        let spans = capture.spans.lock().unwrap();
+        let latches: Vec<_> = spans
+            .iter()
+            .filter(|span| span[0] == "latch_values")
+            .collect();
+        assert_eq!(latches.len(), 2);
+        assert_eq!(latches[1][1..], ["frame=2", "changed=1"]);
+    }
+}
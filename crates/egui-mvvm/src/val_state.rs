@@ -1,6 +1,15 @@
+use crate::derived_state::{
+    ChangeTracker, DerivedSource, LatchedReceiver, LatchedValue, SourceTracker,
+};
+use crate::notify::{ChangeNotifier, StateSignal};
+#[cfg(feature = "recorder")]
+use crate::recorder::{FieldRecorder, Value};
 use crate::view_model::{ViewModel, ViewModelLike};
 use crate::{ChangeDetector, Stateful};
-use std::ops::{Deref, DerefMut};
+use egui::TextBuffer;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use std::ops::{Deref, DerefMut, Range};
 use std::pin::Pin;
 use tokio::sync::watch;
 
@@ -8,8 +17,14 @@ use tokio::sync::watch;
 #[derive(Clone)]
 pub struct ValState<S> {
     latched: S,
+    /// Set by the latest latch if it picked up a new value.
+    changed: bool,
     tx: watch::Sender<S>,
     rx: watch::Receiver<S>,
+    signal: StateSignal,
+    /// The latched value, as seen by the [`DerivedState`](crate::derived_state::DerivedState)s computed
+    /// from this state.
+    mirror: LatchedValue<S>,
 }
 
 impl<S: Default + Send + Sync + Clone + 'static> Default for ValState<S> {
@@ -18,25 +33,26 @@ impl<S: Default + Send + Sync + Clone + 'static> Default for ValState<S> {
     }
 }
 
-pub struct ValStateMutRef<'a, S: Clone> {
+pub struct ValStateMutRef<'a, S: Clone + Send + Sync + 'static> {
     state: &'a mut S,
     tx: watch::Sender<S>,
+    signal: StateSignal,
 }
 
-impl<S: Clone> Drop for ValStateMutRef<'_, S> {
+impl<S: Clone + Send + Sync + 'static> Drop for ValStateMutRef<'_, S> {
     fn drop(&mut self) {
-        let _ = self.tx.send(self.state.clone());
+        send_value(&self.tx, &self.signal, "value_mut", self.state.clone());
     }
 }
 
-impl<S: Clone> Deref for ValStateMutRef<'_, S> {
+impl<S: Clone + Send + Sync + 'static> Deref for ValStateMutRef<'_, S> {
     type Target = S;
     fn deref(&self) -> &Self::Target {
         self.state
     }
 }
 
-impl<S: Clone> DerefMut for ValStateMutRef<'_, S> {
+impl<S: Clone + Send + Sync + 'static> DerefMut for ValStateMutRef<'_, S> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         self.state
     }
@@ -47,19 +63,45 @@ impl<S: 'static + Send + Sync + Clone> ValState<S> {
         let (tx, rx) = watch::channel(value.clone());
         Self {
             latched: value,
+            changed: false,
             tx,
             rx,
+            signal: StateSignal::default(),
+            mirror: LatchedValue::default(),
         }
     }
 
+    /// Streams the values published after this call.
+    ///
+    /// Values published faster than they are consumed are skipped, only the latest is yielded.
+    pub fn to_stream(&self) -> BoxStream<'static, S> {
+        to_stream(self.tx.subscribe())
+    }
+
     pub fn latch_value(&mut self) {
-        if self.rx.has_changed().unwrap_or(true) {
+        self.changed = self.rx.has_changed().unwrap_or(true);
+        if self.changed {
             self.latched = self.rx.borrow_and_update().clone();
         }
+        self.mirror.update(self.changed, &self.latched);
+    }
+
+    /// Latches `value` without publishing it, or the latest published value if `None`, for time travel.
+    #[cfg(feature = "recorder")]
+    pub(crate) fn latch_replayed(&mut self, value: Option<S>) {
+        self.latched = value.unwrap_or_else(|| self.rx.borrow_and_update().clone());
+        self.changed = true;
+        self.mirror.update(true, &self.latched);
+    }
+
+    /// Returns true if the latest latch picked up a new value, e.g. to scroll to the bottom only when a log grew.
+    pub fn changed_this_frame(&self) -> bool {
+        self.changed
     }
 
+    /// The latest published value, with the writes of the current mutable snapshot.
     pub fn latest_value(&self) -> S {
-        self.tx.borrow().clone()
+        self.signal.read(&self.tx, S::clone)
     }
 
     pub fn value(&self) -> &S {
@@ -70,6 +112,7 @@ impl<S: 'static + Send + Sync + Clone> ValState<S> {
         ValStateMutRef {
             state: &mut self.latched,
             tx: self.tx.clone(),
+            signal: self.signal.clone(),
         }
     }
 
@@ -78,15 +121,39 @@ impl<S: 'static + Send + Sync + Clone> ValState<S> {
     }
 
     pub fn send_value(&self, value: S) {
-        let _ = self.tx.send(value);
+        send_value(&self.tx, &self.signal, "send_value", value);
     }
 
     pub fn send_modify(&self, f: impl FnOnce(&mut S)) {
-        self.tx.send_modify(f);
+        self.signal.publish_with(&self.tx, "send_modify", |value| {
+            f(value);
+            true
+        });
+    }
+
+    /// Modifies the latest value, only publishing it if `f` returns true.
+    pub fn maybe_send_modify(&self, f: impl FnOnce(&mut S) -> bool) -> bool {
+        self.signal.publish_with(&self.tx, "maybe_send_modify", f)
     }
 
     pub fn mark_changed(&mut self) {
-        self.tx.send_replace(self.latched.clone());
+        send_value(&self.tx, &self.signal, "mark_changed", self.latched.clone());
+    }
+
+    /// Records every value published to the state, see
+    /// [`Persistable::connect_recorder`](crate::persistence::Persistable::connect_recorder).
+    #[cfg(feature = "recorder")]
+    pub(crate) fn record_with(
+        &self,
+        recorder: FieldRecorder,
+        serialize: impl Fn(&S) -> Option<Value> + Send + Sync + 'static,
+    ) {
+        self.signal.record(recorder, serialize);
+    }
+
+    /// The channel and signal of the state, for wrappers publishing through them.
+    pub(crate) fn channel(&self) -> (&watch::Sender<S>, &StateSignal) {
+        (&self.tx, &self.signal)
     }
 
     pub fn change_detector(&self) -> ValStateChangeDetector<S> {
@@ -99,6 +166,7 @@ impl<S: 'static + Send + Sync + Clone> ValState<S> {
         ValStateHandle {
             latched: self.latched.clone(),
             tx: self.tx.clone(),
+            signal: self.signal.clone(),
         }
     }
 }
@@ -125,13 +193,10 @@ impl<S: 'static + Send + Sync> ChangeDetector for ValStateChangeDetector<S> {
 pub struct ValStateHandle<S> {
     latched: S,
     tx: watch::Sender<S>,
+    signal: StateSignal,
 }
 
 impl<S> ValStateHandle<S> {
-    pub fn set(&mut self, value: S) {
-        self.tx.send_replace(value);
-    }
-
     pub fn value(&self) -> &S {
         &self.latched
     }
@@ -139,30 +204,166 @@ impl<S> ValStateHandle<S> {
     pub fn value_mut(&mut self) -> &mut S {
         &mut self.latched
     }
+}
+
+impl<S: Clone + Send + Sync + 'static> ValStateHandle<S> {
+    pub fn set(&mut self, value: S) {
+        send_value(&self.tx, &self.signal, "set", value);
+    }
 
-    pub fn latest_value(&self) -> S
-    where
-        S: Clone,
-    {
-        self.tx.borrow().clone()
+    /// The latest published value, with the writes of the current mutable snapshot.
+    pub fn latest_value(&self) -> S {
+        self.signal.read(&self.tx, S::clone)
     }
 
     pub fn send_value(&self, value: S) {
-        let _ = self.tx.send(value);
+        send_value(&self.tx, &self.signal, "send_value", value);
     }
 
     pub fn send_update(&self, f: impl FnOnce(&mut S)) {
-        self.tx.send_modify(f);
+        self.signal.publish_with(&self.tx, "send_update", |value| {
+            f(value);
+            true
+        });
+    }
+
+    /// Modifies the latest value, only publishing it if `f` returns true.
+    pub fn maybe_send_update(&self, f: impl FnOnce(&mut S) -> bool) -> bool {
+        self.signal.publish_with(&self.tx, "maybe_send_update", f)
+    }
+
+    /// The channel and signal of the state, for wrappers publishing through them.
+    pub(crate) fn channel(&self) -> (&watch::Sender<S>, &StateSignal) {
+        (&self.tx, &self.signal)
     }
 
-    pub fn maybe_send_update(&self, f: impl FnOnce(&mut S) -> bool) {
-        self.tx.send_if_modified(f);
+    /// Streams the values published after this call, see [`ValState::to_stream`].
+    pub fn to_stream(&self) -> BoxStream<'static, S> {
+        to_stream(self.tx.subscribe())
     }
 }
 
-impl<S: Send + Sync + 'static> Stateful for ValState<S> {
+fn send_value<S: Clone + Send + Sync + 'static>(
+    tx: &watch::Sender<S>,
+    signal: &StateSignal,
+    op: &'static str,
+    value: S,
+) {
+    signal.publish(tx, op, move |latest| {
+        *latest = value.clone();
+        Some(())
+    });
+}
+
+fn to_stream<S: Clone + Send + Sync + 'static>(rx: watch::Receiver<S>) -> BoxStream<'static, S> {
+    futures::stream::unfold(rx, |mut rx| async move {
+        rx.changed().await.ok()?;
+        let value = rx.borrow_and_update().clone();
+        Some((value, rx))
+    })
+    .boxed()
+}
+
+pub struct ValStateTracker<S> {
+    rx: watch::Receiver<S>,
+    signal: StateSignal,
+    latched: LatchedReceiver<S>,
+}
+
+impl<S> Clone for ValStateTracker<S> {
+    fn clone(&self) -> Self {
+        Self {
+            rx: self.rx.clone(),
+            signal: self.signal.clone(),
+            latched: self.latched.clone(),
+        }
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> ChangeTracker for ValStateTracker<S> {
+    fn has_changed(&self) -> bool {
+        self.rx.has_changed().unwrap_or(false)
+    }
+
+    fn mark_seen(&mut self) {
+        self.rx.mark_unchanged();
+    }
+
+    fn change_detectors(&self, detectors: &mut Vec<Box<dyn ChangeDetector>>) {
+        let mut rx = self.rx.clone();
+        rx.mark_unchanged();
+        detectors.push(Box::new(ValStateChangeDetector { rx }));
+    }
+
+    fn boxed_clone(&self) -> Box<dyn ChangeTracker> {
+        Box::new(self.clone())
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        self.signal.connect(notifier);
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> SourceTracker for ValStateTracker<S> {
+    type Value = S;
+
+    fn read(&mut self) -> Self::Value {
+        self.rx.borrow_and_update().clone()
+    }
+
+    fn latched_changed(&self) -> bool {
+        self.latched
+            .has_changed()
+            .unwrap_or_else(|| self.has_changed())
+    }
+
+    fn read_latched(&mut self) -> Self::Value {
+        match self.latched.read() {
+            Some(value) => value,
+            None => self.read(),
+        }
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> DerivedSource for ValState<S> {
+    type Tracker = ValStateTracker<S>;
+
+    fn tracker(&self) -> Self::Tracker {
+        ValStateTracker {
+            rx: self.tx.subscribe(),
+            signal: self.signal.clone(),
+            latched: self.mirror.subscribe(),
+        }
+    }
+}
+
+impl<S: Send + Sync + Clone + 'static> Stateful for ValState<S> {
     type ChangeDetector = ValStateChangeDetector<S>;
     type Handle = ValStateHandle<S>;
+
+    fn latch(&mut self) {
+        self.latch_value()
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+
+    fn set_name(&self, name: &'static str) {
+        self.signal.set_name(name);
+    }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        self.signal.connect(notifier);
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        self.changed
+    }
 }
 
 impl<S: Send + Sync + Clone + 'static> ViewModelLike for ValState<S> {
@@ -173,6 +374,10 @@ impl<S: Send + Sync + Clone + 'static> ViewModelLike for ValState<S> {
     fn change_detector_boxed(&self) -> Box<dyn ChangeDetector> {
         Box::new(self.change_detector())
     }
+
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(self, notifier);
+    }
 }
 
 impl<S: Send + Sync + Clone + 'static> ViewModel for ValState<S> {
@@ -188,6 +393,35 @@ impl<S: Send + Sync + Clone + 'static> ViewModel for ValState<S> {
     }
 }
 
+/// Edits the latched text in place and publishes every change, so
+/// `ui.text_edit_singleline(&mut vm.text)` needs no [`ValState::mark_changed`].
+impl TextBuffer for ValState<String> {
+    fn is_mutable(&self) -> bool {
+        true
+    }
+
+    fn as_str(&self) -> &str {
+        self.latched.as_str()
+    }
+
+    fn insert_text(&mut self, text: &str, char_index: usize) -> usize {
+        let inserted = self.latched.insert_text(text, char_index);
+        if inserted > 0 {
+            self.mark_changed();
+        }
+        inserted
+    }
+
+    fn delete_char_range(&mut self, char_range: Range<usize>) {
+        if char_range.is_empty() {
+            return;
+        }
+
+        self.latched.delete_char_range(char_range);
+        self.mark_changed();
+    }
+}
+
 impl<T: Send + Sync + Clone + 'static> From<T> for ValState<T> {
     fn from(value: T) -> Self {
         ValState::new(value)
@@ -1,10 +1,25 @@
 use crate::ChangeDetector;
-use crate::task_pool::{TaskHandle, TaskPool};
-use egui::{Id, UiBuilder};
-use std::any::Any;
+use crate::devtools::FieldInspection;
+use crate::notify::{ChangeHub, ChangeNotifier};
+#[cfg(feature = "persistence")]
+use crate::persistence::{Storage, storage_key};
+#[cfg(feature = "recorder")]
+use crate::recorder::{Recording, Tape, Value, ViewModelRecorder};
+use crate::snapshot;
+use crate::task_pool::{CancellationToken, SpawnPolicy, TaskHandle, TaskPool};
+use crate::time::Instant;
+use crate::val_state::ValStateHandle;
+use egui::{Id, LayerId, UiBuilder, ViewportId};
+use futures::future::Either;
+use futures::{Stream, StreamExt};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::ops::{Deref, DerefMut};
-use std::pin::Pin;
+use std::pin::{Pin, pin};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+use std::time::Duration;
 use tokio::sync::watch;
 
 pub trait ViewModel: ViewModelLike {
@@ -14,6 +29,7 @@ pub trait ViewModel: ViewModelLike {
     fn make_model(&self) -> Self::Model;
     fn change_detector(&self) -> Self::ChangeDetector;
 
+    #[track_caller]
     fn spawn<F>(&self, f: impl FnOnce(Self::Model) -> F) -> TaskHandle
     where
         F: Future<Output = ()> + Send + 'static,
@@ -22,6 +38,7 @@ pub trait ViewModel: ViewModelLike {
         self.task_pool().spawn(f(self.make_model()))
     }
 
+    #[track_caller]
     fn spawn_local<F>(&self, f: impl FnOnce(Self::Model) -> F) -> TaskHandle
     where
         F: Future<Output = ()> + 'static,
@@ -29,91 +46,946 @@ pub trait ViewModel: ViewModelLike {
     {
         self.task_pool().spawn_local(f(self.make_model()))
     }
+
+    /// Like [`ViewModel::spawn`], but only one task runs per `key`, see [`SpawnPolicy`].
+    #[track_caller]
+    fn spawn_keyed<F>(
+        &self,
+        key: impl Hash,
+        policy: SpawnPolicy,
+        f: impl FnOnce(Self::Model) -> F,
+    ) -> TaskHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+        Self: ViewModelTaskPool,
+    {
+        self.task_pool()
+            .spawn_keyed(key, policy, || f(self.make_model()))
+    }
+
+    /// Like [`ViewModel::spawn`], but keeps the task's output and hands it a [`CancellationToken`].
+    #[track_caller]
+    fn spawn_with_result<T, F>(
+        &self,
+        f: impl FnOnce(Self::Model, CancellationToken) -> F,
+    ) -> TaskHandle<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = T> + Send + 'static,
+        Self: ViewModelTaskPool,
+    {
+        let model = self.make_model();
+        self.task_pool()
+            .spawn_with_result(move |token| f(model, token))
+    }
+
+    /// Sends every item of `stream` to the field returned by `field`, until the stream ends.
+    ///
+    /// ```ignore
+    /// vm.collect_into(|this| &this.messages, socket.messages());
+    /// ```
+    #[track_caller]
+    fn collect_into<S>(
+        &self,
+        field: impl Fn(&Self::Model) -> &ValStateHandle<S> + Send + 'static,
+        stream: impl Stream<Item = S> + Send + 'static,
+    ) -> TaskHandle
+    where
+        S: Clone + Send + Sync + 'static,
+        Self::Model: Send,
+        Self: ViewModelTaskPool,
+    {
+        self.spawn(|this| async move {
+            let mut stream = pin!(stream);
+            while let Some(value) = stream.next().await {
+                field(&this).send_value(value);
+            }
+        })
+    }
 }
 
 pub trait ViewModelTaskPool {
     fn task_pool(&self) -> TaskPool;
+
+    /// Spawns the ViewModel's tasks on `task_pool`, aborting the tasks of its own pool unless shared.
+    fn set_task_pool(&mut self, task_pool: TaskPool);
+
+    /// Spawns the tasks of a nested ViewModel on the pool of its parent, so they are counted and aborted
+    /// with the parent's tasks.
+    ///
+    /// ```ignore
+    /// child: Child = Child::default().with_task_pool(task_pool.clone()),
+    /// ```
+    fn with_task_pool(mut self, task_pool: TaskPool) -> Self
+    where
+        Self: Sized,
+    {
+        self.set_task_pool(task_pool);
+        self
+    }
 }
 
 pub trait ViewModelLike: Any + Send + Sync + 'static {
     fn latch_state(&mut self);
     fn change_detector_boxed(&self) -> Box<dyn ChangeDetector>;
+
+    /// Serializes the fields marked for persistence, `None` if there are none.
+    fn persist(&self) -> Option<String> {
+        None
+    }
+
+    /// Restores fields saved by [`ViewModelLike::persist`].
+    fn restore(&mut self, _state: &str) {}
+
+    /// Returns true if the ViewModel is never evicted, set by `#[viewmodel(retain)]`.
+    fn retain(&self) -> bool {
+        false
+    }
+
+    /// The most repaints per second requested for changes of the ViewModel, set by `#[viewmodel(max_fps = 30)]`.
+    ///
+    /// `None` follows [`ViewModels::set_max_fps`].
+    fn max_fps(&self) -> Option<f32> {
+        None
+    }
+
+    /// The lifecycle callbacks of the ViewModel, set by `#[viewmodel(lifecycle)]`.
+    fn lifecycle(&mut self) -> Option<&mut dyn ViewModelLifecycle> {
+        None
+    }
+
+    /// Runs `event` on the ViewModel, then on the ViewModels nested in its fields, set by `view_model!`.
+    fn run_lifecycle(&mut self, event: LifecycleEvent) {
+        if let Some(lifecycle) = self.lifecycle() {
+            event.run(lifecycle);
+        }
+    }
+
+    /// The latched and latest values of every field, set by `#[viewmodel(debug)]`.
+    fn inspect(&self) -> Option<Vec<FieldInspection>> {
+        None
+    }
+
+    /// The number of tasks still running in the ViewModel's [`TaskPool`].
+    fn running_tasks(&self) -> usize {
+        0
+    }
+
+    /// Names the fields and [`TaskPool`] of the ViewModel for the `tracing` feature, set by `view_model!`.
+    fn set_names(&self) {}
+
+    /// Names each field `{prefix}.field`, set by `view_model!`.
+    ///
+    /// A nested ViewModel is named by its parent with the qualified name of the field holding it, its
+    /// [`TaskPool`] keeps its own name.
+    fn set_field_names(&self, _prefix: &'static str) {}
+
+    /// The names of the fields that picked up a new value in the latest latch, set by `view_model!`.
+    fn changed_fields(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Connects every field to `notifier`, set by `view_model!`.
+    ///
+    /// The [`ViewModels`] the ViewModel is registered in only repaint and count changes of connected fields.
+    fn connect(&self, _notifier: &ChangeNotifier) {}
+
+    /// The latched value of every field, set by `#[viewmodel(record)]`.
+    #[cfg(feature = "recorder")]
+    fn record(&self) -> Option<Vec<(&'static str, Value)>> {
+        None
+    }
+
+    /// Latches the recorded value of every field in `values` without publishing it, and the latest
+    /// published value of the other fields, set by `#[viewmodel(record)]`.
+    #[cfg(feature = "recorder")]
+    fn replay(&mut self, _values: &HashMap<&str, &Value>) {}
+
+    /// Connects every field to `recorder`, set by `#[viewmodel(record)]`.
+    #[cfg(feature = "recorder")]
+    fn connect_recorder(&self, _recorder: &ViewModelRecorder) {}
+}
+
+/// The name `{prefix}.{field}` given to a field by [`ViewModelLike::set_field_names`], empty without the
+/// `tracing` feature.
+pub fn field_name(prefix: &str, field: &str) -> &'static str {
+    crate::trace::field_name(prefix, field)
+}
+
+/// Callbacks for a ViewModel fetched with [`EguiViewModelExt`], enabled with `#[viewmodel(lifecycle)]`.
+///
+/// Frames are counted by [`ViewModels::latch_values`], a ViewModel is hidden when it was not fetched during the
+/// frame before the latch.
+///
+/// Nested ViewModels get the callbacks of their parent, after it. A child inserted into a
+/// [`ViewModelList`](crate::view_model_list::ViewModelList) later gets `on_create` and `on_visible` when
+/// latched if its parent got them, and `on_dispose` when latched out of the list.
+///
+/// ```ignore
+/// view_model! {
+///     #[viewmodel(default, lifecycle)]
+///     pub struct FeedViewModel {
+///         pub posts: RefState<Vec<Post>> = Vec::new(),
+///     }
+/// }
+///
+/// impl ViewModelLifecycle for FeedViewModel {
+///     fn on_visible(&mut self) {
+///         self.spawn_keyed("poll", SpawnPolicy::CancelPrevious, |this| poll_posts(this));
+///     }
+///
+///     fn on_hidden(&mut self) {
+///         self.spawn_keyed("poll", SpawnPolicy::CancelPrevious, |_| async {});
+///     }
+/// }
+/// ```
+pub trait ViewModelLifecycle {
+    /// Called once, after the ViewModel was created and its persisted state restored.
+    fn on_create(&mut self) {}
+
+    /// Called on the first frame the ViewModel is fetched, after being created or hidden.
+    fn on_visible(&mut self) {}
+
+    /// Called when latching, if the ViewModel was not fetched during the previous frame.
+    fn on_hidden(&mut self) {}
+
+    /// Called when the ViewModel leaves the [`ViewModels`]: evicted, removed with [`ViewModels::remove`]
+    /// or dropped by [`ViewModels::shutdown`].
+    fn on_dispose(&mut self) {}
+}
+
+/// A callback of [`ViewModelLifecycle`], run on a ViewModel and then on the ViewModels nested in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    Create,
+    Visible,
+    Hidden,
+    Dispose,
+}
+
+impl LifecycleEvent {
+    pub fn run(self, lifecycle: &mut dyn ViewModelLifecycle) {
+        match self {
+            LifecycleEvent::Create => lifecycle.on_create(),
+            LifecycleEvent::Visible => lifecycle.on_visible(),
+            LifecycleEvent::Hidden => lifecycle.on_hidden(),
+            LifecycleEvent::Dispose => lifecycle.on_dispose(),
+        }
+    }
+}
+
+/// Runs a lifecycle callback of `view_model` and its nested ViewModels, unless it panicked while locked.
+fn run_lifecycle(view_model: &RwLock<dyn ViewModelLike>, event: LifecycleEvent) {
+    if let Ok(mut vm) = view_model.write() {
+        vm.run_lifecycle(event);
+    }
+}
+
+/// The registered ViewModels, as watched by [`request_repaint_on_change`].
+#[derive(Clone, Default)]
+struct Registry {
+    view_models: Vec<Registration>,
+    policy: RepaintPolicy,
+    metrics: Arc<Mutex<RepaintMetrics>>,
+    hub: ChangeHub,
+}
+
+#[derive(Clone)]
+struct Registration {
+    view_model: Weak<RwLock<dyn ViewModelLike>>,
+    viewport: ViewportId,
+    visible: bool,
+    /// The shortest time between two repaints for the ViewModel, `None` if not limited.
+    min_interval: Option<Duration>,
+}
+
+impl Registration {
+    fn key(&self) -> usize {
+        view_model_key(&self.view_model)
+    }
+}
+
+/// Identifies a registered ViewModel, in the registry and in the [`ChangeHub`].
+fn view_model_key(view_model: &Weak<RwLock<dyn ViewModelLike>>) -> usize {
+    view_model.as_ptr().cast::<()>() as usize
+}
+
+/// What [`request_repaint_on_change`] did with the changes of the ViewModels, see [`ViewModels::repaint_metrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RepaintMetrics {
+    /// The changes seen.
+    pub notifications: u64,
+    /// The changes repainted for right away.
+    pub immediate: u64,
+    /// The changes repainted for later, to keep to the ViewModel's frame rate.
+    pub delayed: u64,
+    /// The changes left to a repaint that was already requested for later.
+    pub coalesced: u64,
 }
 
+/// Fires when a ViewModel is registered or evicted, or when any of them changes.
+///
+/// A change made between two waits is not lost, the next wait completes right away.
 #[derive(Clone)]
 pub struct ViewModelsChangeDetector {
-    rx: watch::Receiver<Vec<Weak<RwLock<dyn ViewModelLike>>>>,
+    rx: watch::Receiver<Registry>,
+    changes: watch::Receiver<u64>,
+    /// The epoch of the [`ChangeHub`] the last wait completed for, shared with the clones.
+    seen: Arc<AtomicU64>,
 }
 
 impl ChangeDetector for ViewModelsChangeDetector {
     fn wait_for_change(&self) -> Pin<Box<dyn Future<Output = Option<()>> + Send>> {
         let mut this = self.clone();
+        this.rx.mark_unchanged();
+        let seen = this.seen.load(Ordering::Relaxed);
         Box::pin(async move {
-            // Create a list of the wait_for_change futures for all view models.
-            let list = this.rx.borrow_and_update().clone();
-
-            let list = list
-                .iter()
-                .filter_map(|weak| weak.upgrade())
-                .map(|vm| vm.read().unwrap().change_detector_boxed().wait_for_change())
-                .collect::<Vec<_>>();
-
-            if list.is_empty() {
-                this.rx.changed().await.ok()
-            } else {
-                tokio::select! {
-                    res = this.rx.changed() => {
-                        res.ok()
-                    }
-                    (res, _, _) = futures::future::select_all(list) => {
-                        res
-                    }
-                }
-            }
+            let registered = pin!(this.rx.changed());
+            let changed = pin!(async {
+                let epoch = *this.changes.wait_for(|&epoch| epoch != seen).await?;
+                this.seen.fetch_max(epoch, Ordering::Relaxed);
+                Ok(())
+            });
+            futures::future::select(registered, changed)
+                .await
+                .factor_first()
+                .0
+                .ok()
         })
     }
 }
 
+/// Which ViewModels [`request_repaint_on_change`] repaints for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RepaintPolicy {
+    /// Repaints the viewport of any ViewModel that changes.
+    #[default]
+    All,
+    /// Only repaints for ViewModels rendered during the last frame, changes to hidden ones are latched
+    /// when something else repaints.
+    VisibleOnly,
+}
+
+/// The ViewModels updated by a [`ViewModels::latch_values`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeSet {
+    /// The frame started by the latch.
+    pub frame: u64,
+    pub view_models: Vec<ViewModelChange>,
+}
+
+/// A ViewModel updated by a latch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViewModelChange {
+    /// The id of the [`egui::Ui`] the ViewModel was fetched in.
+    pub id: Id,
+    pub type_name: &'static str,
+    /// The names of the fields that picked up a new value.
+    pub fields: Vec<&'static str>,
+}
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.view_models.is_empty()
+    }
+
+    /// The change of the ViewModel of type `T` fetched with `id`, `None` if it did not change.
+    pub fn get<T: ViewModel>(&self, id: Id) -> Option<&ViewModelChange> {
+        self.of_type::<T>().find(|change| change.id == id)
+    }
+
+    /// The changes of every ViewModel of type `T`.
+    pub fn of_type<T: ViewModel>(&self) -> impl Iterator<Item = &ViewModelChange> {
+        let type_name = std::any::type_name::<T>();
+        self.view_models
+            .iter()
+            .filter(move |change| change.type_name == type_name)
+    }
+
+    /// Returns true if a ViewModel of type `T` changed.
+    pub fn contains<T: ViewModel>(&self) -> bool {
+        self.of_type::<T>().next().is_some()
+    }
+
+    /// Returns true if `field` changed in a ViewModel of type `T`.
+    pub fn field_changed<T: ViewModel>(&self, field: &str) -> bool {
+        self.of_type::<T>()
+            .any(|change| change.fields.contains(&field))
+    }
+}
+
+/// When [`ViewModels::latch_values`] drops a ViewModel that is no longer fetched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Keeps every ViewModel until [`ViewModels`] is dropped.
+    #[default]
+    Never,
+    /// Evicts a ViewModel after it was not fetched for this many frames.
+    AfterFrames(u64),
+    /// Evicts a ViewModel that was not fetched during the last frame and for at least this long.
+    AfterDuration(Duration),
+}
+
 #[derive(Clone, Default)]
-pub struct ViewModels(Arc<Mutex<ViewModelsInner>>);
+pub struct ViewModels(pub(crate) Arc<Mutex<ViewModelsInner>>);
 
 impl ViewModels {
     pub fn change_detector(&self) -> ViewModelsChangeDetector {
+        let this = self.0.lock().unwrap();
+        let changes = this.hub.subscribe();
+        let seen = *changes.borrow();
         ViewModelsChangeDetector {
-            rx: self.0.lock().unwrap().tx.subscribe(),
+            rx: this.tx.subscribe(),
+            changes,
+            seen: Arc::new(AtomicU64::new(seen)),
         }
     }
 
-    pub fn latch_values(&mut self) {
-        self.0.lock().unwrap().view_models.retain(|vm| {
-            let mut keep = false;
+    /// Latches the latest values of every registered ViewModel.
+    ///
+    /// Writes made inside a [`with_mutable_snapshot`](crate::snapshot::with_mutable_snapshot) block are
+    /// latched all together or not at all.
+    ///
+    /// Each call starts a new frame, ViewModels that were not fetched during the previous one are hidden,
+    /// and evicted according to the [`EvictionPolicy`].
+    ///
+    /// Returns which ViewModels and fields picked up a new value, also kept until the next latch as
+    /// [`ViewModels::changes`].
+    ///
+    /// Does nothing while [traveling](ViewModels::travel_to) through a recording.
+    pub fn latch_values(&mut self) -> ChangeSet {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            target: "egui_mvvm::latch",
+            "latch_values",
+            frame = tracing::field::Empty,
+            changed = tracing::field::Empty,
+        )
+        .entered();
+
+        let barrier = snapshot::latch_barrier();
+
+        let mut this = self.0.lock().unwrap();
+        #[cfg(feature = "recorder")]
+        if this.traveling.is_some() {
+            return ChangeSet {
+                frame: this.frame,
+                view_models: Vec::new(),
+            };
+        }
+
+        let rendered = this.frame;
+        let policy = this.eviction_policy;
+        let now = Instant::now();
+        this.frame += 1;
+        let frame = this.frame;
+        #[cfg(feature = "tracing")]
+        span.record("frame", frame);
+        let mut changes = ChangeSet {
+            frame,
+            view_models: Vec::new(),
+        };
+
+        let ViewModelsInner {
+            view_models,
+            #[cfg(feature = "recorder")]
+            tape,
+            ..
+        } = &mut *this;
+        // Values published from now on are picked up by the next latch.
+        #[cfg(feature = "recorder")]
+        tape.set_frame(frame + 1);
+
+        let mut hidden = Vec::new();
+        let mut evicted = Vec::new();
+        view_models.retain_mut(|entry| {
+            let Some(view_model) = entry.view_model.upgrade() else {
+                return false;
+            };
+
+            let keep = match view_model.write() {
+                Ok(mut vm) => {
+                    if entry.visible && entry.last_seen_frame != rendered {
+                        entry.visible = false;
+                        hidden.push(view_model.clone());
+                    }
+
+                    if !entry.retain && entry.is_expired(policy, rendered, now) {
+                        false
+                    } else {
+                        vm.latch_state();
+                        // Fields replaced since the last latch are connected too.
+                        vm.connect(&entry.notifier);
+                        #[cfg(feature = "recorder")]
+                        vm.connect_recorder(&tape.view_model(entry.type_name, entry.id));
 
-            if let Some(vm) = vm.upgrade() {
-                if let Ok(mut vm) = vm.write() {
-                    keep = true;
-                    vm.latch_state();
+                        let fields = vm.changed_fields();
+                        if !fields.is_empty() {
+                            changes.view_models.push(ViewModelChange {
+                                id: entry.id,
+                                type_name: entry.type_name,
+                                fields,
+                            });
+                        }
+
+                        true
+                    }
                 }
-            }
+                Err(_) => false,
+            };
 
+            if !keep {
+                evicted.push(view_model);
+            }
             keep
-        })
+        });
+
+        #[cfg(feature = "tracing")]
+        span.record("changed", changes.view_models.len());
+
+        if !evicted.is_empty() {
+            this.reindex();
+        }
+        if !hidden.is_empty() || !evicted.is_empty() {
+            this.publish_registry();
+        }
+        this.changes = changes.clone();
+
+        // Lifecycle callbacks may write state or fetch ViewModels, so they run with nothing locked.
+        drop(this);
+        drop(barrier);
+        for view_model in hidden {
+            run_lifecycle(&view_model, LifecycleEvent::Hidden);
+        }
+        for view_model in evicted {
+            run_lifecycle(&view_model, LifecycleEvent::Dispose);
+        }
+
+        changes
+    }
+
+    /// Sets when ViewModels that are no longer fetched are dropped, [`EvictionPolicy::Never`] by default.
+    pub fn set_eviction_policy(&self, policy: EvictionPolicy) {
+        self.0.lock().unwrap().eviction_policy = policy;
+    }
+
+    /// Sets which ViewModels [`request_repaint_on_change`] repaints for, [`RepaintPolicy::All`] by default.
+    pub fn set_repaint_policy(&self, policy: RepaintPolicy) {
+        let mut this = self.0.lock().unwrap();
+        this.repaint_policy = policy;
+        this.publish_registry();
+    }
+
+    /// Limits the repaints requested for changes of ViewModels without their own `max_fps`, unlimited by default.
+    ///
+    /// Changes arriving faster are coalesced into a single repaint, requested with
+    /// [`request_repaint_after_for`](egui::Context::request_repaint_after_for).
+    pub fn set_max_fps(&self, max_fps: Option<f32>) {
+        let mut this = self.0.lock().unwrap();
+        this.max_fps = max_fps;
+        this.publish_registry();
     }
 
-    pub fn add<T: ViewModel>(&self, vm: &ViewModelHandle<T>) {
+    /// Counts the changes seen by [`request_repaint_on_change`], and how they were repainted.
+    pub fn repaint_metrics(&self) -> RepaintMetrics {
+        *self.0.lock().unwrap().repaint_metrics.lock().unwrap()
+    }
+
+    /// Drops every ViewModel, aborting their tasks, and stops [`install_repaint_on_change`].
+    pub fn shutdown(&self) {
+        let mut this = self.0.lock().unwrap();
+        let view_models = std::mem::take(&mut this.view_models);
+        this.index.clear();
+        let repaint_task = this.repaint_task.take();
+        this.publish_registry();
+        drop(this);
+
+        for entry in &view_models {
+            if let Some(view_model) = entry.view_model.upgrade() {
+                run_lifecycle(&view_model, LifecycleEvent::Dispose);
+            }
+        }
+        drop(view_models);
+        drop(repaint_task);
+    }
+
+    /// Unregisters the ViewModel of type `T` fetched with `id`, running its `on_dispose`.
+    ///
+    /// Returns the ViewModel if it was registered, it is dropped with the returned handle. The next fetch
+    /// with `id` creates a new one.
+    pub fn remove<T: ViewModel>(&self, id: Id) -> Option<ViewModelHandle<T>> {
+        let mut this = self.0.lock().unwrap();
+        let index = this.position(id, TypeId::of::<T>())?;
+        let entry = this.view_models.remove(index);
+        this.reindex();
+        this.publish_registry();
+        drop(this);
+
+        let view_model = entry.view_model.upgrade()?;
+        run_lifecycle(&view_model, LifecycleEvent::Dispose);
+        entry
+            .handle
+            .clone()
+            .downcast::<RwLock<T>>()
+            .ok()
+            .map(ViewModelHandle)
+    }
+
+    /// The number of frames started by [`ViewModels::latch_values`].
+    pub fn frame(&self) -> u64 {
+        self.0.lock().unwrap().frame
+    }
+
+    /// What the latest [`ViewModels::latch_values`] picked up, for views that only react to changes:
+    ///
+    /// ```ignore
+    /// let changes = ctx.memory_mut(|mem| mem.view_models()).changes();
+    /// if changes.field_changed::<ChatViewModel>("messages") {
+    ///     ui.scroll_to_cursor(Some(Align::BOTTOM));
+    /// }
+    /// ```
+    pub fn changes(&self) -> ChangeSet {
+        self.0.lock().unwrap().changes.clone()
+    }
+
+    /// Starts recording the values published by the ViewModels declared with `#[viewmodel(record)]`,
+    /// dropping the previous recording.
+    ///
+    /// The latched values of each ViewModel are recorded when recording starts, or when it is registered, then
+    /// every value its fields publish, with the task that published it.
+    #[cfg(feature = "recorder")]
+    pub fn start_recording(&self) {
+        self.stop_travel();
+        let this = self.0.lock().unwrap();
+        this.tape.start(this.frame + 1);
+        for entry in &this.view_models {
+            if let Some(vm) = entry.view_model.upgrade() {
+                let vm = vm.read().unwrap();
+                let recorder = this.tape.view_model(entry.type_name, entry.id);
+                recorder.baseline(this.frame, &*vm);
+            }
+        }
+    }
+
+    /// Stops recording, coming back from time travel, and returns what was recorded.
+    #[cfg(feature = "recorder")]
+    pub fn stop_recording(&self) -> Option<Recording> {
+        self.stop_travel();
+        self.0.lock().unwrap().tape.stop()
+    }
+
+    #[cfg(feature = "recorder")]
+    pub fn is_recording(&self) -> bool {
+        self.0.lock().unwrap().tape.is_recording()
+    }
+
+    /// A copy of what was recorded so far.
+    #[cfg(feature = "recorder")]
+    pub fn recording(&self) -> Option<Recording> {
+        self.0.lock().unwrap().tape.recording()
+    }
+
+    /// Travels to `frame` of the current recording, see [`ViewModels::travel_through`].
+    #[cfg(feature = "recorder")]
+    pub fn travel_to(&self, frame: u64) {
+        if let Some(recording) = self.recording() {
+            self.travel_through(&recording, frame);
+        }
+    }
+
+    /// Latches the values every ViewModel had at `frame` of `recording`, and pauses latching until
+    /// [`ViewModels::stop_travel`].
+    ///
+    /// Values are replayed into the latched values only, nothing is published: tasks keep seeing the latest
+    /// values, and the values they publish meanwhile are recorded and latched once traveling stops.
+    #[cfg(feature = "recorder")]
+    pub fn travel_through(&self, recording: &Recording, frame: u64) {
+        let mut this = self.0.lock().unwrap();
+        replay_all(&this.view_models, recording, frame);
+        this.traveling = Some(frame);
+    }
+
+    /// Latches the latest published values again and resumes latching.
+    #[cfg(feature = "recorder")]
+    pub fn stop_travel(&self) {
         let mut this = self.0.lock().unwrap();
-        this.tx.send_modify(|v| {
-            v.push(Arc::downgrade(&vm.0) as Weak<_>);
+        if this.traveling.take().is_some() {
+            // Nothing is recorded, so every field latches its latest published value.
+            replay_all(&this.view_models, &Recording::default(), u64::MAX);
+        }
+    }
+
+    /// The frame shown by [`ViewModels::travel_to`], `None` when not traveling.
+    #[cfg(feature = "recorder")]
+    pub fn traveling(&self) -> Option<u64> {
+        self.0.lock().unwrap().traveling
+    }
+
+    /// Registers `vm` as the ViewModel of type `T` fetched with `id`, keeping it alive until evicted.
+    pub fn add<T: ViewModel>(&self, id: Id, vm: &ViewModelHandle<T>) {
+        let view_model = Arc::downgrade(&vm.0) as Weak<RwLock<dyn ViewModelLike>>;
+        let mut this = self.0.lock().unwrap();
+        let notifier = this.hub.notifier(view_model_key(&view_model));
+        let (retain, max_fps) = {
+            let vm = vm.get();
+            vm.set_names();
+            vm.connect(&notifier);
+            #[cfg(feature = "recorder")]
+            {
+                let recorder = this.tape.view_model(std::any::type_name::<T>(), id);
+                vm.connect_recorder(&recorder);
+                if this.tape.is_recording() {
+                    recorder.baseline(this.frame, &*vm);
+                }
+            }
+            (vm.retain(), vm.max_fps())
+        };
+        let frame = this.frame;
+        let index = this.view_models.len();
+        this.index.insert((id, TypeId::of::<T>()), index);
+        this.view_models.push(ViewModelEntry {
+            id,
+            type_name: std::any::type_name::<T>(),
+            type_id: TypeId::of::<T>(),
+            view_model,
+            last_seen_frame: frame,
+            last_seen_at: Instant::now(),
+            visible: false,
+            viewport: ViewportId::ROOT,
+            layer: LayerId::background(),
+            retain,
+            max_fps,
+            notifier,
+            handle: vm.0.clone() as Arc<dyn Any + Send + Sync>,
         });
+        this.publish_registry();
+    }
+
+    /// Returns the ViewModel of type `T` fetched with `id`, if it was not evicted.
+    pub fn get<T: ViewModel>(&self, id: Id) -> Option<ViewModelHandle<T>> {
+        let mut this = self.0.lock().unwrap();
+        let index = this.position(id, TypeId::of::<T>())?;
 
-        let new = this.tx.borrow().clone();
-        this.view_models = new;
+        this.view_models[index]
+            .handle
+            .clone()
+            .downcast::<RwLock<T>>()
+            .ok()
+            .map(ViewModelHandle)
+    }
+
+    /// Marks the ViewModel of type `T` fetched with `id` as rendered this frame, in `layer` of `viewport`.
+    ///
+    /// Returns true if it was not visible before.
+    fn mark_seen<T: ViewModel>(&self, id: Id, viewport: ViewportId, layer: LayerId) -> bool {
+        let mut this = self.0.lock().unwrap();
+        let frame = this.frame;
+        let Some(index) = this.position(id, TypeId::of::<T>()) else {
+            return false;
+        };
+
+        let entry = &mut this.view_models[index];
+        entry.last_seen_frame = frame;
+        entry.last_seen_at = Instant::now();
+        entry.layer = layer;
+        let moved = std::mem::replace(&mut entry.viewport, viewport) != viewport;
+        let shown = !std::mem::replace(&mut entry.visible, true);
+
+        if moved || shown {
+            this.publish_registry();
+        }
+        shown
+    }
+
+    /// Sets where `#[viewmodel(persist)]` ViewModels are restored from and saved to.
+    #[cfg(feature = "persistence")]
+    pub fn set_storage(&self, storage: impl Storage + 'static) {
+        self.0.lock().unwrap().storage = Some(Box::new(storage));
+    }
+
+    /// Saves the latched state of every persisted ViewModel and flushes the storage, returning its error.
+    #[cfg(feature = "persistence")]
+    pub fn save(&self) -> std::io::Result<()> {
+        let mut this = self.0.lock().unwrap();
+        let ViewModelsInner {
+            view_models,
+            storage,
+            ..
+        } = &mut *this;
+        let Some(storage) = storage else {
+            return Ok(());
+        };
+
+        for entry in view_models.iter() {
+            let Some(vm) = entry.view_model.upgrade() else {
+                continue;
+            };
+
+            if let Some(state) = vm.read().unwrap().persist() {
+                storage.set_string(&storage_key(entry.type_name, entry.id), state);
+            }
+        }
+
+        storage.flush()
+    }
+
+    #[cfg(feature = "persistence")]
+    fn restore<T: ViewModel>(&self, id: Id, vm: &mut T) {
+        let this = self.0.lock().unwrap();
+        let Some(storage) = &this.storage else {
+            return;
+        };
+
+        if let Some(state) = storage.get_string(&storage_key(std::any::type_name::<T>(), id)) {
+            vm.restore(&state);
+        }
     }
 }
 
 #[derive(Default)]
 pub struct ViewModelsInner {
-    pub view_models: Vec<Weak<RwLock<dyn ViewModelLike>>>,
-    tx: watch::Sender<Vec<Weak<RwLock<dyn ViewModelLike>>>>,
+    pub view_models: Vec<ViewModelEntry>,
+    /// The position of each entry of `view_models`, by id and type, for fetching in constant time.
+    index: HashMap<(Id, TypeId), usize>,
+    tx: watch::Sender<Registry>,
+    frame: u64,
+    /// Runs [`request_repaint_on_change`] once installed, dropping it stops the task.
+    repaint_task: Option<TaskPool>,
+    pub(crate) latch_installed: bool,
+    eviction_policy: EvictionPolicy,
+    repaint_policy: RepaintPolicy,
+    /// The default of [`ViewModelEntry::max_fps`].
+    max_fps: Option<f32>,
+    repaint_metrics: Arc<Mutex<RepaintMetrics>>,
+    /// Signaled by the fields of every ViewModel.
+    hub: ChangeHub,
+    /// Returned by the latest latch.
+    changes: ChangeSet,
+    #[cfg(feature = "persistence")]
+    storage: Option<Box<dyn Storage>>,
+    /// Written to by the recorded fields of every ViewModel.
+    #[cfg(feature = "recorder")]
+    tape: Tape,
+    /// The frame shown by time travel, latching is paused meanwhile.
+    #[cfg(feature = "recorder")]
+    traveling: Option<u64>,
+}
+
+impl ViewModelsInner {
+    /// The position of the entry of type `type_id` fetched with `id`.
+    fn position(&mut self, id: Id, type_id: TypeId) -> Option<usize> {
+        let matches = |entry: &ViewModelEntry| entry.id == id && entry.type_id == type_id;
+        let index = self.index.get(&(id, type_id)).copied();
+        if index.is_some_and(|index| self.view_models.get(index).is_some_and(matches)) {
+            return index;
+        }
+
+        // `view_models` is public, the index is rebuilt if it was changed from outside.
+        if index.is_some() || self.index.len() != self.view_models.len() {
+            self.reindex();
+            return self
+                .index
+                .get(&(id, type_id))
+                .copied()
+                .filter(|&index| matches(&self.view_models[index]));
+        }
+        None
+    }
+
+    /// Rebuilds the index after entries were removed.
+    fn reindex(&mut self) {
+        self.index = self
+            .view_models
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| ((entry.id, entry.type_id), index))
+            .collect();
+    }
+
+    /// Publishes the registered ViewModels, with their viewport and visibility, to the repaint task.
+    fn publish_registry(&mut self) {
+        let view_models = self
+            .view_models
+            .iter()
+            .map(|entry| Registration {
+                view_model: entry.view_model.clone(),
+                viewport: entry.viewport,
+                visible: entry.visible,
+                min_interval: entry
+                    .max_fps
+                    .or(self.max_fps)
+                    .filter(|fps| *fps > 0.0)
+                    .map(|fps| Duration::from_secs_f32(1.0 / fps)),
+            })
+            .collect();
+        self.tx.send_replace(Registry {
+            view_models,
+            policy: self.repaint_policy,
+            metrics: self.repaint_metrics.clone(),
+            hub: self.hub.clone(),
+        });
+    }
+}
+
+#[cfg(feature = "recorder")]
+fn replay_all(view_models: &[ViewModelEntry], recording: &Recording, frame: u64) {
+    for entry in view_models {
+        if let Some(vm) = entry.view_model.upgrade() {
+            let mut vm = vm.write().unwrap();
+            recording.replay_dyn(frame, entry.type_name, entry.id.value(), &mut *vm);
+        }
+    }
+}
+
+pub struct ViewModelEntry {
+    /// The id of the [`egui::Ui`] the ViewModel was fetched in.
+    pub id: Id,
+    pub type_name: &'static str,
+    type_id: TypeId,
+    pub view_model: Weak<RwLock<dyn ViewModelLike>>,
+    /// The last frame the ViewModel was fetched in.
+    pub last_seen_frame: u64,
+    pub last_seen_at: Instant,
+    pub visible: bool,
+    /// The viewport the ViewModel was last fetched in, repainted when it changes.
+    pub viewport: ViewportId,
+    /// The layer the ViewModel was last fetched in.
+    pub layer: LayerId,
+    /// Set by `#[viewmodel(retain)]`, the ViewModel is never evicted.
+    pub retain: bool,
+    /// Set by `#[viewmodel(max_fps = 30)]`, see [`ViewModels::set_max_fps`].
+    pub max_fps: Option<f32>,
+    notifier: ChangeNotifier,
+    /// Keeps the ViewModel alive, as the `Arc<RwLock<T>>` of its concrete type.
+    handle: Arc<dyn Any + Send + Sync>,
+}
+
+impl Drop for ViewModelEntry {
+    /// Values published by the states of an evicted ViewModel, through handles kept by tasks or other
+    /// ViewModels, no longer signal the [`ChangeHub`].
+    fn drop(&mut self) {
+        self.notifier.disconnect();
+    }
+}
+
+impl ViewModelEntry {
+    /// The number of values published to the fields of the ViewModel, counting the ones a latch picks up
+    /// together.
+    pub fn changes(&self) -> u64 {
+        self.notifier.notifications()
+    }
+
+    fn is_expired(&self, policy: EvictionPolicy, rendered: u64, now: Instant) -> bool {
+        let unseen_frames = rendered.saturating_sub(self.last_seen_frame);
+
+        match policy {
+            EvictionPolicy::Never => false,
+            EvictionPolicy::AfterFrames(frames) => unseen_frames >= frames.max(1),
+            EvictionPolicy::AfterDuration(duration) => {
+                unseen_frames > 0 && now.duration_since(self.last_seen_at) >= duration
+            }
+        }
+    }
 }
 
 pub trait EguiViewModelExt {
@@ -128,22 +1000,24 @@ impl EguiViewModelExt for &mut egui::Ui {
 
     fn fetch_model_or_insert<V: ViewModel, F: FnOnce() -> V>(self, f: F) -> ViewModelHandle<V> {
         let id = self.allocate_new_ui(UiBuilder::new(), |ui| ui.id()).inner;
-        let mut inserted = false;
-        let vm = self.memory_mut(|mem| {
-            let vm = mem
-                .data
-                .get_temp_mut_or_insert_with::<ViewModelHandle<V>>(id, || {
-                    inserted = true;
-                    ViewModelHandle(Arc::new(RwLock::new(f())))
-                })
-                .clone();
+        let vms = self.memory_mut(|mem| mem.view_models());
+
+        let vm = vms.get::<V>(id).unwrap_or_else(|| {
+            #[cfg(all(feature = "web", target_arch = "wasm32"))]
+            install_repaint_on_change(self.ctx());
+
+            let vm = ViewModelHandle::new(f());
+            #[cfg(feature = "persistence")]
+            vms.restore(id, &mut *vm.get_mut());
+            vms.add(id, &vm);
+
+            vm.get_mut().run_lifecycle(LifecycleEvent::Create);
 
             vm
         });
 
-        if inserted {
-            let vms = self.memory_mut(|mem| mem.view_models());
-            vms.add(&vm);
+        if vms.mark_seen::<V>(id, self.ctx().viewport_id(), self.layer_id()) {
+            vm.get_mut().run_lifecycle(LifecycleEvent::Visible);
         }
 
         vm
@@ -181,11 +1055,20 @@ impl<V> ViewModelMutRef<'_, V> {
 }
 
 impl<V> ViewModelHandle<V> {
-    pub fn get(&self) -> ViewModelRef<V> {
+    pub fn new(view_model: V) -> Self {
+        Self(Arc::new(RwLock::new(view_model)))
+    }
+
+    /// Returns true if `self` and `other` are handles to the same ViewModel.
+    pub(crate) fn same(&self, other: &ViewModelHandle<V>) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    pub fn get(&self) -> ViewModelRef<'_, V> {
         ViewModelRef(self.0.read().unwrap(), self.clone())
     }
 
-    pub fn get_mut(&self) -> ViewModelMutRef<V> {
+    pub fn get_mut(&self) -> ViewModelMutRef<'_, V> {
         ViewModelMutRef(self.0.write().unwrap(), self.clone())
     }
 }
@@ -217,19 +1100,518 @@ impl<V> DerefMut for ViewModelMutRef<'_, V> {
     }
 }
 
-pub async fn request_repaint_on_change(ctx: egui::Context) -> ! {
+/// Spawns [`request_repaint_on_change`] on the default executor, once per context.
+///
+/// Called when the first ViewModel is created on `wasm32` with the `web` feature.
+pub fn install_repaint_on_change(ctx: &egui::Context) {
     let view_models = ctx.memory_mut(|mem| mem.view_models());
+    let mut this = view_models.0.lock().unwrap();
+    if this.repaint_task.is_some() {
+        return;
+    }
 
-    // Send Repaint Requests when the ViewModel changes.
-    let mut change_detector = view_models.change_detector();
+    let task_pool = TaskPool::new();
     let ctx = ctx.clone();
+    task_pool.spawn(async move { request_repaint_on_change(ctx).await });
+    this.repaint_task = Some(task_pool);
+}
+
+/// What [`request_repaint_on_change`] waits for.
+enum RepaintEvent {
+    /// ViewModels signaled the [`ChangeHub`].
+    Changed,
+    /// ViewModels were registered, evicted, hidden or shown.
+    Registry,
+    /// The [`ViewModels`] were dropped.
+    Closed,
+}
+
+/// The registered ViewModels that the [`RepaintPolicy`] repaints for, by key.
+fn repaint_targets(registry: &Registry) -> HashMap<usize, Registration> {
+    registry
+        .view_models
+        .iter()
+        .filter(|registration| registry.policy == RepaintPolicy::All || registration.visible)
+        .map(|registration| (registration.key(), registration.clone()))
+        .collect()
+}
+
+/// Repaints the viewport of a ViewModel when it changes, following the [`RepaintPolicy`] and the
+/// ViewModel's `max_fps`.
+///
+/// Waits on the [`ChangeHub`] of the [`ViewModels`], so a change costs the same however many ViewModels
+/// are registered.
+pub async fn request_repaint_on_change(ctx: egui::Context) -> ! {
+    let subscribe = |ctx: &egui::Context| {
+        ctx.memory_mut(|mem| {
+            let this = mem.view_models();
+            let this = this.0.lock().unwrap();
+            (this.tx.subscribe(), this.hub.subscribe())
+        })
+    };
+
+    let (mut rx, mut changes) = subscribe(&ctx);
+    let mut targets = repaint_targets(&rx.borrow_and_update());
+    // When the last repaint was requested for each rate limited ViewModel, in the future if delayed.
+    let mut repainted_at = HashMap::<usize, Instant>::new();
     loop {
-        if change_detector.wait_for_change().await.is_none() {
-            ctx.memory_mut(|mem| {
-                change_detector = mem.view_models().change_detector();
-            })
+        let event = match futures::future::select(pin!(rx.changed()), pin!(changes.changed())).await
+        {
+            Either::Left((Ok(()), _)) => RepaintEvent::Registry,
+            Either::Right((Ok(()), _)) => RepaintEvent::Changed,
+            Either::Left((Err(_), _)) | Either::Right((Err(_), _)) => RepaintEvent::Closed,
+        };
+
+        match event {
+            RepaintEvent::Changed => {
+                let registry = rx.borrow();
+                let mut metrics = registry.metrics.lock().unwrap();
+                let now = Instant::now();
+                for key in registry.hub.take_changed() {
+                    // Hidden or evicted since.
+                    let Some(registration) = targets.get(&key) else {
+                        continue;
+                    };
+                    metrics.notifications += 1;
+
+                    let Some(min_interval) = registration.min_interval else {
+                        metrics.immediate += 1;
+                        ctx.request_repaint_of(registration.viewport);
+                        continue;
+                    };
+
+                    match repainted_at.get(&key) {
+                        Some(&at) if at > now => metrics.coalesced += 1,
+                        Some(&at) if now < at + min_interval => {
+                            metrics.delayed += 1;
+                            let at = at + min_interval;
+                            repainted_at.insert(key, at);
+                            ctx.request_repaint_after_for(at - now, registration.viewport);
+                        }
+                        _ => {
+                            metrics.immediate += 1;
+                            repainted_at.insert(key, now);
+                            ctx.request_repaint_of(registration.viewport);
+                        }
+                    }
+                }
+            }
+            RepaintEvent::Registry => {
+                targets = repaint_targets(&rx.borrow());
+                repainted_at.retain(|key, _| targets.contains_key(key));
+            }
+            RepaintEvent::Closed => {
+                (rx, changes) = subscribe(&ctx);
+                targets = repaint_targets(&rx.borrow_and_update());
+                repainted_at.clear();
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate as egui_mvvm;
+    #[cfg(feature = "recorder")]
+    use crate::derived_state::DerivedState;
+    use crate::task_pool::TaskError;
+    use crate::testing::TestHarness;
+    use crate::val_state::ValState;
+    use crate::view_model;
+    use futures::FutureExt;
+
+    view_model! {
+        #[viewmodel(default, lifecycle)]
+        struct FeedViewModel {
+            events: ValState<Vec<&'static str>> = Vec::new(),
+        }
+
+        #[viewmodel(default, retain)]
+        struct PinnedViewModel {
+            count: ValState<u32> = 0,
+        }
+
+        #[viewmodel(default)]
+        struct FormViewModel {
+            name: ValState<String> = String::new(),
+            age: ValState<u32> = 0,
+        }
+
+        #[viewmodel(default, max_fps = 10.0)]
+        struct DownloadViewModel {
+            progress: ValState<f32> = 0.0,
         }
 
-        ctx.request_repaint();
+        #[viewmodel(default)]
+        struct SearchViewModel {
+            query: ValState<String> = String::new(),
+        }
+
+        #[viewmodel(default)]
+        struct InboxViewModel {
+            search: SearchViewModel = SearchViewModel::default().with_task_pool(task_pool.clone()),
+            threads: Vec<SearchViewModel> = vec![SearchViewModel::default(), SearchViewModel::default()],
+            unread: ValState<u32> = 0,
+        }
+    }
+
+    #[cfg(feature = "persistence")]
+    view_model! {
+        #[viewmodel(default)]
+        struct DraftViewModel {
+            #[persist]
+            text: ValState<String> = String::new(),
+            focused: ValState<bool> = false,
+        }
+    }
+
+    #[cfg(feature = "recorder")]
+    view_model! {
+        #[viewmodel(default, record)]
+        struct CounterViewModel {
+            count: ValState<u32> = 0,
+            doubled: DerivedState<u32> = DerivedState::new(&count, |count| count * 2),
+        }
+    }
+
+    impl FeedViewModel {
+        fn log(&self, event: &'static str) {
+            self.events.send_modify(|events| events.push(event));
+        }
+    }
+
+    impl ViewModelLifecycle for FeedViewModel {
+        fn on_create(&mut self) {
+            self.log("create");
+        }
+
+        fn on_visible(&mut self) {
+            self.log("visible");
+        }
+
+        fn on_hidden(&mut self) {
+            self.log("hidden");
+        }
+
+        fn on_dispose(&mut self) {
+            self.log("dispose");
+        }
+    }
+
+    #[test]
+    fn lifecycle_callbacks_run_in_order() {
+        let mut harness = TestHarness::new();
+        let vm = harness.run(|ui| ui.fetch_model::<FeedViewModel>());
+        let events = vm.get().events.handle();
+        assert_eq!(events.latest_value(), ["create", "visible"]);
+
+        harness.run(|ui| ui.fetch_model::<FeedViewModel>());
+        harness.run(|_| ());
+        assert_eq!(events.latest_value(), ["create", "visible"]);
+
+        // Not fetched during the previous frame.
+        harness.run(|_| ());
+        assert_eq!(events.latest_value(), ["create", "visible", "hidden"]);
+
+        harness.run(|ui| ui.fetch_model::<FeedViewModel>());
+        harness.view_models().shutdown();
+        assert_eq!(
+            events.latest_value(),
+            ["create", "visible", "hidden", "visible", "dispose"]
+        );
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn persisted_fields_are_restored_in_a_new_context() {
+        use crate::persistence::FileStorage;
+
+        let path =
+            std::env::temp_dir().join(format!("egui-mvvm-{}-draft.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut harness = TestHarness::new();
+        harness
+            .view_models()
+            .set_storage(FileStorage::new(&path).unwrap());
+        let vm = harness.run(|ui| ui.fetch_model::<DraftViewModel>());
+        vm.get().text.send_value("draft".to_string());
+        vm.get().focused.send_value(true);
+        harness.run(|ui| ui.fetch_model::<DraftViewModel>());
+        harness.view_models().save().unwrap();
+        drop(harness);
+
+        let mut harness = TestHarness::new();
+        harness
+            .view_models()
+            .set_storage(FileStorage::new(&path).unwrap());
+        let vm = harness.run(|ui| ui.fetch_model::<DraftViewModel>());
+        assert_eq!(vm.get().text.value(), "draft");
+        assert!(!*vm.get().focused.value());
+    }
+
+    #[test]
+    fn evicts_after_frames_unless_retained() {
+        let mut harness = TestHarness::new();
+        harness
+            .view_models()
+            .set_eviction_policy(EvictionPolicy::AfterFrames(2));
+        let (feed, pinned, events) = harness.run(|ui| {
+            let feed = ui.fetch_model::<FeedViewModel>();
+            let pinned = ui.fetch_model::<PinnedViewModel>();
+            let events = feed.get().events.handle();
+            (Arc::downgrade(&feed.0), Arc::downgrade(&pinned.0), events)
+        });
+
+        harness.run(|_| ());
+        harness.run(|_| ());
+        assert!(feed.upgrade().is_some());
+
+        harness.run(|_| ());
+        assert!(feed.upgrade().is_none());
+        assert!(pinned.upgrade().is_some());
+        assert_eq!(
+            events.latest_value(),
+            ["create", "visible", "hidden", "dispose"]
+        );
+    }
+
+    #[test]
+    fn evicts_after_duration() {
+        let mut harness = TestHarness::new();
+        harness
+            .view_models()
+            .set_eviction_policy(EvictionPolicy::AfterDuration(Duration::from_secs(5)));
+        let feed = harness.run(|ui| Arc::downgrade(&ui.fetch_model::<FeedViewModel>().0));
+
+        harness.run(|_| ());
+        harness.advance(Duration::from_secs(4));
+        harness.run(|_| ());
+        assert!(feed.upgrade().is_some());
+
+        harness.advance(Duration::from_secs(1));
+        harness.run(|_| ());
+        assert!(feed.upgrade().is_none());
+    }
+
+    #[test]
+    fn removed_view_model_is_disposed() {
+        let mut harness = TestHarness::new();
+        let vm = harness.run(|ui| ui.fetch_model::<FeedViewModel>());
+        let events = vm.get().events.handle();
+        let registered = harness.view_models().0.lock().unwrap().view_models[0].id;
+
+        let removed = harness.view_models().remove::<FeedViewModel>(registered);
+        assert!(removed.is_some());
+        assert!(
+            harness
+                .view_models()
+                .get::<FeedViewModel>(registered)
+                .is_none()
+        );
+        assert_eq!(events.latest_value(), ["create", "visible", "dispose"]);
+    }
+
+    #[test]
+    fn change_set_lists_changed_fields() {
+        let mut harness = TestHarness::new();
+        let (form, _feed) = harness.run(|ui| {
+            (
+                ui.fetch_model::<FormViewModel>(),
+                ui.fetch_model::<PinnedViewModel>(),
+            )
+        });
+        harness.latch();
+
+        form.get().age.send_value(42);
+        let changes = harness.latch();
+        assert_eq!(changes.view_models.len(), 1);
+        assert!(changes.contains::<FormViewModel>());
+        assert!(!changes.contains::<PinnedViewModel>());
+        assert!(changes.field_changed::<FormViewModel>("age"));
+        assert!(!changes.field_changed::<FormViewModel>("name"));
+        assert_eq!(changes.view_models[0].fields, ["age"]);
+        assert_eq!(harness.view_models().changes(), changes);
+        assert!(form.get().age.changed_this_frame());
+
+        assert!(harness.latch().is_empty());
+        assert!(!form.get().age.changed_this_frame());
+    }
+
+    #[test]
+    fn entry_counts_every_published_value() {
+        let mut harness = TestHarness::new();
+        let form = harness.run(|ui| ui.fetch_model::<FormViewModel>());
+        let changes = || harness.view_models().0.lock().unwrap().view_models[0].changes();
+        let before = changes();
+
+        form.get().age.send_value(1);
+        form.get().age.send_value(2);
+        form.get().name.send_value("Ada".to_string());
+        harness.latch();
+
+        assert_eq!(changes() - before, 3);
+    }
+
+    #[cfg(feature = "recorder")]
+    #[test]
+    fn travel_latches_recorded_values_without_publishing() {
+        let mut harness = TestHarness::new();
+        let counter = harness.run(|ui| ui.fetch_model::<CounterViewModel>());
+        harness.view_models().start_recording();
+
+        counter.get().count.send_value(1);
+        let frame = harness.latch().frame;
+        counter.get().count.send_value(2);
+        harness.latch();
+
+        harness.view_models().travel_to(frame);
+        assert_eq!(*counter.get().count.value(), 1);
+        assert_eq!(*counter.get().doubled.value(), 2);
+        assert_eq!(counter.get().count.latest_value(), 2);
+
+        counter.get().count.send_value(3);
+        harness.latch();
+        assert_eq!(*counter.get().count.value(), 1);
+
+        harness.view_models().stop_travel();
+        assert_eq!(*counter.get().count.value(), 3);
+        assert_eq!(*counter.get().doubled.value(), 6);
+        let recording = harness.view_models().recording().unwrap();
+        assert_eq!(recording.changes.last().unwrap().value, 3);
+    }
+
+    #[test]
+    fn collect_into_sends_every_item() {
+        let mut harness = TestHarness::new();
+        let vm = harness.run(|ui| ui.fetch_model::<FormViewModel>());
+        let _runtime = harness.enter();
+
+        let stream = futures::stream::iter(1..=3).then(|age| async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            age
+        });
+        let task = vm.get().collect_into(|this| &this.age, stream);
+        let mut ages = vm.get().age.to_stream();
+
+        harness.advance(Duration::from_secs(1));
+        assert_eq!(vm.get().age.latest_value(), 1);
+        harness.advance(Duration::from_secs(2));
+        assert_eq!(harness.block_on(task.join()), Ok(()));
+        assert_eq!(vm.get().age.latest_value(), 3);
+        assert_eq!(harness.block_on(ages.next()), Some(3));
+    }
+
+    #[test]
+    fn collect_into_stops_with_the_view_model() {
+        let mut harness = TestHarness::new();
+        let vm = harness.run(|ui| ui.fetch_model::<FormViewModel>());
+        let _runtime = harness.enter();
+
+        let task = vm
+            .get()
+            .collect_into(|this| &this.age, futures::stream::pending());
+        harness.yield_to_tasks();
+        assert!(!task.is_finished());
+
+        harness.view_models().shutdown();
+        drop(vm);
+        assert_eq!(harness.block_on(task.join()), Err(TaskError::Aborted));
+    }
+
+    #[test]
+    fn max_fps_coalesces_repaints() {
+        let mut harness = TestHarness::new();
+        let vm = harness.run(|ui| ui.fetch_model::<DownloadViewModel>());
+        let view_models = harness.view_models();
+        {
+            let _runtime = harness.enter();
+            install_repaint_on_change(harness.ctx());
+        }
+        harness.yield_to_tasks();
+
+        for progress in [0.1, 0.2, 0.3] {
+            vm.get().progress.send_value(progress);
+            harness.yield_to_tasks();
+        }
+        assert_eq!(
+            view_models.repaint_metrics(),
+            RepaintMetrics {
+                notifications: 3,
+                immediate: 1,
+                delayed: 1,
+                coalesced: 1,
+            }
+        );
+
+        harness.advance(Duration::from_millis(250));
+        vm.get().progress.send_value(0.4);
+        harness.yield_to_tasks();
+        assert_eq!(view_models.repaint_metrics().immediate, 2);
+    }
+
+    #[test]
+    fn nested_view_models_latch_and_notify_with_their_parent() {
+        let mut harness = TestHarness::new();
+        let vm = harness.run(|ui| ui.fetch_model::<InboxViewModel>());
+        harness.latch();
+        let detector = harness.view_models().change_detector();
+        let parent = ViewModel::change_detector(&*vm.get());
+        let _runtime = harness.enter();
+
+        let changed = (detector.wait_for_change(), parent.wait_for_change());
+        let task = vm.get().spawn_with_result(|this, _| async move {
+            this.search.query.send_value("mvvm".to_string());
+            this.threads[1].query.send_value("unread".to_string());
+        });
+        harness.block_on(task.join()).unwrap();
+        assert_eq!(changed.0.now_or_never(), Some(Some(())));
+        assert_eq!(changed.1.now_or_never(), Some(Some(())));
+
+        assert_eq!(vm.get().search.query.value(), "");
+        let changes = harness.latch();
+        assert_eq!(changes.view_models[0].fields, ["search", "threads"]);
+        assert_eq!(vm.get().search.query.value(), "mvvm");
+        assert_eq!(vm.get().threads[1].query.value(), "unread");
+        assert!(vm.get().threads[1].query.changed_this_frame());
+        assert!(!vm.get().threads[0].query.changed_this_frame());
+
+        // The child runs on the parent's pool, the other children on their own.
+        vm.get().search.spawn(|_| std::future::pending::<()>());
+        assert_eq!(vm.get().running_tasks(), 1);
+    }
+
+    #[test]
+    fn detector_keeps_changes_made_between_waits() {
+        let mut harness = TestHarness::new();
+        let vm = harness.run(|ui| ui.fetch_model::<FormViewModel>());
+        let detector = harness.view_models().change_detector();
+        let _runtime = harness.enter();
+
+        vm.get().age.send_value(1);
+        assert_eq!(detector.wait_for_change().now_or_never(), Some(Some(())));
+        assert!(detector.wait_for_change().now_or_never().is_none());
+
+        // Published while nobody waits.
+        vm.get().name.send_value("Ada".to_string());
+        assert_eq!(detector.wait_for_change().now_or_never(), Some(Some(())));
+        assert!(detector.wait_for_change().now_or_never().is_none());
+    }
+
+    #[test]
+    fn removed_view_model_stops_signaling() {
+        let mut harness = TestHarness::new();
+        let age = harness.run(|ui| ui.fetch_model::<FormViewModel>().get().age.handle());
+        let id = harness.view_models().0.lock().unwrap().view_models[0].id;
+        let detector = harness.view_models().change_detector();
+        let _runtime = harness.enter();
+
+        let vm = harness.view_models().remove::<FormViewModel>(id).unwrap();
+        let changed = detector.wait_for_change();
+        age.send_value(1);
+        vm.get().name.send_value("Ada".to_string());
+        assert_eq!(age.latest_value(), 1);
+        assert!(changed.now_or_never().is_none());
     }
 }
@@ -0,0 +1,509 @@
+use crate::list_state::{
+    ListDiff, ListItemId, ListState, ListStateChangeDetector, ListStateHandle,
+};
+use crate::notify::ChangeNotifier;
+use crate::view_model::{LifecycleEvent, ViewModel, ViewModelHandle, ViewModelLike};
+use crate::{ChangeDetector, Stateful, wait_for_any};
+use std::pin::Pin;
+use std::sync::OnceLock;
+
+/// A list of nested ViewModels, whose children are inserted and removed like the items of a [`ListState`].
+///
+/// Unlike a `Vec` of ViewModels, inserting or removing a child is published: it repaints the parent, shows
+/// up in its change set, and tasks and change detectors see the children present when they run. Children
+/// are latched, connected and named with their parent, and get its lifecycle callbacks.
+///
+/// ```ignore
+/// view_model! {
+///     #[viewmodel(default)]
+///     pub struct InboxViewModel {
+///         pub threads: ViewModelList<ThreadViewModel> = Vec::new(),
+///     }
+/// }
+///
+/// self.spawn(|this| async move {
+///     this.threads.push(ThreadViewModel::default());
+///     if let Some(thread) = this.threads.model(0) {
+///         thread.unread.send_value(0);
+///     }
+/// });
+/// ```
+pub struct ViewModelList<T> {
+    list: ListState<ViewModelHandle<T>>,
+    /// The name given by `view_model!`, for the children inserted after it.
+    name: OnceLock<&'static str>,
+    /// Set by the lifecycle callbacks of the parent, replayed to the children inserted after them.
+    created: bool,
+    visible: bool,
+}
+
+impl<T: ViewModel> Default for ViewModelList<T> {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl<T: ViewModel> ViewModelList<T> {
+    pub fn new(children: Vec<T>) -> Self {
+        Self {
+            list: ListState::new(children.into_iter().map(ViewModelHandle::new).collect()),
+            name: OnceLock::new(),
+            created: false,
+            visible: false,
+        }
+    }
+
+    /// Latches the latest list, then every child.
+    ///
+    /// Children inserted since the last latch get the lifecycle callbacks the list got so far, removed ones
+    /// get `on_dispose`.
+    pub fn latch_value(&mut self) {
+        let previous = self
+            .list
+            .has_changed()
+            .then(|| self.list.iter().cloned().collect::<Vec<_>>());
+        self.list.latch_value();
+        for child in self.list.iter() {
+            child.get_mut().latch_state();
+        }
+
+        let Some(previous) = previous else {
+            return;
+        };
+        for child in &previous {
+            if self.created && !self.list.iter().any(|latched| latched.same(child)) {
+                child.get_mut().run_lifecycle(LifecycleEvent::Dispose);
+            }
+        }
+        for child in self.list.iter() {
+            if previous.iter().any(|previous| previous.same(child)) {
+                continue;
+            }
+            if let Some(name) = self.name.get() {
+                child.get().set_field_names(name);
+            }
+            if self.created {
+                child.get_mut().run_lifecycle(LifecycleEvent::Create);
+            }
+            if self.visible {
+                child.get_mut().run_lifecycle(LifecycleEvent::Visible);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&ViewModelHandle<T>> {
+        self.list.get(index)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ViewModelHandle<T>> {
+        self.list.iter()
+    }
+
+    /// Identifies the child at `index` for as long as it stays in the list, e.g. to salt its `egui::Id`.
+    pub fn item_id(&self, index: usize) -> Option<ListItemId> {
+        self.list.item_id(index)
+    }
+
+    /// The insertions, removals and moves applied by the latest latch, see [`ListState::diff`].
+    pub fn diff(&self) -> &[ListDiff] {
+        self.list.diff()
+    }
+
+    /// Returns true if the latest latch inserted, removed or moved a child, or picked up a change of one.
+    pub fn changed_this_frame(&self) -> bool {
+        self.list.changed_this_frame()
+            || self
+                .list
+                .iter()
+                .any(|child| !child.get().changed_fields().is_empty())
+    }
+
+    pub fn latest_value(&self) -> Vec<ViewModelHandle<T>> {
+        self.list.latest_value()
+    }
+
+    pub fn send_value(&self, children: Vec<T>) {
+        self.list
+            .send_value(children.into_iter().map(ViewModelHandle::new).collect());
+    }
+
+    pub fn push(&self, child: T) {
+        self.list.push(ViewModelHandle::new(child));
+    }
+
+    /// Inserts `child` at `index` of the latest list, returns false if `index` is out of bounds.
+    pub fn insert(&self, index: usize, child: T) -> bool {
+        self.list.insert(index, ViewModelHandle::new(child))
+    }
+
+    pub fn remove(&self, index: usize) -> Option<ViewModelHandle<T>> {
+        self.list.remove(index)
+    }
+
+    pub fn move_item(&self, from: usize, to: usize) -> bool {
+        self.list.move_item(from, to)
+    }
+
+    pub fn clear(&self) {
+        self.list.clear();
+    }
+
+    pub fn change_detector(&self) -> ViewModelListChangeDetector<T> {
+        ViewModelListChangeDetector {
+            list: self.list.change_detector(),
+            children: self.list.handle(),
+        }
+    }
+
+    pub fn handle(&self) -> ViewModelListHandle<T> {
+        ViewModelListHandle {
+            list: self.list.handle(),
+        }
+    }
+}
+
+/// Fires when a child is inserted, removed or moved, or when any child of the latest list changes.
+pub struct ViewModelListChangeDetector<T> {
+    list: ListStateChangeDetector<ViewModelHandle<T>>,
+    children: ListStateHandle<ViewModelHandle<T>>,
+}
+
+impl<T> Clone for ViewModelListChangeDetector<T> {
+    fn clone(&self) -> Self {
+        Self {
+            list: self.list.clone(),
+            children: self.children.clone(),
+        }
+    }
+}
+
+impl<T: ViewModel> ChangeDetector for ViewModelListChangeDetector<T> {
+    fn wait_for_change(&self) -> Pin<Box<dyn Future<Output = Option<()>> + Send + 'static>> {
+        let mut changes = vec![self.list.wait_for_change()];
+        changes.extend(
+            self.children
+                .latest_value()
+                .iter()
+                .map(|child| child.get().change_detector_boxed().wait_for_change()),
+        );
+        wait_for_any(changes)
+    }
+}
+
+/// Inserts and removes the children of a [`ViewModelList`] from tasks, indices refer to the latest list.
+pub struct ViewModelListHandle<T> {
+    list: ListStateHandle<ViewModelHandle<T>>,
+}
+
+impl<T> Clone for ViewModelListHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            list: self.list.clone(),
+        }
+    }
+}
+
+impl<T: ViewModel> ViewModelListHandle<T> {
+    pub fn latest_value(&self) -> Vec<ViewModelHandle<T>> {
+        self.list.latest_value()
+    }
+
+    pub fn latest_len(&self) -> usize {
+        self.list.latest_len()
+    }
+
+    /// The model of the child at `index` of the latest list, to publish to its fields.
+    pub fn model(&self, index: usize) -> Option<T::Model> {
+        let child = self.list.latest_value().into_iter().nth(index)?;
+        Some(child.get().make_model())
+    }
+
+    /// The models of the children of the latest list.
+    pub fn models(&self) -> Vec<T::Model> {
+        self.list
+            .latest_value()
+            .iter()
+            .map(|child| child.get().make_model())
+            .collect()
+    }
+
+    pub fn send_value(&self, children: Vec<T>) {
+        self.list
+            .send_value(children.into_iter().map(ViewModelHandle::new).collect());
+    }
+
+    pub fn push(&self, child: T) {
+        self.list.push(ViewModelHandle::new(child));
+    }
+
+    pub fn insert(&self, index: usize, child: T) -> bool {
+        self.list.insert(index, ViewModelHandle::new(child))
+    }
+
+    pub fn remove(&self, index: usize) -> Option<ViewModelHandle<T>> {
+        self.list.remove(index)
+    }
+
+    pub fn move_item(&self, from: usize, to: usize) -> bool {
+        self.list.move_item(from, to)
+    }
+
+    pub fn clear(&self) {
+        self.list.clear();
+    }
+}
+
+impl<T: ViewModel> Stateful for ViewModelList<T> {
+    type ChangeDetector = ViewModelListChangeDetector<T>;
+    type Handle = ViewModelListHandle<T>;
+
+    fn latch(&mut self) {
+        self.latch_value()
+    }
+
+    fn handle(&self) -> Self::Handle {
+        self.handle()
+    }
+
+    fn change_detector(&self) -> Self::ChangeDetector {
+        self.change_detector()
+    }
+
+    fn set_name(&self, name: &'static str) {
+        Stateful::set_name(&self.list, name);
+        let _ = self.name.set(name);
+        for child in self.list.iter() {
+            child.get().set_field_names(name);
+        }
+    }
+
+    /// Connects the list and its latched children, children inserted later are connected by the next latch.
+    fn connect(&self, notifier: &ChangeNotifier) {
+        Stateful::connect(&self.list, notifier);
+        for child in self.list.iter() {
+            ViewModelLike::connect(&*child.get(), notifier);
+        }
+    }
+
+    fn changed_this_frame(&self) -> bool {
+        self.changed_this_frame()
+    }
+
+    fn run_lifecycle(&mut self, event: LifecycleEvent) {
+        match event {
+            LifecycleEvent::Create => self.created = true,
+            LifecycleEvent::Visible => self.visible = true,
+            LifecycleEvent::Hidden => self.visible = false,
+            LifecycleEvent::Dispose => {
+                self.created = false;
+                self.visible = false;
+            }
+        }
+        for child in self.list.iter() {
+            child.get_mut().run_lifecycle(event);
+        }
+    }
+}
+
+impl<T: ViewModel> From<Vec<T>> for ViewModelList<T> {
+    fn from(children: Vec<T>) -> Self {
+        ViewModelList::new(children)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate as egui_mvvm;
+    use crate::testing::TestHarness;
+    use crate::val_state::ValState;
+    use crate::view_model;
+    use crate::view_model::{ChangeSet, EguiViewModelExt, ViewModelLifecycle};
+    use futures::FutureExt;
+
+    view_model! {
+        #[viewmodel(default, lifecycle, debug)]
+        struct ThreadViewModel {
+            subject: ValState<String> = String::new(),
+            events: ValState<Vec<&'static str>> = Vec::new(),
+        }
+
+        #[viewmodel(default, debug)]
+        struct InboxViewModel {
+            pinned: ThreadViewModel = ThreadViewModel::default(),
+            threads: ViewModelList<ThreadViewModel> = vec![ThreadViewModel::default()],
+        }
+    }
+
+    impl ThreadViewModel {
+        fn log(&self, event: &'static str) {
+            self.events.send_modify(|events| events.push(event));
+        }
+    }
+
+    impl ViewModelLifecycle for ThreadViewModel {
+        fn on_create(&mut self) {
+            self.log("create");
+        }
+
+        fn on_visible(&mut self) {
+            self.log("visible");
+        }
+
+        fn on_hidden(&mut self) {
+            self.log("hidden");
+        }
+
+        fn on_dispose(&mut self) {
+            self.log("dispose");
+        }
+    }
+
+    /// Latches and draws a frame fetching the inbox, so it stays visible, returning what the latch changed.
+    fn pass(harness: &mut TestHarness) -> ChangeSet {
+        harness.run(|ui| ui.fetch_model::<InboxViewModel>());
+        harness.view_models().changes()
+    }
+
+    #[test]
+    fn children_inserted_by_tasks_are_latched_and_detected() {
+        let mut harness = TestHarness::new();
+        let vm = harness.run(|ui| ui.fetch_model::<InboxViewModel>());
+        pass(&mut harness);
+        let detector = harness.view_models().change_detector();
+        let _runtime = harness.enter();
+
+        let changed = detector.wait_for_change();
+        let task = vm.get().spawn_with_result(|this, _| async move {
+            this.threads.push(ThreadViewModel::default());
+            let thread = this.threads.model(1).unwrap();
+            thread.subject.send_value("hello".to_string());
+        });
+        harness.block_on(task.join()).unwrap();
+        assert_eq!(changed.now_or_never(), Some(Some(())));
+        assert_eq!(vm.get().threads.len(), 1);
+
+        let changes = pass(&mut harness);
+        assert_eq!(changes.view_models[0].fields, ["threads"]);
+        assert_eq!(vm.get().threads.diff(), [ListDiff::Inserted { index: 1 }]);
+        let thread = vm.get().threads.get(1).unwrap().clone();
+        assert_eq!(thread.get().subject.value(), "hello");
+
+        // Connected by the latch that inserted it.
+        let changed = detector.wait_for_change();
+        let parent = ViewModel::change_detector(&*vm.get()).wait_for_change();
+        thread.get().subject.send_value("re: hello".to_string());
+        assert_eq!(changed.now_or_never(), Some(Some(())));
+        assert_eq!(parent.now_or_never(), Some(Some(())));
+
+        let changes = pass(&mut harness);
+        assert_eq!(changes.view_models[0].fields, ["threads"]);
+        assert!(vm.get().threads.diff().is_empty());
+        assert_eq!(thread.get().subject.value(), "re: hello");
+    }
+
+    #[test]
+    fn children_get_the_lifecycle_of_their_parent() {
+        let mut harness = TestHarness::new();
+        let vm = harness.run(|ui| ui.fetch_model::<InboxViewModel>());
+        let first = vm.get().threads.get(0).unwrap().clone();
+        assert_eq!(first.get().events.latest_value(), ["create", "visible"]);
+        assert_eq!(vm.get().pinned.events.latest_value(), ["create", "visible"]);
+
+        vm.get().threads.push(ThreadViewModel::default());
+        harness.run(|ui| ui.fetch_model::<InboxViewModel>());
+        let second = vm.get().threads.get(1).unwrap().clone();
+        assert_eq!(second.get().events.latest_value(), ["create", "visible"]);
+
+        vm.get().threads.remove(0);
+        harness.run(|ui| ui.fetch_model::<InboxViewModel>());
+        assert_eq!(
+            first.get().events.latest_value(),
+            ["create", "visible", "dispose"]
+        );
+
+        harness.view_models().shutdown();
+        assert_eq!(
+            second.get().events.latest_value(),
+            ["create", "visible", "dispose"]
+        );
+        assert_eq!(
+            vm.get().pinned.events.latest_value(),
+            ["create", "visible", "dispose"]
+        );
+    }
+
+    #[test]
+    fn children_are_inspected_with_their_parent() {
+        let mut inbox = InboxViewModel::default();
+        inbox.threads.push(ThreadViewModel::default());
+        inbox
+            .threads
+            .get(0)
+            .unwrap()
+            .get()
+            .subject
+            .send_value("hello".to_string());
+
+        let fields = inbox.inspect().unwrap();
+        assert_eq!(fields[0].latched, "{ subject: \"\", events: [] }");
+        assert_eq!(fields[1].latched, "[{ subject: \"\", events: [] }]");
+        assert_eq!(
+            fields[1].latest,
+            "[{ subject: \"hello\", events: [] }, { subject: \"\", events: [] }]"
+        );
+
+        inbox.latch_state();
+        let fields = inbox.inspect().unwrap();
+        assert_eq!(fields[1].latched, fields[1].latest);
+    }
+
+    #[cfg(feature = "persistence")]
+    mod persistence {
+        use super::*;
+
+        view_model! {
+            #[viewmodel(default)]
+            struct DraftViewModel {
+                #[persist]
+                text: ValState<String> = String::new(),
+                sending: ValState<bool> = false,
+            }
+
+            #[viewmodel(default, persist)]
+            struct OutboxViewModel {
+                current: DraftViewModel = DraftViewModel::default(),
+                drafts: ViewModelList<DraftViewModel> = Vec::new(),
+            }
+        }
+
+        #[test]
+        fn children_are_persisted_with_their_parent() {
+            let mut outbox = OutboxViewModel::default();
+            outbox.current.text.send_value("current".to_string());
+            outbox.drafts.push(DraftViewModel::default());
+            outbox.drafts.push(DraftViewModel::default());
+            outbox.latch_state();
+            let draft = outbox.drafts.get(1).unwrap().clone();
+            draft.get().text.send_value("second".to_string());
+            draft.get().sending.send_value(true);
+            outbox.latch_state();
+
+            let mut restored = OutboxViewModel::default();
+            restored.restore(&outbox.persist().unwrap());
+            assert_eq!(restored.current.text.value(), "current");
+            assert_eq!(restored.drafts.len(), 2);
+            let draft = restored.drafts.get(1).unwrap().get();
+            assert_eq!(draft.text.value(), "second");
+            assert!(!*draft.sending.value());
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Runs in a headless browser, with `wasm-pack test --headless --firefox crates/egui-mvvm --features web`.
+#![cfg(all(target_arch = "wasm32", feature = "web"))]
+
+use egui::{CentralPanel, Context, RawInput};
+use egui_mvvm::ChangeDetector;
+use egui_mvvm::history_state::HistoryState;
+use egui_mvvm::hooks::debounce::UseDebounce;
+use egui_mvvm::task_pool::TaskPool;
+use egui_mvvm::val_state::ValState;
+use egui_mvvm::view_model::EguiViewModelsExt;
+use std::time::Duration;
+use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+fn history_reads_the_browser_clock() {
+    let mut state = HistoryState::new(0).with_coalesce_window(Duration::from_secs(1));
+    state.send_value(1);
+    state.send_value(2);
+    state.latch_value();
+
+    assert!(state.undo());
+    state.latch_value();
+    assert_eq!(*state.value(), 0);
+}
+
+#[wasm_bindgen_test]
+async fn tasks_run_on_the_web_executor() {
+    let pool = TaskPool::new();
+    let handle = pool.spawn_with_result(|_| async { 5 });
+    assert_eq!(handle.join().await, Ok(5));
+}
+
+#[wasm_bindgen_test]
+async fn tasks_publish_to_states() {
+    let mut state = ValState::new(0);
+    let changed = state.change_detector().wait_for_change();
+    let handle = state.handle();
+    TaskPool::new().spawn(async move { handle.send_value(1) });
+
+    assert_eq!(changed.await, Some(()));
+    state.latch_value();
+    assert_eq!(*state.value(), 1);
+}
+
+#[wasm_bindgen_test]
+async fn debounce_waits_on_browser_timers() {
+    let ctx = Context::default();
+    let frame = |value: i32| {
+        ctx.memory_mut(|mem| mem.view_models()).latch_values();
+        let mut debounced = None;
+        let _ = ctx.run(RawInput::default(), |ctx| {
+            CentralPanel::default().show(ctx, |ui| {
+                debounced = Some(ui.use_debounce(value, Duration::from_millis(20)));
+            });
+        });
+        debounced.unwrap()
+    };
+
+    assert_eq!(frame(0), 0);
+    assert_eq!(frame(1), 0);
+    futures_timer::Delay::new(Duration::from_millis(100)).await;
+    assert_eq!(frame(1), 1);
+}